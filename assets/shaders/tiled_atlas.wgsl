@@ -0,0 +1,61 @@
+// Lets one quad repeat a single tile of the block atlas, so greedy meshed chunks can merge faces.
+// uv counts tiles across the quad, uv_b is the corner of the tile in the atlas.
+
+#import bevy_pbr::{
+    pbr_fragment::pbr_input_from_standard_material,
+    pbr_functions::alpha_discard,
+    pbr_bindings::{base_color_texture, base_color_sampler},
+}
+
+#ifdef PREPASS_PIPELINE
+#import bevy_pbr::{
+    prepass_io::{VertexOutput, FragmentOutput},
+    pbr_deferred_functions::deferred_output,
+}
+#else
+#import bevy_pbr::{
+    forward_io::{VertexOutput, FragmentOutput},
+    pbr_functions::{apply_pbr_lighting, main_pass_post_lighting_processing},
+    pbr_types::STANDARD_MATERIAL_FLAGS_UNLIT_BIT,
+}
+#endif
+
+// Size of one tile, in atlas UVs.
+@group(2) @binding(100) var<uniform> tile_size: f32;
+// Brightness of the sun, which dims the whole world at night.
+@group(2) @binding(101) var<uniform> sun_intensity: f32;
+
+@fragment
+fn fragment(
+    in: VertexOutput,
+    @builtin(front_facing) is_front: bool,
+) -> FragmentOutput {
+    var pbr_input = pbr_input_from_standard_material(in, is_front);
+
+#ifdef VERTEX_UVS_B
+    let atlas_uv = in.uv_b + fract(in.uv) * tile_size;
+    // Gradients come from the unwrapped UVs, otherwise fract() makes a seam at every tile edge.
+    var color = textureSampleGrad(base_color_texture, base_color_sampler, atlas_uv, dpdx(in.uv) * tile_size, dpdy(in.uv) * tile_size);
+#ifdef VERTEX_COLORS
+    color = color * in.color;
+#endif
+    color = vec4(color.rgb * sun_intensity, color.a);
+    pbr_input.material.base_color = color;
+#endif
+
+    pbr_input.material.base_color = alpha_discard(pbr_input.material, pbr_input.material.base_color);
+
+#ifdef PREPASS_PIPELINE
+    let out = deferred_output(in, pbr_input);
+#else
+    var out: FragmentOutput;
+    if (pbr_input.material.flags & STANDARD_MATERIAL_FLAGS_UNLIT_BIT) == 0u {
+        out.color = apply_pbr_lighting(pbr_input);
+    } else {
+        out.color = pbr_input.material.base_color;
+    }
+    out.color = main_pass_post_lighting_processing(pbr_input, out.color);
+#endif
+
+    return out;
+}
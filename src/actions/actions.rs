@@ -1,13 +1,26 @@
 use std::time::Duration;
 
-use bevy::{prelude::*, time::Stopwatch};
+use bevy::{prelude::*, time::Stopwatch, window::{CursorGrabMode, PrimaryWindow}};
 
+use felling::*;
 use movement::*;
 
-use crate::{block_pos_from_global, chunk_pos_from_global, hotbar::{Hotbar, SlotAction}, raycast_blocks, update_chunk_events_from_global, Block, BlockID, BlockUpdateEvent, Chunk, ChunkMap, Inventory, UpdateChunkEvent, CHUNK_SIZE};
+use crate::{block_entities::BlockEntity, block_pos_from_global, blocks::BlockRegistry, chunk_pos_from_global, dropped::DropItemEvent, hotbar::{Hotbar, SlotAction}, light::relight, point::GridPoint, tools::{best_tool, HAND}, raycast_blocks, update_chunk_events_from_global, AabbCollider, Block, BlockID, BlockUpdateEvent, Chunk, ChunkMap, EffectCause, Instigator, Inventory, LinearVelocity, Solidity, StatChangeEvent, StatType, Stats, UpdateChunkEvent, BLOCK_AABB, CHUNK_SIZE};
+pub mod felling;
 pub mod movement;
 
 
+/// Time between hits when mining by hand. Tools divide this by their speed.
+const MINING_INTERVAL: Duration = Duration::from_millis(750);
+/// How far an entity can get from an open crate before it closes.
+const CRATE_CLOSE_DISTANCE: f32 = 8.0;
+/// How far away an entity can be hit from.
+const ATTACK_REACH: f32 = 3.0;
+/// Health taken off with each hit.
+const ATTACK_DAMAGE: f32 = 2.0;
+/// How hard a hit knocks its target back.
+const ATTACK_KNOCKBACK: f32 = 6.0;
+
 pub struct ActionsPlugin;
 
 impl Plugin for ActionsPlugin {
@@ -17,7 +30,10 @@ impl Plugin for ActionsPlugin {
         .add_event::<MiningEvent>()
         .add_event::<DamageBlockEvent>()
         .add_event::<BuildingEvent>()
-        .add_event::<PutBlockEvent>();
+        .add_event::<PutBlockEvent>()
+        .add_event::<InteractEvent>()
+        .add_event::<FellTreeEvent>()
+        .init_resource::<Felling>();
     }
 }
 
@@ -35,6 +51,12 @@ pub struct BuildingEvent {
     pub is_start: bool,
 }
 
+/// Sent when an entity tries to use the block it's looking at, like opening a crate.
+#[derive(Clone, Copy, Event)]
+pub struct InteractEvent {
+    pub entity: Entity,
+}
+
 #[derive(Clone, Copy, Event)]
 pub struct DamageBlockEvent {
     pub position: IVec3,
@@ -52,11 +74,15 @@ pub struct PutBlockEvent {
 
 
 
+/// Global position of the crate an entity is looking through.
+#[derive(Clone, Copy, Component, Deref, DerefMut, Debug)]
+pub struct OpenCrate(pub IVec3);
+
 #[derive(Clone, Component, Deref, DerefMut, Debug)]
 pub struct MiningTimer (pub Timer);
 impl Default for MiningTimer {
     fn default() -> Self {
-        let mut timer = Timer::new(Duration::from_millis(750), TimerMode::Repeating);
+        let mut timer = Timer::new(MINING_INTERVAL, TimerMode::Repeating);
         timer.pause();
         Self(timer)
     }
@@ -72,43 +98,68 @@ impl Default for BuildingTimer {
     }
 }
 
+/// Hits whatever block a miner is looking at every so often, using the best tool they have for it.
 pub fn mining (
-    mut miner_query: Query<(Entity, &mut MiningTimer, &Children)>,
+    mut miner_query: Query<(Entity, &mut MiningTimer, &Children, Option<&mut Inventory>)>,
     // TODO: We should use some "head" component or something later on when we have entities that mine but don't have a camera.
     cam_query: Query<(&GlobalTransform), With<Camera>>,
 
     chunk_map: Res<ChunkMap>,
+    block_registry: Res<BlockRegistry>,
     
     mut evr_mining: EventReader<MiningEvent>,
     mut evw_damage_block: EventWriter<DamageBlockEvent>,
 
     time: Res<Time>,
 ) {
-    for (entity, mut timer, children) in &mut miner_query {
-        timer.tick(time.delta());
-
-        if timer.finished() {
+    for (entity, mut timer, children, mut opt_inventory) in &mut miner_query {
+        if timer.paused() {
+            continue
+        }
 
-            for child in children.iter() {
-                if let Ok(global_transform) = cam_query.get(*child) {
-                    let hits = raycast_blocks(global_transform.translation(), global_transform.forward().normalize(), 5.0);
-                    for hit in hits {
-                        //println!("hit_position: {}, hit_normal: {}", hit.position, hit.normal);
+        let mut target = None;
+        for child in children.iter() {
+            if let Ok(global_transform) = cam_query.get(*child) {
+                let hits = raycast_blocks(global_transform.translation(), global_transform.forward().normalize(), 5.0);
+                for hit in hits {
+                    //println!("hit_position: {}, hit_normal: {}", hit.position, hit.normal);
 
-                        let chunk_pos = chunk_pos_from_global(hit.position.as_ivec3());
+                    let chunk_pos = chunk_pos_from_global(hit.position.as_ivec3());
 
-                        if let Some(chunk) = chunk_map.get(&chunk_pos) {
-                            let block_pos = block_pos_from_global(hit.position.as_ivec3());
+                    if let Some(chunk) = chunk_map.get(&chunk_pos) {
+                        let block_pos = block_pos_from_global(hit.position.as_ivec3());
 
-                            if chunk.blocks[block_pos].id != BlockID::Air && chunk.blocks[block_pos].id != BlockID::Water {
-                                evw_damage_block.send(DamageBlockEvent { position: hit.position.as_ivec3(), damage: 1, strength: 1, entity });
-                                break;
-                            }
+                        let attributes = block_registry[chunk.blocks[block_pos].id];
+                        if attributes.solidity != Solidity::NonSolid && attributes.solidity != Solidity::Water {
+                            target = Some((hit.position.as_ivec3(), attributes));
+                            break;
                         }
                     }
                 }
             }
         }
+
+        let Some((position, attributes)) = target else {
+            timer.tick(time.delta());
+            continue
+        };
+
+        let tool = opt_inventory.as_deref().and_then(|inventory| best_tool(inventory, &attributes));
+        let stats = tool.map_or(HAND, |(_, tool)| tool.stats);
+
+        timer.set_duration(MINING_INTERVAL.div_f32(stats.speed));
+        timer.tick(time.delta());
+
+        if timer.finished() {
+            evw_damage_block.send(DamageBlockEvent { position, damage: stats.damage, strength: stats.strength, entity });
+
+            // Tools only wear down when they actually get some use out of the block.
+            if let (Some((slot, _)), Some(inventory)) = (tool, opt_inventory.as_mut()) {
+                if stats.strength >= attributes.toughness {
+                    inventory.wear_tool(slot);
+                }
+            }
+        }
     }
 
     // TODO: Should this be a separate system?
@@ -125,15 +176,79 @@ pub fn mining (
     }
 }
 
+/// Hits the closest entity in front of an attacker when they start swinging, as long as there isn't a block in the way.
+pub fn attack (
+    attacker_query: Query<&Children>,
+    // TODO: We should use some "head" component or something later on when we have entities that attack but don't have a camera.
+    cam_query: Query<(&GlobalTransform), With<Camera>>,
+    mut target_query: Query<(Entity, &Transform, &AabbCollider, &mut LinearVelocity), With<Stats>>,
+
+    chunk_map: Res<ChunkMap>,
+    block_registry: Res<BlockRegistry>,
+
+    mut evr_mining: EventReader<MiningEvent>,
+    mut evw_stat_change: EventWriter<StatChangeEvent>,
+) {
+    for ev in evr_mining.read() {
+        if !ev.is_start {
+            continue
+        }
+        let Ok(children) = attacker_query.get(ev.entity) else {
+            continue
+        };
+
+        for child in children.iter() {
+            let Ok(global_transform) = cam_query.get(*child) else {
+                continue
+            };
+            let origin = global_transform.translation();
+            let direction = global_transform.forward().normalize();
+
+            let mut reach = ATTACK_REACH;
+            for hit in raycast_blocks(origin, direction, ATTACK_REACH) {
+                let chunk_pos = chunk_pos_from_global(hit.position.as_ivec3());
+
+                if let Some(chunk) = chunk_map.get(&chunk_pos) {
+                    let block_pos = block_pos_from_global(hit.position.as_ivec3());
+
+                    if block_registry[chunk.blocks[block_pos].id].solidity == Solidity::Solid {
+                        reach = BLOCK_AABB.get_ray_intersection(hit.position, origin, direction).unwrap_or(0.0);
+                        break;
+                    }
+                }
+            }
+
+            let closest = target_query.iter()
+                .filter(|(entity, ..)| *entity != ev.entity)
+                .filter_map(|(entity, transform, collider, _)| collider.get_ray_intersection(transform.translation, origin, direction).map(|distance| (entity, distance)))
+                .filter(|(_, distance)| *distance <= reach)
+                .min_by(|(_, a), (_, b)| a.total_cmp(b));
+
+            if let Some((target, _)) = closest {
+                evw_stat_change.send(StatChangeEvent::new(Instigator::Entity(ev.entity), EffectCause::Attack, StatType::Health, -ATTACK_DAMAGE, target));
+
+                if let Ok((_, _, _, mut velocity)) = target_query.get_mut(target) {
+                    **velocity += Vec3::new(direction.x, 0.0, direction.z).normalize_or_zero() * ATTACK_KNOCKBACK + Vec3::Y * ATTACK_KNOCKBACK / 2.0;
+                }
+            }
+        }
+    }
+}
+
 pub fn damage_block (
     mut inventory_query: Query<&mut Inventory>,
 
     mut chunk_map: ResMut<ChunkMap>,
+    block_registry: Res<BlockRegistry>,
 
     mut evr_damage_block: EventReader<DamageBlockEvent>,
     mut evw_update_chunk: EventWriter<UpdateChunkEvent>,
     mut evw_block_update: EventWriter<BlockUpdateEvent>,
+    mut evw_drop_item: EventWriter<DropItemEvent>,
+    mut evw_fell_tree: EventWriter<FellTreeEvent>,
 ) {
+    let mut broken_blocks = Vec::new();
+
     for ev in evr_damage_block.read() {
         let chunk_pos = chunk_pos_from_global(ev.position);
 
@@ -142,35 +257,120 @@ pub fn damage_block (
             //println!("position: {}", ev.position);
             //println!("chunk pos: {}", chunk_pos);
             //println!("block pos: {}", block_pos);
-            let attributes = chunk.blocks[block_pos].get_attributes();
+            let attributes = block_registry[chunk.blocks[block_pos].id];
             
             if ev.strength >= attributes.toughness {
-                chunk.blocks[block_pos].damage = chunk.blocks[block_pos].damage + ev.damage;
+                // Stronger hits can't do more damage than the block has health left.
+                let dealt = ev.damage.min(attributes.health.saturating_sub(chunk.blocks[block_pos].damage));
+                chunk.blocks[block_pos].damage = chunk.blocks[block_pos].damage + dealt;
+                chunk.mark_modified();
 
                 // TODO: Should we condense things by just sending these when we handle block updates?
                 for event in update_chunk_events_from_global(ev.position) {
                     evw_update_chunk.send(event);
                 }
 
-                if let Some(drop) = attributes.give_on_damage {
+                if let Some(mut drop) = attributes.give_on_damage {
+                    drop.amount *= dealt as u16;
                     if let Ok(mut inventory) = inventory_query.get_mut(ev.entity) {
-                        if let Err(fault) = inventory.insert_item(drop) {
-                            match fault {
-                                crate::ItemInsertFault::NoSpace => todo!("We should drop the item as an entity!"),
-                                crate::ItemInsertFault::InsufficientSpace => todo!("We should drop some of the item as an entity!"),
-                            }
+                        // Inserts are all or nothing, so whatever doesn't fit gets dropped in one piece.
+                        if inventory.insert_item(drop).is_err() {
+                            evw_drop_item.send(DropItemEvent { item: drop, position: ev.position.as_vec3() });
                         }
                     }
                 }
                 
-                if chunk.blocks[block_pos].damage == attributes.health {
+                if chunk.blocks[block_pos].damage >= attributes.health {
+                    let broken = chunk.blocks[block_pos];
                     chunk.blocks[block_pos] = Block::new(attributes.breaks_into);
+
+                    // Whatever the block was holding spills out where it stood.
+                    if let Some(block_entity) = chunk.block_entities.remove(&block_pos) {
+                        for item in block_entity.into_contents().iter() {
+                            evw_drop_item.send(DropItemEvent { item: *item, position: ev.position.as_vec3() });
+                        }
+                    }
                     //println!("new block: {:?}", attributes.breaks_into);
                     evw_block_update.send(BlockUpdateEvent { position: ev.position, time_waited: Stopwatch::new() });
+                    broken_blocks.push(ev.position);
+
+                    // Cutting a tree off at its base brings the rest of it down.
+                    if broken.id == BlockID::Log && broken.data.is_tree() {
+                        let below = ev.position.down(1);
+                        let below_is_tree = chunk_map.get(&chunk_pos_from_global(below)).map_or(false, |chunk| chunk.blocks[block_pos_from_global(below)].data.is_tree());
+                        if !below_is_tree {
+                            evw_fell_tree.send(FellTreeEvent { position: ev.position, entity: ev.entity });
+                        }
+                    }
                 }
             }
         }
     }
+
+    for event in relight(&broken_blocks, &mut chunk_map, &block_registry) {
+        evw_update_chunk.send(event);
+    }
+}
+
+/// Opens crates that get interacted with, and closes them again once they're gone or out of reach.
+pub fn interact (
+    mut commands: Commands,
+
+    interactor_query: Query<&Children>,
+    open_crate_query: Query<(Entity, &OpenCrate, &GlobalTransform)>,
+    // TODO: We should use some "head" component or something later on when we have entities that interact but don't have a camera.
+    cam_query: Query<(&GlobalTransform), With<Camera>>,
+    mut primary_window: Query<&mut Window, With<PrimaryWindow>>,
+
+    chunk_map: Res<ChunkMap>,
+    block_registry: Res<BlockRegistry>,
+
+    mut evr_interact: EventReader<InteractEvent>,
+    mut evw_building: EventWriter<BuildingEvent>,
+) {
+    for ev in evr_interact.read() {
+        let Ok(children) = interactor_query.get(ev.entity) else {
+            continue
+        };
+
+        for child in children.iter() {
+            if let Ok(global_transform) = cam_query.get(*child) {
+                let hits = raycast_blocks(global_transform.translation(), global_transform.forward().normalize(), 5.0);
+                for hit in hits {
+                    let chunk_pos = chunk_pos_from_global(hit.position.as_ivec3());
+
+                    if let Some(chunk) = chunk_map.get(&chunk_pos) {
+                        let block_pos = block_pos_from_global(hit.position.as_ivec3());
+
+                        let solidity = block_registry[chunk.blocks[block_pos].id].solidity;
+                        if solidity != Solidity::NonSolid && solidity != Solidity::Water {
+                            if let Some(BlockEntity::Crate(_)) = chunk.block_entities.get(&block_pos) {
+                                commands.entity(ev.entity).insert(OpenCrate(hit.position.as_ivec3()));
+                                // The same button builds, and we don't want to put anything down while we're rummaging around.
+                                evw_building.send(BuildingEvent { entity: ev.entity, is_start: false });
+
+                                if let Ok(mut window) = primary_window.get_single_mut() {
+                                    window.cursor.grab_mode = CursorGrabMode::None;
+                                    window.cursor.visible = true;
+                                }
+                            }
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    for (entity, open_crate, global_transform) in &open_crate_query {
+        let chunk_pos = chunk_pos_from_global(**open_crate);
+        let is_crate = chunk_map.get(&chunk_pos).map_or(false, |chunk| chunk.block_entities.contains_key(&block_pos_from_global(**open_crate)));
+        let in_reach = global_transform.translation().distance(open_crate.as_vec3() + Vec3::splat(0.5)) < CRATE_CLOSE_DISTANCE;
+
+        if !is_crate || !in_reach {
+            commands.entity(entity).remove::<OpenCrate>();
+        }
+    }
 }
 
 pub fn building (
@@ -180,6 +380,7 @@ pub fn building (
     cam_query: Query<(&GlobalTransform), With<Camera>>,
 
     chunk_map: Res<ChunkMap>,
+    block_registry: Res<BlockRegistry>,
     
     mut evr_building: EventReader<BuildingEvent>,
     mut evw_put_block: EventWriter<PutBlockEvent>,
@@ -204,7 +405,8 @@ pub fn building (
                                 if let Some(chunk) = chunk_map.get(&chunk_pos) {
                                     let block_pos = block_pos_from_global(hit.position.as_ivec3());
 
-                                    if chunk.blocks[block_pos].id != BlockID::Air && chunk.blocks[block_pos].id != BlockID::Water {
+                                    let solidity = block_registry[chunk.blocks[block_pos].id].solidity;
+                                    if solidity != Solidity::NonSolid && solidity != Solidity::Water {
                                         evw_put_block.send(PutBlockEvent { position: hit.position.as_ivec3() + hit.normal.as_ivec3(), id: block_id, entity } );
                                         break;
                                     }
@@ -238,20 +440,24 @@ pub fn place_block (
     mut inventory_query: Query<&mut Inventory>,
 
     mut chunk_map: ResMut<ChunkMap>,
+    block_registry: Res<BlockRegistry>,
 
     mut evr_put_block: EventReader<PutBlockEvent>,
     mut evw_update_chunk: EventWriter<UpdateChunkEvent>,
     mut evw_block_update: EventWriter<BlockUpdateEvent>,
 ) {
+    let mut placed_blocks = Vec::new();
+
     'events: for ev in evr_put_block.read() {
         let chunk_pos = chunk_pos_from_global(ev.position);
 
         if let Some(chunk) = chunk_map.get_mut(&chunk_pos) {
             let block_pos = block_pos_from_global(ev.position);
                 
-            if chunk.blocks[block_pos].id == BlockID::Air || chunk.blocks[block_pos].id == BlockID::Water {
+            let solidity = block_registry[chunk.blocks[block_pos].id].solidity;
+            if solidity == Solidity::NonSolid || solidity == Solidity::Water {
                 if let Ok(mut inventory) = inventory_query.get_mut(ev.entity) {
-                    let attributes = ev.id.get_attributes();
+                    let attributes = block_registry[ev.id];
 
                     for cost_opt in attributes.cost_to_build {
                         if let Some(cost) = cost_opt {
@@ -271,7 +477,12 @@ pub fn place_block (
                 }
 
                 chunk.blocks[block_pos] = Block::new(ev.id);
+                if let Some(block_entity) = BlockEntity::new(ev.id) {
+                    chunk.block_entities.insert(block_pos, block_entity);
+                }
+                chunk.mark_modified();
                 evw_block_update.send(BlockUpdateEvent { position: ev.position, time_waited: Stopwatch::new() });
+                placed_blocks.push(ev.position);
 
                 for event in update_chunk_events_from_global(ev.position) {
                     evw_update_chunk.send(event);
@@ -279,4 +490,8 @@ pub fn place_block (
             }
         }
     }
+
+    for event in relight(&placed_blocks, &mut chunk_map, &block_registry) {
+        evw_update_chunk.send(event);
+    }
 }
\ No newline at end of file
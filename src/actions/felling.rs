@@ -0,0 +1,166 @@
+use std::time::Duration;
+
+use bevy::{prelude::*, time::Stopwatch};
+use fastrand::Rng;
+use indexmap::IndexMap;
+use itertools::iproduct;
+
+use crate::{block_pos_from_global, blocks::BlockRegistry, chunk_pos_from_global, directions::DIR_6, dropped::DropItemEvent, light::relight, update_chunk_events_from_global, Block, BlockData, BlockID, BlockUpdateEvent, ChunkMap, Inventory, UpdateChunkEvent};
+
+/// Time between felling steps. Each step, the tree comes down one more block away from where it was cut.
+const FELLING_STEP: Duration = Duration::from_millis(80);
+/// Leaves take a random number of steps up to this long to decay once their log is gone.
+const MAX_LEAF_DECAY_STEPS: u8 = 40;
+/// Leaves within this many blocks of a standing log are held up by it, so decay doesn't spread into the canopy of a tree next door.
+const LEAF_SUPPORT_RANGE: i32 = 3;
+
+// Events
+/// Sent when the bottom log of a tree is broken, bringing down the rest of the tree connected to it.
+#[derive(Clone, Copy, Event)]
+pub struct FellTreeEvent {
+    pub position: IVec3,
+    /// Who gets the wood.
+    pub entity: Entity,
+}
+
+// Resources
+/// Tree blocks that are coming down, along with who cut the tree. Their steps left are kept in their [`BlockData::DamagedAdjacent`].
+#[derive(Resource)]
+pub struct Felling {
+    timer: Timer,
+    blocks: IndexMap<IVec3, Entity>,
+}
+impl Default for Felling {
+    fn default() -> Self {
+        Self { timer: Timer::new(FELLING_STEP, TimerMode::Repeating), blocks: IndexMap::new() }
+    }
+}
+
+// Systems
+pub fn fell_trees (
+    mut inventory_query: Query<&mut Inventory>,
+
+    mut felling: ResMut<Felling>,
+    mut chunk_map: ResMut<ChunkMap>,
+    block_registry: Res<BlockRegistry>,
+
+    mut evr_fell_tree: EventReader<FellTreeEvent>,
+    mut evw_update_chunk: EventWriter<UpdateChunkEvent>,
+    mut evw_block_update: EventWriter<BlockUpdateEvent>,
+    mut evw_drop_item: EventWriter<DropItemEvent>,
+
+    time: Res<Time>,
+) {
+    let mut rng = Rng::new();
+
+    for ev in evr_fell_tree.read() {
+        damage_adjacent(ev.position, ev.entity, true, &mut felling.blocks, &mut chunk_map, &mut rng);
+    }
+
+    felling.timer.tick(time.delta());
+    if !felling.timer.just_finished() || felling.blocks.is_empty() {
+        return
+    }
+
+    let mut felled = Vec::new();
+    let mut chunk_updates = Vec::new();
+
+    for (position, feller) in std::mem::take(&mut felling.blocks) {
+        let Some(chunk) = chunk_map.get_mut(&chunk_pos_from_global(position)) else {
+            // Picks back up if the chunk comes back while we're still around.
+            felling.blocks.insert(position, feller);
+            continue
+        };
+        let block_pos = block_pos_from_global(position);
+        let block = chunk.blocks[block_pos];
+
+        match block.data {
+            BlockData::DamagedAdjacent(steps) if steps > 1 => {
+                chunk.blocks[block_pos].data = BlockData::DamagedAdjacent(steps - 1);
+                felling.blocks.insert(position, feller);
+            },
+            BlockData::DamagedAdjacent(_) => {
+                chunk.blocks[block_pos] = Block::new(BlockID::Air);
+                chunk.mark_modified();
+                felled.push((position, block.id, feller));
+            },
+            // Something else has taken its place since.
+            _ => {},
+        }
+    }
+
+    for (position, id, feller) in felled.iter() {
+        // Logs are worth as much as mining them all the way down. Leaves just rot away.
+        if *id == BlockID::Log {
+            let attributes = &block_registry[*id];
+            if let Some(mut drop) = attributes.give_on_damage {
+                drop.amount *= attributes.health as u16;
+                let given = inventory_query.get_mut(*feller).map_or(false, |mut inventory| inventory.insert_item(drop).is_ok());
+                if !given {
+                    evw_drop_item.send(DropItemEvent { item: drop, position: position.as_vec3() });
+                }
+            }
+        }
+        // Logs bring down everything they touch. Leaves only pass the rot on to other leaves, so canopies fall apart from the inside out.
+        damage_adjacent(*position, *feller, *id == BlockID::Log, &mut felling.blocks, &mut chunk_map, &mut rng);
+
+        evw_block_update.send(BlockUpdateEvent { position: *position, time_waited: Stopwatch::new() });
+        for event in update_chunk_events_from_global(*position) {
+            if !chunk_updates.contains(&event) {
+                chunk_updates.push(event);
+            }
+        }
+    }
+
+    let felled_positions = felled.iter().map(|(position, ..)| *position).collect::<Vec<_>>();
+    for event in relight(&felled_positions, &mut chunk_map, &block_registry) {
+        if !chunk_updates.contains(&event) {
+            chunk_updates.push(event);
+        }
+    }
+    for event in chunk_updates {
+        evw_update_chunk.send(event);
+    }
+}
+
+// Helpers
+/// Starts bringing down the tree blocks next to `position`. Logs go on the next step, leaves whenever they get around to it.
+/// With `from_log` unset only leaves that no standing log holds up are touched.
+fn damage_adjacent (position: IVec3, feller: Entity, from_log: bool, blocks: &mut IndexMap<IVec3, Entity>, chunk_map: &mut ChunkMap, rng: &mut Rng) {
+    for direction in DIR_6 {
+        let adjacent = position + *direction;
+        let Some(chunk) = chunk_map.get(&chunk_pos_from_global(adjacent)) else {
+            continue
+        };
+        let block = chunk.blocks[block_pos_from_global(adjacent)];
+        // Blocks already on their way down keep their own countdown.
+        if block.data != BlockData::Tree {
+            continue
+        }
+        if !from_log && (block.id != BlockID::Leaves || has_log_support(adjacent, chunk_map)) {
+            continue
+        }
+
+        let Some(chunk) = chunk_map.get_mut(&chunk_pos_from_global(adjacent)) else {
+            continue
+        };
+        let block = &mut chunk.blocks[block_pos_from_global(adjacent)];
+
+        let steps = if block.id == BlockID::Log { 1 } else { rng.u8(1..=MAX_LEAF_DECAY_STEPS) };
+        block.data = BlockData::DamagedAdjacent(steps);
+        chunk.mark_modified();
+        blocks.insert(adjacent, feller);
+    }
+}
+
+/// Whether there's a standing tree log within [`LEAF_SUPPORT_RANGE`] of `position`.
+fn has_log_support (position: IVec3, chunk_map: &ChunkMap) -> bool {
+    let range = -LEAF_SUPPORT_RANGE..=LEAF_SUPPORT_RANGE;
+    iproduct!(range.clone(), range.clone(), range).any(|(x, y, z)| {
+        let nearby = position + IVec3::new(x, y, z);
+        chunk_map.get(&chunk_pos_from_global(nearby)).map_or(false, |chunk| {
+            let block = chunk.blocks[block_pos_from_global(nearby)];
+            block.id == BlockID::Log && block.data == BlockData::Tree
+        })
+    })
+}
@@ -46,7 +46,7 @@ pub struct MovementDampingFactor(f32);
 
 /// The strength of a jump.
 #[derive(Component)]
-pub struct JumpImpulse(f32);
+pub struct JumpImpulse(pub f32);
 
 /// A bundle that contains the components needed for a basic
 /// kinematic character controller.
@@ -0,0 +1,26 @@
+use bevy::prelude::*;
+
+use pathfinding::*;
+use zombie::*;
+
+use crate::{movement, GameState};
+
+pub mod pathfinding;
+pub mod zombie;
+
+//Plugin
+pub struct CreaturesPlugin;
+
+impl Plugin for CreaturesPlugin {
+    fn build(&self, app: &mut App) {
+        app
+        .add_event::<PathRequestEvent>()
+        .add_event::<PathFailedEvent>()
+        .init_resource::<PathSearches>()
+        .init_resource::<ZombieSpawner>()
+        .add_systems(OnEnter(GameState::Playing), setup_zombie_assets)
+        .add_systems(Update, (spawn_zombies, despawn_far_zombies).chain().run_if(in_state(GameState::Playing)))
+        .add_systems(Update, zombie_ai.before(movement::movement).run_if(in_state(GameState::Playing)))
+        .add_systems(Update, (invalidate_paths, request_paths, run_path_searches).chain().after(zombie_ai).run_if(in_state(GameState::Playing)));
+    }
+}
@@ -0,0 +1,391 @@
+use std::{cmp::Ordering, collections::BinaryHeap};
+
+use bevy::{prelude::*, utils::{HashMap, HashSet}};
+use indexmap::IndexMap;
+
+use crate::{block_pos_from_global, blocks::BlockRegistry, chunk_pos_from_global, directions::{NORTH, SOUTH, EAST, WEST}, movement::JumpImpulse, point::GridPoint, AabbCollider, BlockUpdateEvent, ChunkMap, Gravity, Solidity};
+
+/// How many nodes all searches get to look at between them each frame. Searches that run out pick up where they left off next frame.
+pub const NODE_BUDGET: usize = 2048;
+/// Searches that look at this many nodes without reaching their goal give up.
+pub const MAX_SEARCH_NODES: usize = 16384;
+/// How far agents are willing to drop. Any further and they'd take fall damage.
+const MAX_DROP: i32 = 3;
+
+//Events
+/// Asks for a path from where `entity` is standing to `goal`. The entity needs a [`PathAgent`].
+/// The answer comes back later as a [`Path`] on the entity, or a [`PathFailedEvent`].
+#[derive(Clone, Copy, Event)]
+pub struct PathRequestEvent {
+    pub entity: Entity,
+    pub goal: IVec3,
+}
+
+#[derive(Clone, Copy, Event)]
+pub struct PathFailedEvent {
+    pub entity: Entity,
+    pub fault: PathFault,
+}
+
+// Resources
+/// Searches that are still going, in the order they were asked for. A new request for an entity replaces its old one.
+#[derive(Default, Resource, Deref, DerefMut)]
+pub struct PathSearches(IndexMap<Entity, PathSearch>);
+
+// Components
+/// How much room an entity needs to get through, and how far it can jump and drop, in blocks.
+#[derive(Component, Clone, Copy, Debug)]
+pub struct PathAgent {
+    pub height: i32,
+    pub width: i32,
+    pub max_jump: i32,
+    pub max_drop: i32,
+}
+impl PathAgent {
+    pub fn new(collider: &AabbCollider, jump_impulse: &JumpImpulse, gravity: &Gravity) -> PathAgent {
+        // A jump goes up by v^2 / 2g before it starts coming back down.
+        let jump_height = jump_impulse.0.powi(2) / (2.0 * gravity.0);
+
+        PathAgent {
+            height: collider.height.ceil() as i32,
+            width: collider.width.max(collider.length).ceil() as i32,
+            max_jump: jump_height.floor() as i32,
+            max_drop: MAX_DROP,
+        }
+    }
+
+    /// The block an entity with this collider has its feet in.
+    pub fn feet_position(&self, translation: Vec3, collider: &AabbCollider) -> IVec3 {
+        // Blocks are centered on their positions, so something standing on top of a block has its feet half a block under our position.
+        (translation - Vec3::Y * (collider.height / 2.0 - 0.5)).round().as_ivec3()
+    }
+}
+
+/// A walkable route, as the blocks an agent's feet pass through. Removed when a block along it changes.
+#[derive(Component, Clone, Debug)]
+pub struct Path {
+    pub goal: IVec3,
+    pub nodes: Vec<IVec3>,
+    /// Index of the node the agent is heading towards.
+    pub next: usize,
+}
+impl Path {
+    pub fn next_node(&self) -> Option<IVec3> {
+        self.nodes.get(self.next).copied()
+    }
+
+    pub fn advance(&mut self) {
+        self.next += 1;
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.next >= self.nodes.len()
+    }
+}
+
+// Data
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PathFault {
+    /// The agent isn't standing anywhere it could walk from, like in the middle of a fall.
+    BadStart,
+    /// Everything reachable was searched without finding the goal.
+    Unreachable,
+    /// The search went over [`MAX_SEARCH_NODES`].
+    TooFar,
+}
+
+/// An A* search that can be paused and resumed across frames.
+pub struct PathSearch {
+    agent: PathAgent,
+    start: IVec3,
+    goal: IVec3,
+    open: BinaryHeap<OpenNode>,
+    came_from: HashMap<IVec3, IVec3>,
+    costs: HashMap<IVec3, u32>,
+    /// Every block any node in [`Self::costs`] depends on. A change to one of these makes the search stale.
+    searched: HashSet<IVec3>,
+    expanded: usize,
+}
+impl PathSearch {
+    pub fn new(agent: PathAgent, start: IVec3, goal: IVec3) -> PathSearch {
+        let mut search = PathSearch {
+            agent,
+            start,
+            goal,
+            open: BinaryHeap::new(),
+            came_from: HashMap::new(),
+            costs: HashMap::new(),
+            searched: HashSet::new(),
+            expanded: 0,
+        };
+        search.open.push(OpenNode { estimate: heuristic(start, goal), position: start });
+        search.costs.insert(start, 0);
+        search.searched.extend(agent.dependencies(start));
+        search
+    }
+
+    /// Whether this search has looked at `position`, which would make its answer stale if the block there changed.
+    pub fn touches(&self, position: IVec3) -> bool {
+        self.searched.contains(&position)
+    }
+
+    /// Expands up to `budget` nodes. Returns how many it actually used, and the result once there is one.
+    pub fn step(&mut self, budget: usize, chunk_map: &ChunkMap, block_registry: &BlockRegistry) -> (usize, Option<Result<Vec<IVec3>, PathFault>>) {
+        if self.expanded == 0 && !self.agent.can_stand(self.start, chunk_map, block_registry) {
+            return (0, Some(Err(PathFault::BadStart)))
+        }
+
+        let mut used = 0;
+        while used < budget {
+            let Some(OpenNode { estimate, position }) = self.open.pop() else {
+                return (used, Some(Err(PathFault::Unreachable)))
+            };
+            // A cheaper way here was found after this entry went in, and it's already been expanded.
+            if estimate > self.costs[&position] + heuristic(position, self.goal) {
+                continue
+            }
+            if position == self.goal {
+                return (used, Some(Ok(self.reconstruct())))
+            }
+            if self.expanded >= MAX_SEARCH_NODES {
+                return (used, Some(Err(PathFault::TooFar)))
+            }
+            used += 1;
+            self.expanded += 1;
+
+            let cost = self.costs[&position];
+            for (neighbor, step_cost) in self.agent.neighbors(position, chunk_map, block_registry) {
+                let neighbor_cost = cost + step_cost;
+                if self.costs.get(&neighbor).map_or(true, |old_cost| neighbor_cost < *old_cost) {
+                    if self.costs.insert(neighbor, neighbor_cost).is_none() {
+                        self.searched.extend(self.agent.dependencies(neighbor));
+                    }
+                    self.came_from.insert(neighbor, position);
+                    self.open.push(OpenNode { estimate: neighbor_cost + heuristic(neighbor, self.goal), position: neighbor });
+                }
+            }
+        }
+
+        (used, None)
+    }
+
+    fn reconstruct(&self) -> Vec<IVec3> {
+        let mut nodes = vec![self.goal];
+        let mut current = self.goal;
+        while let Some(previous) = self.came_from.get(&current) {
+            nodes.push(*previous);
+            current = *previous;
+        }
+        nodes.reverse();
+        nodes
+    }
+}
+
+/// Open set entry. Ordered so that the [`BinaryHeap`] hands out the lowest estimate first.
+#[derive(Clone, Copy, PartialEq, Eq)]
+struct OpenNode {
+    estimate: u32,
+    position: IVec3,
+}
+impl Ord for OpenNode {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.estimate.cmp(&self.estimate).then_with(|| self.position.to_array().cmp(&other.position.to_array()))
+    }
+}
+impl PartialOrd for OpenNode {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PathAgent {
+    /// Every node reachable in one move from `position`, along with what it costs to get there.
+    /// Agents walk and jump between blocks they can stand in, drop off of ledges, and go straight up and down through water and climbable blocks.
+    fn neighbors(&self, position: IVec3, chunk_map: &ChunkMap, block_registry: &BlockRegistry) -> Vec<(IVec3, u32)> {
+        let mut neighbors = Vec::new();
+        let solidity = |position: IVec3| solidity_at(position, chunk_map, block_registry);
+        let on_ground = solidity(position.down(1)) == Some(Solidity::Solid) || solidity(position.down(1)) == Some(Solidity::Climable);
+        let in_climbable = matches!(solidity(position), Some(Solidity::Climable) | Some(Solidity::Water));
+
+        for direction in [NORTH, SOUTH, EAST, WEST] {
+            let adj = position + direction;
+
+            if self.can_stand(adj, chunk_map, block_registry) {
+                neighbors.push((adj, 1));
+                continue
+            }
+
+            // Jumping up onto something needs headroom above us as well as room up there.
+            if on_ground || in_climbable {
+                for rise in 1..=self.max_jump {
+                    if !self.fits(position.up(rise), chunk_map, block_registry) {
+                        break
+                    }
+                    if self.can_stand(adj.up(rise), chunk_map, block_registry) {
+                        neighbors.push((adj.up(rise), 1 + rise as u32));
+                        break
+                    }
+                }
+            }
+
+            // Walking off of a ledge.
+            if self.fits(adj, chunk_map, block_registry) {
+                for fall in 1..=self.max_drop {
+                    if !self.fits(adj.down(fall), chunk_map, block_registry) {
+                        break
+                    }
+                    if self.can_stand(adj.down(fall), chunk_map, block_registry) {
+                        neighbors.push((adj.down(fall), 1 + fall as u32));
+                        break
+                    }
+                }
+            }
+        }
+
+        if in_climbable && self.can_stand(position.up(1), chunk_map, block_registry) {
+            neighbors.push((position.up(1), 1));
+        }
+        if matches!(solidity(position.down(1)), Some(Solidity::Climable) | Some(Solidity::Water)) && self.can_stand(position.down(1), chunk_map, block_registry) {
+            neighbors.push((position.down(1), 1));
+        }
+
+        neighbors
+    }
+
+    /// Whether the agent could be at `position` without falling. That means there's room for it, and it's either on something or holding onto something.
+    fn can_stand(&self, position: IVec3, chunk_map: &ChunkMap, block_registry: &BlockRegistry) -> bool {
+        if !self.fits(position, chunk_map, block_registry) {
+            return false
+        }
+
+        (0..self.width).flat_map(|x| (0..self.width).map(move |z| IVec3::new(x, 0, z))).any(|offset| {
+            let below = solidity_at(position + offset + IVec3::NEG_Y, chunk_map, block_registry);
+            let at = solidity_at(position + offset, chunk_map, block_registry);
+            matches!(below, Some(Solidity::Solid) | Some(Solidity::Climable)) || matches!(at, Some(Solidity::Climable) | Some(Solidity::Water))
+        })
+    }
+
+    /// Whether every block the agent would take up at `position` can be moved through.
+    fn fits(&self, position: IVec3, chunk_map: &ChunkMap, block_registry: &BlockRegistry) -> bool {
+        self.occupied(position).all(|cell| matches!(solidity_at(cell, chunk_map, block_registry), Some(Solidity::NonSolid) | Some(Solidity::Water) | Some(Solidity::Climable)))
+    }
+
+    fn occupied(&self, position: IVec3) -> impl Iterator<Item = IVec3> {
+        let (height, width) = (self.height, self.width);
+        (0..width).flat_map(move |x| (0..height).flat_map(move |y| (0..width).map(move |z| position + IVec3::new(x, y, z))))
+    }
+
+    /// Whether standing at `node` depends on the block at `position`, either as room to stand in or as something to stand on.
+    fn depends_on(&self, node: IVec3, position: IVec3) -> bool {
+        let offset = position - node;
+        (0..self.width).contains(&offset.x) && (0..self.width).contains(&offset.z) && (-1..self.height).contains(&offset.y)
+    }
+
+    /// Every block [`Self::depends_on`] is true for at `node`.
+    fn dependencies(&self, node: IVec3) -> impl Iterator<Item = IVec3> {
+        itertools::iproduct!(0..self.width, -1..self.height, 0..self.width).map(move |(x, y, z)| node + IVec3::new(x, y, z))
+    }
+}
+
+// Systems
+pub fn request_paths (
+    agent_query: Query<(&PathAgent, &Transform, &AabbCollider)>,
+
+    mut searches: ResMut<PathSearches>,
+
+    mut evr_path_request: EventReader<PathRequestEvent>,
+) {
+    for ev in evr_path_request.read() {
+        if let Ok((agent, transform, collider)) = agent_query.get(ev.entity) {
+            let start = agent.feet_position(transform.translation, collider);
+            // Reinserted at the back, so one agent asking over and over can't hog the budget.
+            searches.shift_remove(&ev.entity);
+            searches.insert(ev.entity, PathSearch::new(*agent, start, ev.goal));
+        }
+    }
+}
+
+/// Runs searches in the order they were asked for, until they're done or the frame's node budget is used up.
+pub fn run_path_searches (
+    mut commands: Commands,
+
+    mut searches: ResMut<PathSearches>,
+    chunk_map: Res<ChunkMap>,
+    block_registry: Res<BlockRegistry>,
+
+    mut evw_path_failed: EventWriter<PathFailedEvent>,
+) {
+    let mut budget = NODE_BUDGET;
+    let mut finished = Vec::new();
+
+    for (entity, search) in searches.iter_mut() {
+        if budget == 0 {
+            break
+        }
+
+        let (used, result) = search.step(budget, &chunk_map, &block_registry);
+        budget -= used;
+
+        match result {
+            Some(Ok(nodes)) => {
+                if let Some(mut entity_commands) = commands.get_entity(*entity) {
+                    entity_commands.insert(Path { goal: search.goal, nodes, next: 1 });
+                }
+                finished.push(*entity);
+            },
+            Some(Err(fault)) => {
+                evw_path_failed.send(PathFailedEvent { entity: *entity, fault });
+                finished.push(*entity);
+            },
+            None => {},
+        }
+    }
+
+    for entity in finished {
+        searches.shift_remove(&entity);
+    }
+}
+
+/// Throws out paths and searches that went through blocks that have since changed, and asks for them again.
+pub fn invalidate_paths (
+    mut commands: Commands,
+
+    path_query: Query<(Entity, &Path, &PathAgent)>,
+
+    mut searches: ResMut<PathSearches>,
+
+    mut evr_block_update: EventReader<BlockUpdateEvent>,
+    mut evw_path_request: EventWriter<PathRequestEvent>,
+) {
+    // Block updates keep getting sent again while they wait to be processed. Only the first send is an actual change.
+    let changed = evr_block_update.read().filter(|ev| ev.time_waited.elapsed().is_zero()).map(|ev| ev.position).collect::<HashSet<IVec3>>();
+    if changed.is_empty() {
+        return
+    }
+
+    for (entity, path, agent) in &path_query {
+        let stale = path.nodes.iter().skip(path.next.saturating_sub(1)).any(|node| changed.iter().any(|position| agent.depends_on(*node, *position)));
+        if stale {
+            commands.entity(entity).remove::<Path>();
+            evw_path_request.send(PathRequestEvent { entity, goal: path.goal });
+        }
+    }
+
+    for (entity, search) in searches.iter() {
+        if changed.iter().any(|position| search.touches(*position)) {
+            evw_path_request.send(PathRequestEvent { entity: *entity, goal: search.goal });
+        }
+    }
+}
+
+// Helpers
+/// Manhattan distance. Every move costs at least one per block it covers on each axis, so this never overestimates.
+fn heuristic (from: IVec3, to: IVec3) -> u32 {
+    let difference = (to - from).abs();
+    (difference.x + difference.y + difference.z) as u32
+}
+
+/// `None` in unloaded chunks, which paths never go into.
+fn solidity_at (position: IVec3, chunk_map: &ChunkMap, block_registry: &BlockRegistry) -> Option<Solidity> {
+    chunk_map.get(&chunk_pos_from_global(position)).map(|chunk| block_registry[chunk.blocks[block_pos_from_global(position)].id].solidity)
+}
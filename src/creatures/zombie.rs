@@ -0,0 +1,327 @@
+use std::time::Duration;
+
+use bevy::{prelude::*, utils::HashMap};
+use fastrand::Rng;
+
+use crate::{block_pos_from_global, blocks::BlockRegistry, chunk_pos_from_global, movement::{CharacterControllerBundle, JumpImpulse, MovementAction, MovementType}, pathfinding::{Path, PathAgent, PathRequestEvent}, point::GridPoint, AabbCollider, Block, ChunkMap, DespawnOnDeath, DistanceBeforeCollision, EffectCause, Gravity, Instigator, LinearVelocity, Player, Solidity, Stat, StatChangeEvent, StatType, Stats, SurfaceContact, SurfaceContacts, world_time::WorldTime};
+
+const ZOMBIE_HEIGHT: f32 = 1.8;
+const ZOMBIE_WIDTH: f32 = 0.6;
+const ZOMBIE_HEALTH: f32 = 10.0;
+/// Health a zombie takes off with each hit.
+const ZOMBIE_DAMAGE: f32 = 3.0;
+/// How far past its own collider a zombie can hit.
+const ZOMBIE_REACH: f32 = 0.5;
+const ZOMBIE_ATTACK_COOLDOWN: Duration = Duration::from_millis(1000);
+/// Players closer than this get chased.
+const CHASE_RANGE: f32 = 16.0;
+/// Players that get further than this away are given up on.
+const GIVE_UP_RANGE: f32 = 24.0;
+/// Seconds between working out a new path to whoever is being chased.
+const REPATH_INTERVAL: f32 = 1.0;
+/// How close to the middle of a path node a zombie has to get before heading for the next one.
+const NODE_REACHED_DISTANCE: f32 = 0.3;
+
+const MAX_ZOMBIES: usize = 12;
+/// Seconds between attempts at spawning zombies.
+const SPAWN_INTERVAL: f32 = 5.0;
+/// How many random spots near each player get tried every spawn attempt.
+const SPAWN_TRIES: usize = 16;
+/// Zombies spawn in a box this many blocks out from a player in every direction...
+const SPAWN_RADIUS: i32 = 32;
+/// ...but never this close to one.
+const MIN_SPAWN_DISTANCE: f32 = 12.0;
+/// Zombies only spawn where it's at most this bright, unless they're underground.
+const MAX_SPAWN_LIGHT: u8 = 7;
+/// Zombies further than this from every player are removed.
+const DESPAWN_DISTANCE: f32 = 64.0;
+
+// Resources
+#[derive(Resource)]
+pub struct ZombieSpawner {
+    pub timer: Timer,
+}
+impl Default for ZombieSpawner {
+    fn default() -> Self {
+        Self { timer: Timer::from_seconds(SPAWN_INTERVAL, TimerMode::Repeating) }
+    }
+}
+
+#[derive(Resource)]
+pub struct ZombieAssets {
+    pub mesh: Handle<Mesh>,
+    pub material: Handle<StandardMaterial>,
+}
+
+// Components
+#[derive(Component, Default, Copy, Clone)]
+pub struct Zombie;
+
+#[derive(Component, Clone, Debug)]
+pub enum ZombieState {
+    /// Shuffling along in `direction`, or standing around if there isn't one, until the timer runs out.
+    Wandering { direction: Option<Vec2>, timer: Timer },
+    Chasing(Entity),
+}
+impl Default for ZombieState {
+    fn default() -> Self {
+        ZombieState::Wandering { direction: None, timer: Timer::from_seconds(1.0, TimerMode::Once) }
+    }
+}
+
+#[derive(Component, Clone, Deref, DerefMut, Debug)]
+pub struct RepathTimer(pub Timer);
+impl Default for RepathTimer {
+    fn default() -> Self {
+        Self(Timer::from_seconds(REPATH_INTERVAL, TimerMode::Repeating))
+    }
+}
+
+#[derive(Component, Clone, Deref, DerefMut, Debug)]
+pub struct AttackCooldown(pub Timer);
+impl Default for AttackCooldown {
+    fn default() -> Self {
+        // Starts out finished, so the first hit doesn't have to wait.
+        let mut timer = Timer::new(ZOMBIE_ATTACK_COOLDOWN, TimerMode::Once);
+        timer.tick(ZOMBIE_ATTACK_COOLDOWN);
+        Self(timer)
+    }
+}
+
+// Systems
+pub fn setup_zombie_assets (
+    mut commands: Commands,
+
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    commands.insert_resource(ZombieAssets {
+        mesh: meshes.add(Cuboid::new(ZOMBIE_WIDTH, ZOMBIE_HEIGHT, ZOMBIE_WIDTH)),
+        material: materials.add(StandardMaterial {
+            base_color: Color::rgb(0.33, 0.55, 0.27),
+            unlit: true,
+            ..default()
+        }),
+    });
+}
+
+/// Every so often, tries to put a zombie somewhere dark near each player.
+pub fn spawn_zombies (
+    mut commands: Commands,
+
+    player_query: Query<&Transform, With<Player>>,
+    zombie_query: Query<(), With<Zombie>>,
+
+    mut spawner: ResMut<ZombieSpawner>,
+    zombie_assets: Res<ZombieAssets>,
+    chunk_map: Res<ChunkMap>,
+    block_registry: Res<BlockRegistry>,
+    world_time: Res<WorldTime>,
+
+    time: Res<Time>,
+) {
+    spawner.timer.tick(time.delta());
+    if !spawner.timer.just_finished() {
+        return
+    }
+
+    let mut zombie_count = zombie_query.iter().count();
+    let mut rng = Rng::new();
+
+    for player_transform in &player_query {
+        if zombie_count >= MAX_ZOMBIES {
+            return
+        }
+
+        let player_position = player_transform.translation.round().as_ivec3();
+        for _ in 0..SPAWN_TRIES {
+            let position = player_position + IVec3::new(rng.i32(-SPAWN_RADIUS..=SPAWN_RADIUS), rng.i32(-SPAWN_RADIUS/2..=SPAWN_RADIUS/2), rng.i32(-SPAWN_RADIUS..=SPAWN_RADIUS));
+            if position.as_vec3().distance(player_transform.translation) < MIN_SPAWN_DISTANCE {
+                continue
+            }
+            if !can_spawn_at(position, world_time.is_night(), &chunk_map, &block_registry) {
+                continue
+            }
+
+            // Blocks are centered on their position, so the bottom of this one is half a block down.
+            let translation = position.as_vec3() + Vec3::Y * (ZOMBIE_HEIGHT / 2.0 - 0.5);
+            let collider = AabbCollider::new(ZOMBIE_WIDTH, ZOMBIE_HEIGHT, ZOMBIE_WIDTH);
+            commands.spawn((
+                PbrBundle {
+                    mesh: zombie_assets.mesh.clone(),
+                    material: zombie_assets.material.clone(),
+                    transform: Transform::from_translation(translation),
+                    ..default()
+                },
+                CharacterControllerBundle::new(collider).with_movement(
+                    6.0,
+                    6.0,
+                ),
+                PathAgent::new(&collider, &JumpImpulse(6.0), &Gravity(14.0)),
+                DistanceBeforeCollision::default(),
+                LinearVelocity::default(),
+                Gravity(14.0),
+                Stats(HashMap::from([
+                    (StatType::Health, Stat::new(0.0, ZOMBIE_HEALTH)),
+                ])),
+                Zombie,
+                ZombieState::default(),
+                AttackCooldown::default(),
+                RepathTimer::default(),
+                DespawnOnDeath,
+            ));
+
+            zombie_count += 1;
+            break;
+        }
+    }
+}
+
+/// Gets rid of zombies nobody is around to see, and ones whose chunk has been unloaded out from under them.
+pub fn despawn_far_zombies (
+    mut commands: Commands,
+
+    zombie_query: Query<(Entity, &Transform), With<Zombie>>,
+    player_query: Query<&Transform, With<Player>>,
+
+    chunk_map: Res<ChunkMap>,
+) {
+    for (entity, transform) in &zombie_query {
+        let near_player = player_query.iter().any(|player_transform| player_transform.translation.distance(transform.translation) < DESPAWN_DISTANCE);
+        let loaded = chunk_map.contains_key(&chunk_pos_from_global(transform.translation.round().as_ivec3()));
+
+        if !near_player || !loaded {
+            commands.entity(entity).despawn_recursive();
+        }
+    }
+}
+
+/// Zombies wander around until a player gets close, then follow a path to them and hit them whenever they're in reach.
+pub fn zombie_ai (
+    mut zombie_query: Query<(Entity, &mut Transform, &AabbCollider, &PathAgent, &mut ZombieState, &mut AttackCooldown, &mut RepathTimer, Option<&mut Path>, Option<&SurfaceContacts>), With<Zombie>>,
+    player_query: Query<(Entity, &Transform, &AabbCollider), (With<Player>, Without<Zombie>)>,
+
+    mut evw_movement: EventWriter<MovementAction>,
+    mut evw_stat_change: EventWriter<StatChangeEvent>,
+    mut evw_path_request: EventWriter<PathRequestEvent>,
+
+    time: Res<Time>,
+) {
+    let mut rng = Rng::new();
+
+    for (entity, mut transform, collider, agent, mut state, mut cooldown, mut repath_timer, mut opt_path, opt_surface_contacts) in &mut zombie_query {
+        cooldown.tick(time.delta());
+        let feet = agent.feet_position(transform.translation, collider);
+        let mut climbing = false;
+
+        // Chase whoever is closest, and keep chasing them until they get away.
+        let target = match *state {
+            ZombieState::Chasing(player) => player_query.get(player).ok().filter(|(_, player_transform, _)| player_transform.translation.distance(transform.translation) < GIVE_UP_RANGE),
+            ZombieState::Wandering { .. } => None,
+        };
+        let target = target.or_else(|| {
+            player_query.iter()
+                .filter(|(_, player_transform, _)| player_transform.translation.distance(transform.translation) < CHASE_RANGE)
+                .min_by(|(_, a, _), (_, b, _)| a.translation.distance(transform.translation).total_cmp(&b.translation.distance(transform.translation)))
+        });
+
+        let direction = match target {
+            Some((player, player_transform, player_collider)) => {
+                let mut reach = *collider;
+                reach.width += ZOMBIE_REACH * 2.0;
+                reach.length += ZOMBIE_REACH * 2.0;
+                if cooldown.finished() && reach.get_intersection(transform.translation, *player_collider, player_transform.translation) {
+                    evw_stat_change.send(StatChangeEvent::new(Instigator::Entity(entity), EffectCause::Attack, StatType::Health, -ZOMBIE_DAMAGE, player));
+                    cooldown.reset();
+                }
+
+                if repath_timer.tick(time.delta()).just_finished() || !matches!(*state, ZombieState::Chasing(_)) {
+                    evw_path_request.send(PathRequestEvent { entity, goal: agent.feet_position(player_transform.translation, player_collider) });
+                }
+
+                *state = ZombieState::Chasing(player);
+
+                // Head for the next node on the path, and straight for the player once it runs out or there isn't one yet.
+                let mut heading = player_transform.translation;
+                if let Some(path) = opt_path.as_deref_mut() {
+                    if let Some(node) = path.next_node() {
+                        let offset = node.as_vec3() - transform.translation;
+                        if feet.y == node.y && Vec2::new(offset.x, offset.z).length() < NODE_REACHED_DISTANCE {
+                            path.advance();
+                        }
+                    }
+                    if let Some(node) = path.next_node() {
+                        heading = node.as_vec3();
+                        climbing = node.y > feet.y;
+                    }
+                }
+
+                let to_heading = heading - transform.translation;
+                Vec2::new(to_heading.x, to_heading.z).try_normalize()
+            },
+            None => {
+                if let ZombieState::Chasing(_) = *state {
+                    *state = ZombieState::default();
+                    // Whatever path it had led to someone it isn't after anymore.
+                    if let Some(path) = opt_path.as_deref_mut() {
+                        path.next = path.nodes.len();
+                    }
+                }
+
+                match &mut *state {
+                    ZombieState::Wandering { direction, timer } => {
+                        timer.tick(time.delta());
+                        if timer.finished() {
+                            // About half the time is spent standing around.
+                            *direction = if rng.bool() { Vec2::from_angle(rng.f32() * std::f32::consts::TAU).try_normalize() } else { None };
+                            *timer = Timer::from_seconds(2.0 + rng.f32() * 3.0, TimerMode::Once);
+                        }
+                        // Wandering is slower than chasing.
+                        direction.map(|direction| direction * 0.5)
+                    },
+                    ZombieState::Chasing(_) => None,
+                }
+            },
+        };
+
+        if let Some(direction) = direction {
+            evw_movement.send(MovementAction::new(entity, MovementType::Move(direction)));
+            transform.rotation = Quat::from_rotation_y(direction.x.atan2(direction.y));
+
+            // Anything in the way gets jumped over.
+            if let Some(surface_contacts) = opt_surface_contacts {
+                if [SurfaceContact::PosX, SurfaceContact::NegX, SurfaceContact::PosZ, SurfaceContact::NegZ].iter().any(|contact| surface_contacts.contains(contact)) {
+                    evw_movement.send(MovementAction::new(entity, MovementType::Jump));
+                }
+            }
+        }
+
+        // Jumping also gets zombies up ladders and out of water.
+        if climbing {
+            evw_movement.send(MovementAction::new(entity, MovementType::Jump));
+        }
+    }
+}
+
+// Helpers
+/// Zombies need two blocks of open space to stand in, solid ground under them, and either darkness or to be underground.
+/// At night sky light doesn't count, so they can show up anywhere that isn't lit by a block.
+fn can_spawn_at (position: IVec3, is_night: bool, chunk_map: &ChunkMap, block_registry: &BlockRegistry) -> bool {
+    let is_open = |position: IVec3| block_at(position, chunk_map).map_or(false, |block| block_registry[block.id].solidity == Solidity::NonSolid);
+    let is_ground = block_at(position.down(1), chunk_map).map_or(false, |block| block_registry[block.id].solidity == Solidity::Solid);
+    if !is_open(position) || !is_open(position.up(1)) || !is_ground {
+        return false
+    }
+
+    let underground = chunk_pos_from_global(position).y < 0;
+    let dark = chunk_map.get(&chunk_pos_from_global(position)).map_or(false, |chunk| {
+        let light = chunk.light[block_pos_from_global(position)];
+        let level = if is_night { light.block() } else { light.level() };
+        level <= MAX_SPAWN_LIGHT
+    });
+    underground || dark
+}
+
+fn block_at (position: IVec3, chunk_map: &ChunkMap) -> Option<Block> {
+    chunk_map.get(&chunk_pos_from_global(position)).map(|chunk| chunk.blocks[block_pos_from_global(position)])
+}
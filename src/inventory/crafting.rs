@@ -0,0 +1,172 @@
+use std::{fmt::Display, fs};
+
+use bevy::{prelude::*, utils::{HashMap, HashSet}};
+use itertools::iproduct;
+use serde::Deserialize;
+
+use crate::{block_pos_from_global, blocks::BlockRegistry, chunk_pos_from_global, BlockID, ChunkMap, Inventory, Item, ItemID};
+
+pub const RECIPE_REGISTRY_PATH: &str = "assets/recipes.ron";
+/// How many blocks away from a crafter a station still counts as being at hand.
+const STATION_REACH: i32 = 3;
+
+// Events
+/// Sent when an entity wants to craft the recipe at `recipe` in the [`RecipeRegistry`].
+#[derive(Clone, Copy, Event)]
+pub struct CraftEvent {
+    pub entity: Entity,
+    pub recipe: usize,
+}
+
+// Components
+/// The entity has its crafting menu open.
+#[derive(Clone, Copy, Component, Debug)]
+pub struct CraftingOpen;
+
+// Resources
+/// Every recipe the game knows about. Loaded from [`RECIPE_REGISTRY_PATH`] when the [`InventoryPlugin`](crate::InventoryPlugin) is built.
+#[derive(Clone, Resource, Deref)]
+pub struct RecipeRegistry(Vec<Recipe>);
+impl RecipeRegistry {
+    pub fn load(path: &str) -> Result<RecipeRegistry, RecipeRegistryFault> {
+        let text = fs::read_to_string(path).map_err(|err| RecipeRegistryFault::Io(err.to_string()))?;
+        let file: RecipeDefinitionFile = ron::from_str(&text).map_err(|err| RecipeRegistryFault::Parse(err.to_string()))?;
+
+        let mut names = HashSet::new();
+        for recipe in file.recipes.iter() {
+            if !names.insert(recipe.name.clone()) {
+                return Err(RecipeRegistryFault::DuplicateName(recipe.name.clone()));
+            }
+            if recipe.inputs.is_empty() || recipe.outputs.is_empty() {
+                return Err(RecipeRegistryFault::Empty(recipe.name.clone()));
+            }
+        }
+
+        // Written items only have an id and amount, so fill in whatever else a fresh item should have, like a tool's durability.
+        let recipes = file.recipes.into_iter().map(|recipe| Recipe {
+            outputs: recipe.outputs.iter().map(|item| Item::new(item.id, item.amount)).collect(),
+            ..recipe
+        }).collect();
+
+        Ok(RecipeRegistry(recipes))
+    }
+
+    /// Indices of the recipes that can be crafted out of `inventory` with the stations in `stations`.
+    pub fn craftable<'a>(&'a self, inventory: &'a Inventory, stations: &'a HashSet<BlockID>, block_registry: &'a BlockRegistry) -> impl Iterator<Item = usize> + 'a {
+        self.iter().enumerate()
+            .filter(|(_, recipe)| recipe.has_station(stations, block_registry) && recipe.has_inputs(inventory))
+            .map(|(i, _)| i)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum RecipeRegistryFault {
+    Io(String),
+    Parse(String),
+    DuplicateName(String),
+    Empty(String),
+}
+impl Display for RecipeRegistryFault {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RecipeRegistryFault::Io(err) => write!(f, "could not read recipe registry: {}", err),
+            RecipeRegistryFault::Parse(err) => write!(f, "could not parse recipe registry: {}", err),
+            RecipeRegistryFault::DuplicateName(name) => write!(f, "recipe \"{}\" is defined more than once", name),
+            RecipeRegistryFault::Empty(name) => write!(f, "recipe \"{}\" needs at least one input and one output", name),
+        }
+    }
+}
+
+// Data
+#[derive(Clone, Debug, Deserialize)]
+pub struct Recipe {
+    pub name: String,
+    pub inputs: Vec<Item>,
+    pub outputs: Vec<Item>,
+    /// Name of the block that has to be nearby to craft this, if any.
+    #[serde(default)]
+    pub station: Option<String>,
+}
+impl Recipe {
+    pub fn has_inputs(&self, inventory: &Inventory) -> bool {
+        let mut needed = HashMap::<ItemID, u16>::new();
+        for input in self.inputs.iter() {
+            *needed.entry(input.id).or_default() += input.amount;
+        }
+        needed.iter().all(|(id, amount)| inventory.get_item_amount(*id) >= *amount)
+    }
+
+    pub fn has_station(&self, stations: &HashSet<BlockID>, block_registry: &BlockRegistry) -> bool {
+        match &self.station {
+            Some(name) => block_registry.id(name).map_or(false, |id| stations.contains(&id)),
+            None => true,
+        }
+    }
+
+    /// What `inventory` would look like after crafting this once. Takes all the inputs and puts in all the outputs, or fails without touching anything.
+    pub fn craft(&self, inventory: &Inventory) -> Result<Inventory, CraftFault> {
+        let mut crafted = inventory.clone();
+        for input in self.inputs.iter() {
+            crafted.take_item(*input).map_err(|_| CraftFault::MissingInputs)?;
+        }
+        for output in self.outputs.iter() {
+            crafted.insert_item(*output).map_err(|_| CraftFault::NoSpace)?;
+        }
+        Ok(crafted)
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum CraftFault {
+    MissingInputs,
+    MissingStation,
+    NoSpace,
+}
+
+#[derive(Deserialize)]
+struct RecipeDefinitionFile {
+    recipes: Vec<Recipe>,
+}
+
+// Systems
+pub fn craft_items (
+    mut crafter_query: Query<(&Transform, &mut Inventory)>,
+
+    recipe_registry: Res<RecipeRegistry>,
+    block_registry: Res<BlockRegistry>,
+    chunk_map: Res<ChunkMap>,
+
+    mut evr_craft: EventReader<CraftEvent>,
+) {
+    for ev in evr_craft.read() {
+        let Ok((transform, mut inventory)) = crafter_query.get_mut(ev.entity) else {
+            continue
+        };
+        let Some(recipe) = recipe_registry.get(ev.recipe) else {
+            continue
+        };
+
+        let stations = nearby_stations(transform.translation, &chunk_map);
+        let result = if recipe.has_station(&stations, &block_registry) { recipe.craft(&inventory) } else { Err(CraftFault::MissingStation) };
+        match result {
+            Ok(crafted) => *inventory = crafted,
+            Err(fault) => info!("Couldn't craft {}: {:?}", recipe.name, fault),
+        }
+    }
+}
+
+// Helpers
+/// Every kind of block within [`STATION_REACH`] of `position`.
+pub fn nearby_stations (position: Vec3, chunk_map: &ChunkMap) -> HashSet<BlockID> {
+    let center = position.round().as_ivec3();
+    let mut stations = HashSet::new();
+
+    for (x, y, z) in iproduct!(-STATION_REACH..=STATION_REACH, -STATION_REACH..=STATION_REACH, -STATION_REACH..=STATION_REACH) {
+        let position = center + IVec3::new(x, y, z);
+        if let Some(chunk) = chunk_map.get(&chunk_pos_from_global(position)) {
+            stations.insert(chunk.blocks[block_pos_from_global(position)].id);
+        }
+    }
+
+    stations
+}
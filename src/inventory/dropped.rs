@@ -0,0 +1,175 @@
+use std::time::Duration;
+
+use bevy::{prelude::*, render::mesh::VertexAttributeValues};
+use fastrand::Rng;
+
+use crate::{AabbCollider, DistanceBeforeCollision, Gravity, InGameCamera, Inventory, Item, LinearVelocity, Materials, Player};
+
+/// Width and height of a dropped item, both for drawing it and for colliding with it.
+const ITEM_SIZE: f32 = 0.4;
+/// How long a dropped item sits in the world before it disappears.
+const DESPAWN_TIME: Duration = Duration::from_secs(300);
+/// Stacks of the same item closer together than this get merged into one.
+const MERGE_DISTANCE: f32 = 1.0;
+/// Items in the atlas are laid out on a grid this many tiles across.
+const ITEM_ATLAS_COLUMNS: f32 = 32.0;
+
+// Events
+/// Puts an item into the world at `position`, to be picked up later.
+#[derive(Clone, Copy, Event)]
+pub struct DropItemEvent {
+    pub item: Item,
+    pub position: Vec3,
+}
+
+// Components
+#[derive(Clone, Copy, Component, Deref, DerefMut, Debug)]
+pub struct DroppedItem(pub Item);
+
+#[derive(Clone, Component, Deref, DerefMut, Debug)]
+pub struct DespawnTimer(pub Timer);
+impl Default for DespawnTimer {
+    fn default() -> Self {
+        Self(Timer::new(DESPAWN_TIME, TimerMode::Once))
+    }
+}
+
+// Systems
+pub fn spawn_dropped_items (
+    mut commands: Commands,
+
+    materials: Res<Materials>,
+
+    mut evr_drop_item: EventReader<DropItemEvent>,
+) {
+    let mut rng = Rng::new();
+
+    for ev in evr_drop_item.read() {
+        // Items pop out of wherever they came from in a random direction, so a pile of them doesn't all land on the same spot.
+        let velocity = Vec3::new(rng.f32() - 0.5, 1.0 + rng.f32() * 0.5, rng.f32() - 0.5) * 3.0;
+
+        commands.spawn((
+            PbrBundle {
+                material: materials.items_8x8.clone(),
+                transform: Transform::from_translation(ev.position),
+                ..default()
+            },
+            DroppedItem(ev.item),
+            DespawnTimer::default(),
+            AabbCollider::new(ITEM_SIZE, ITEM_SIZE, ITEM_SIZE),
+            DistanceBeforeCollision::default(),
+            LinearVelocity(velocity),
+            Gravity(14.0),
+        ));
+    }
+}
+
+/// Items show a different picture depending on how many of them there are, so their mesh gets rebuilt whenever the stack changes.
+pub fn update_dropped_item_meshes (
+    mut query: Query<(&DroppedItem, &mut Handle<Mesh>), Changed<DroppedItem>>,
+
+    mut meshes: ResMut<Assets<Mesh>>,
+) {
+    for (dropped_item, mut mesh) in &mut query {
+        *mesh = meshes.add(item_billboard_mesh(**dropped_item));
+    }
+}
+
+/// Keeps dropped items turned towards the camera.
+pub fn face_dropped_items (
+    mut query: Query<&mut Transform, With<DroppedItem>>,
+    cam_query: Query<&GlobalTransform, With<InGameCamera>>,
+) {
+    let Ok(camera_transform) = cam_query.get_single() else {
+        return
+    };
+
+    for mut transform in &mut query {
+        let to_camera = camera_transform.translation() - transform.translation;
+        transform.rotation = Quat::from_rotation_y(to_camera.x.atan2(to_camera.z));
+    }
+}
+
+pub fn merge_dropped_items (
+    mut commands: Commands,
+
+    mut query: Query<(Entity, &mut DroppedItem, &Transform, &mut DespawnTimer)>,
+) {
+    let stacks = query.iter().map(|(entity, dropped_item, transform, _)| (entity, **dropped_item, transform.translation)).collect::<Vec<_>>();
+    let mut merged = vec![false; stacks.len()];
+
+    for i in 0..stacks.len() {
+        if merged[i] {
+            continue
+        }
+        let (entity, mut item, position) = stacks[i];
+        let max_amount = item.get_attributes().max_amount;
+
+        for j in (i + 1)..stacks.len() {
+            let (other_entity, other_item, other_position) = stacks[j];
+            if merged[j] || other_item.id != item.id || item.amount + other_item.amount > max_amount || position.distance(other_position) > MERGE_DISTANCE {
+                continue
+            }
+
+            item.amount += other_item.amount;
+            merged[j] = true;
+            commands.entity(other_entity).despawn_recursive();
+        }
+
+        if item.amount != stacks[i].1.amount {
+            if let Ok((_, mut dropped_item, _, mut timer)) = query.get_mut(entity) {
+                **dropped_item = item;
+                timer.reset();
+            }
+        }
+    }
+}
+
+/// Players pick up whatever they walk into, as long as they have room for all of it.
+pub fn pickup_dropped_items (
+    mut commands: Commands,
+
+    mut player_query: Query<(&Transform, &AabbCollider, &mut Inventory), With<Player>>,
+    item_query: Query<(Entity, &DroppedItem, &Transform, &AabbCollider), Without<Player>>,
+) {
+    for (player_transform, player_collider, mut inventory) in &mut player_query {
+        for (entity, dropped_item, transform, collider) in &item_query {
+            if !player_collider.get_intersection(player_transform.translation, *collider, transform.translation) {
+                continue
+            }
+
+            if inventory.insert_item(**dropped_item).is_ok() {
+                commands.entity(entity).despawn_recursive();
+            }
+        }
+    }
+}
+
+pub fn despawn_dropped_items (
+    mut commands: Commands,
+
+    mut query: Query<(Entity, &mut DespawnTimer), With<DroppedItem>>,
+
+    time: Res<Time>,
+) {
+    for (entity, mut timer) in &mut query {
+        timer.tick(time.delta());
+
+        if timer.finished() {
+            commands.entity(entity).despawn_recursive();
+        }
+    }
+}
+
+// Helpers
+/// A quad showing `item`'s tile from the item atlas.
+fn item_billboard_mesh (item: Item) -> Mesh {
+    let tex_coords = item.get_tex_coords().as_vec2();
+    let min = tex_coords / ITEM_ATLAS_COLUMNS;
+    let max = (tex_coords + 1.0) / ITEM_ATLAS_COLUMNS;
+
+    let mut mesh = Mesh::from(Rectangle::new(ITEM_SIZE, ITEM_SIZE));
+    // Same corner order as the rectangle's own UVs.
+    mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, VertexAttributeValues::Float32x2(vec![[max.x, min.y], [min.x, min.y], [min.x, max.y], [max.x, max.y]]));
+    mesh
+}
@@ -1,11 +1,12 @@
 use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
 
 use crate::BlockID;
 
 use super::ItemID;
 
 // Components
-#[derive(Clone, Debug, Component)]
+#[derive(Clone, Debug, Component, Serialize, Deserialize)]
 pub struct Hotbar {
     pub position: usize,
     pub slots: Vec<SlotAction>,
@@ -13,13 +14,13 @@ pub struct Hotbar {
 impl Default for Hotbar {
     fn default() -> Self {
         Self { position: 0, slots: vec![SlotAction::Block(BlockID::Planks), SlotAction::Block(BlockID::StoneBrick), SlotAction::Block(BlockID::Crate), 
-                                        SlotAction::Block(BlockID::Scaffold), SlotAction::None, SlotAction::None, 
+                                        SlotAction::Block(BlockID::Scaffold), SlotAction::Block(BlockID::Workbench), SlotAction::None, 
                                         SlotAction::None, SlotAction::None, SlotAction::None, 
                                         SlotAction::None, ]}
     }
 }
 
-#[derive(Clone, Default, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Clone, Default, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
 pub enum SlotAction {
     #[default] None,
     Block(BlockID),
@@ -1,17 +1,43 @@
 use std::{collections::VecDeque, fmt::Display};
 
+use crafting::*;
 use hotbar::*;
+use dropped::*;
+use tools::*;
 use bevy::{prelude::*, utils::hashbrown::HashMap};
 use itertools::Itertools;
+use serde::{Deserialize, Serialize};
 
+use crate::GameState;
+
+pub mod crafting;
+pub mod dropped;
 pub mod hotbar;
+pub mod tools;
 
 const MAX_MATERIAL: u16 = 2048;
 const INVENTORY_SIZE: IVec2 = IVec2::new(9, 2);
+/// Hits a tool lasts for, by what it's made of.
+const WOOD_DURABILITY: u16 = 64;
+const STONE_DURABILITY: u16 = 192;
+
+//Plugin
+pub struct InventoryPlugin;
+
+impl Plugin for InventoryPlugin {
+    fn build(&self, app: &mut App) {
+        app
+        .insert_resource(RecipeRegistry::load(RECIPE_REGISTRY_PATH).unwrap_or_else(|fault| panic!("Failed to load recipe registry: {}", fault)))
+        .add_event::<DropItemEvent>()
+        .add_event::<CraftEvent>()
+        .add_systems(Update, craft_items.run_if(in_state(GameState::Playing)))
+        .add_systems(Update, (spawn_dropped_items, despawn_dropped_items, merge_dropped_items, pickup_dropped_items, update_dropped_item_meshes, face_dropped_items).chain().run_if(in_state(GameState::Playing)));
+    }
+}
 
 
 // TODO: We might want to make this a non tuple struct later on when we have non player inventories that differ in size from the player's inventory and will need a unique inventory_size field.
-#[derive(Clone, Component, Deref, DerefMut)]
+#[derive(Clone, Debug, Component, Deref, DerefMut, Serialize, Deserialize)]
 pub struct Inventory(pub Vec<Item>);
 impl Default for Inventory {
     fn default() -> Self {
@@ -21,9 +47,14 @@ impl Default for Inventory {
 impl Inventory {
     pub fn consolidate (&mut self) {
         let mut item_totals = HashMap::<ItemID, u16>::new();
+        // Tools each wear out on their own, so they never get stacked.
+        let mut tools = Vec::new();
 
         for slot in self.iter() {
-            if let Some(total) = item_totals.get_mut(&slot.id) {
+            if slot.get_attributes().tool.is_some() {
+                tools.push(*slot);
+            }
+            else if let Some(total) = item_totals.get_mut(&slot.id) {
                 *total += slot.amount;
             }
             else {
@@ -31,7 +62,7 @@ impl Inventory {
             }
         }
 
-        let mut item_totals: Vec<Item> = item_totals.iter().map(|(id, total)| Item{ id: *id, amount: *total }).collect();
+        let mut item_totals: Vec<Item> = item_totals.iter().map(|(id, total)| Item::new(*id, *total)).collect();
         item_totals.sort_unstable_by_key(|item| item.id);
 
         self.clear();
@@ -40,15 +71,28 @@ impl Inventory {
             let attributes = item.id.get_attributes();
             while item.amount > 0 {
                 if item.amount < attributes.max_amount {
-                    self.push(Item { id: item.id, amount: item.amount });
+                    self.push(Item::new(item.id, item.amount));
                     item.amount = 0;
                 } else {
-                    self.push(Item { id: item.id, amount: attributes.max_amount});
+                    self.push(Item::new(item.id, attributes.max_amount));
                     item.amount -= attributes.max_amount;
                 }
                 
             }
         }
+
+        tools.sort_by_key(|tool| tool.id);
+        self.extend(tools);
+    }
+
+    /// Wears down the tool in `slot` by one use, throwing it away once it breaks.
+    pub fn wear_tool(&mut self, slot: usize) {
+        if let Some(item) = self.get_mut(slot) {
+            item.durability = item.durability.saturating_sub(1);
+            if item.durability == 0 {
+                self.remove(slot);
+            }
+        }
     }
 
     pub fn get_item_amount(&self, item_id: ItemID) -> u16 {
@@ -129,15 +173,19 @@ pub enum ItemExtractFault {
     InsufficientAmount
 }
 
-#[derive(Clone, Copy,)]
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
 pub struct Item {
     pub id: ItemID,
     pub amount: u16,
+    /// Uses left before a tool breaks. Always 0 for anything that isn't a tool.
+    #[serde(default)]
+    pub durability: u16,
 }
 impl Item {
     pub fn new(id: ItemID, amount: u16) -> Item {
         // TODO: Make the ItemData thing be tailored for the Item we're making.
-        Item {id, amount } //data: [ItemData::None]}
+        let durability = id.get_attributes().tool.map_or(0, |tool| tool.durability);
+        Item {id, amount, durability } //data: [ItemData::None]}
     }
 
     pub fn get_attributes(self) -> ItemAttributes {
@@ -147,6 +195,9 @@ impl Item {
     pub fn get_tex_coords(self) -> IVec2 {
         let attributes = self.id.get_attributes();
         let coords = attributes.tex_coords;
+        if attributes.coord_increment_num == 0 {
+            return coords
+        }
         IVec2::new(coords.x + (self.amount.min(attributes.max_amount) / attributes.coord_increment_num) as i32, coords.y)
     }
 }
@@ -160,27 +211,56 @@ pub struct ItemAttributes {
     // The number where we stop incrementing the coords' x value
     //pub coord_increment_limit: u16,
     pub max_amount: u16,
+    pub tool: Option<ToolAttributes>,
 }
 
-#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Reflect, Debug)]
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Reflect, Debug, Serialize, Deserialize)]
 pub enum ItemID {
     //BuildingMaterial(BuildingMaterial),
     Stone,
     Wood,
+    IronOre,
+    GoldOre,
+    WoodPickaxe,
+    StonePickaxe,
+    WoodAxe,
+    StoneAxe,
+    WoodShovel,
+    StoneShovel,
 }
 impl ItemID {
     fn get_attributes(self) -> ItemAttributes {
         match self {
-            ItemID::Stone => ItemAttributes { tex_coords: IVec2::new(0, 0), coord_increment_num: MAX_MATERIAL / 4, max_amount: MAX_MATERIAL}, //coord_increment_limit: MAX_MATERIAL },
-            ItemID::Wood => ItemAttributes { tex_coords: IVec2::new(0, 1), coord_increment_num: MAX_MATERIAL / 4, max_amount: MAX_MATERIAL},
+            ItemID::Stone => ItemAttributes { tex_coords: IVec2::new(0, 0), coord_increment_num: MAX_MATERIAL / 4, max_amount: MAX_MATERIAL, ..default() }, //coord_increment_limit: MAX_MATERIAL },
+            ItemID::Wood => ItemAttributes { tex_coords: IVec2::new(0, 1), coord_increment_num: MAX_MATERIAL / 4, max_amount: MAX_MATERIAL, ..default() },
+            ItemID::IronOre => ItemAttributes { tex_coords: IVec2::new(0, 5), coord_increment_num: MAX_MATERIAL / 4, max_amount: MAX_MATERIAL, ..default() },
+            ItemID::GoldOre => ItemAttributes { tex_coords: IVec2::new(0, 6), coord_increment_num: MAX_MATERIAL / 4, max_amount: MAX_MATERIAL, ..default() },
+            ItemID::WoodPickaxe => tool_attributes(IVec2::new(0, 2), ToolKind::Pickaxe, MiningStats { strength: 1, damage: 1, speed: 1.5 }, WOOD_DURABILITY),
+            ItemID::StonePickaxe => tool_attributes(IVec2::new(1, 2), ToolKind::Pickaxe, MiningStats { strength: 2, damage: 2, speed: 2.0 }, STONE_DURABILITY),
+            ItemID::WoodAxe => tool_attributes(IVec2::new(0, 3), ToolKind::Axe, MiningStats { strength: 1, damage: 1, speed: 2.0 }, WOOD_DURABILITY),
+            ItemID::StoneAxe => tool_attributes(IVec2::new(1, 3), ToolKind::Axe, MiningStats { strength: 2, damage: 2, speed: 3.0 }, STONE_DURABILITY),
+            ItemID::WoodShovel => tool_attributes(IVec2::new(0, 4), ToolKind::Shovel, MiningStats { strength: 1, damage: 1, speed: 2.0 }, WOOD_DURABILITY),
+            ItemID::StoneShovel => tool_attributes(IVec2::new(1, 4), ToolKind::Shovel, MiningStats { strength: 2, damage: 2, speed: 3.0 }, STONE_DURABILITY),
         }
     }
 }
+
+fn tool_attributes(tex_coords: IVec2, kind: ToolKind, stats: MiningStats, durability: u16) -> ItemAttributes {
+    ItemAttributes { tex_coords, coord_increment_num: 0, max_amount: 1, tool: Some(ToolAttributes { kind, stats, durability }) }
+}
 impl Display for ItemID {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             ItemID::Stone => write!(f, "Stone"),
             ItemID::Wood => write!(f, "Wood"),
+            ItemID::IronOre => write!(f, "Iron Ore"),
+            ItemID::GoldOre => write!(f, "Gold Ore"),
+            ItemID::WoodPickaxe => write!(f, "Wooden Pickaxe"),
+            ItemID::StonePickaxe => write!(f, "Stone Pickaxe"),
+            ItemID::WoodAxe => write!(f, "Wooden Axe"),
+            ItemID::StoneAxe => write!(f, "Stone Axe"),
+            ItemID::WoodShovel => write!(f, "Wooden Shovel"),
+            ItemID::StoneShovel => write!(f, "Stone Shovel"),
         }
     }
 }
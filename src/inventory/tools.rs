@@ -0,0 +1,45 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{BlockAttributes, Inventory};
+
+/// What mining with nothing in particular looks like.
+pub const HAND: MiningStats = MiningStats { strength: 0, damage: 1, speed: 1.0 };
+
+// Data
+/// The kinds of tools there are. Blocks name the kind that's good for mining them.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Serialize, Deserialize)]
+pub enum ToolKind {
+    Pickaxe,
+    Axe,
+    Shovel,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct ToolAttributes {
+    pub kind: ToolKind,
+    pub stats: MiningStats,
+    /// How many hits the tool lasts for.
+    pub durability: u16,
+}
+
+/// How well something mines.
+#[derive(Clone, Copy, Debug)]
+pub struct MiningStats {
+    /// Compared against [`BlockAttributes::toughness`]. Blocks that are tougher than this can't be mined at all.
+    pub strength: u8,
+    /// Damage done to a block with each hit.
+    pub damage: u8,
+    /// How many times faster than by hand hits come.
+    pub speed: f32,
+}
+
+// Helpers
+/// The best tool in `inventory` for mining a block, along with its slot. Tools only help with the blocks they're meant for.
+pub fn best_tool (inventory: &Inventory, block: &BlockAttributes) -> Option<(usize, ToolAttributes)> {
+    let kind = block.tool?;
+
+    inventory.iter().enumerate()
+        .filter_map(|(i, item)| item.get_attributes().tool.map(|tool| (i, tool)))
+        .filter(|(_, tool)| tool.kind == kind)
+        .max_by(|(_, a), (_, b)| a.stats.strength.cmp(&b.stats.strength).then(a.stats.speed.total_cmp(&b.stats.speed)))
+}
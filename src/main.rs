@@ -18,6 +18,7 @@ use bevy_asset_loader::prelude::*;
 //use bevy_mod_mipmap_generator::{generate_mipmaps, MipmapGeneratorPlugin, MipmapGeneratorSettings};
 use fastrand::Rng;
 use hotbar::Hotbar;
+use entities::PersistentID;
 use iyes_perf_ui::PerfUiPlugin;
 //use bevy_flycam::PlayerPlugin;
 use leafwing_input_manager::prelude::*;
@@ -36,6 +37,10 @@ use spatial::*;
 mod actions;
 use actions::*;
 
+#[path = "creatures/creatures.rs"]
+mod creatures;
+use creatures::*;
+
 #[path = "inventory/inventory.rs"]
 mod inventory;
 use inventory::*;
@@ -74,11 +79,11 @@ use stats::*;
 mod ui;
 use ui::*;
 
-/*
 #[path = "saveload/saveload.rs"]
 mod saveload;
 use saveload::*;
 
+/*
 #[path = "setup/setup.rs"]
 mod setup;
 use setup::*;
@@ -114,6 +119,7 @@ pub enum PlayingState {
 pub enum GameState {
     #[default] AssetLoading,
     //Setup,
+    WorldSelect,
     Playing,
 }
 
@@ -173,13 +179,14 @@ fn main () {
     .add_systems(Update, generate_mipmaps::<StandardMaterial>)
       */
     .add_plugins(WireframePlugin)
+    .add_plugins(MaterialPlugin::<TiledAtlasMaterial>::default())
     .insert_resource(Msaa::Off)
     .insert_resource(ClearColor(Color::Rgba { red: 129.0/256.0, green: 194.0/256.0, blue: 247.0/256.0, alpha: 1.0 }))
 
     .init_state::<GameState>()
         .add_loading_state(
             LoadingState::new(GameState::AssetLoading)
-                .continue_to_state(GameState::Playing)
+                .continue_to_state(GameState::WorldSelect)
                 .load_collection::<Atlas>()
                 .load_collection::<Materials>(),
         )
@@ -209,38 +216,53 @@ fn main () {
 
     .add_plugins(PhysicsPlugin)
     .add_plugins(ActionsPlugin)
+    .add_plugins(InventoryPlugin)
+    .add_plugins(CreaturesPlugin)
     .add_plugins(MapPlugin)
     .add_plugins(StatsPlugin)
     .add_plugins(MechanicsPlugin)
+    .add_plugins(SaveLoadPlugin)
 
     .init_resource::<RNGSeed>()
+    .init_resource::<ChunkMeshTasks>()
 
     
     .add_systems(Startup, setup)
     .add_systems(OnEnter(GameState::Playing), setup_ui)
 
     .add_systems(OnEnter(GameState::Playing), modify_materials)
+    .add_systems(Update, (world_time::advance_world_time, update_sky).chain().run_if(in_state(GameState::Playing)))
     .add_systems(PostUpdate, update_water_material.run_if(in_state(GameState::Playing)).after(TransformPropagate).before(RenderSet::PrepareAssets))
 
+    .add_systems(OnEnter(GameState::WorldSelect), world_select::setup_world_select)
+    .add_systems(OnExit(GameState::WorldSelect), world_select::cleanup_world_select)
+    .add_systems(Update, (world_select::world_select_name_input, world_select::world_select_buttons, world_select::update_world_select).chain().run_if(in_state(GameState::WorldSelect)))
+    .add_systems(Update, saveload::exit_on_close_requested.run_if(not(in_state(GameState::Playing))))
+
     .add_systems(Update, map::update_chunk_positions)
     .add_systems(Update, map::update_chunk_loaders)
-    .add_systems(Update, map::generate_trees.before(generate_chunks))
-    .add_systems(Update, map::generate_chunks)
+    .add_systems(Update, trees::generate_trees.before(generate_chunks).run_if(in_state(GameState::Playing)))
+    .add_systems(Update, structures::generate_structures.before(generate_chunks).run_if(in_state(GameState::Playing)))
+    .add_systems(Update, map::generate_chunks.run_if(in_state(GameState::Playing)))
     //.add_systems(Update, map::read_modification_events)
     // TODO: Chained just for exit/save reasons. We should add a state for exiting and saving (and also a state for pausing!)
     .add_systems(
         Update,
         (
             map::unload_chunks,
-            map::save_chunks,
+            autosave::save_on_exit,
         )
-            .chain(),
+            .chain()
+            .run_if(in_state(GameState::Playing)),
     )
 
     .add_systems(Update, rendering::update_chunk_meshes.run_if(in_state(GameState::Playing)))
     .add_systems(Update, move_to_spawn.run_if(in_state(GameState::Playing)))
     .add_systems(Update, mining)
+    .add_systems(Update, attack)
     .add_systems(Update, damage_block)
+    .add_systems(Update, felling::fell_trees.after(damage_block).run_if(in_state(GameState::Playing)))
+    .add_systems(Update, interact.before(building))
     .add_systems(Update, building)
     .add_systems(Update, place_block)
     .add_systems(Update, process_block_updates)
@@ -258,7 +280,7 @@ fn main () {
     //        .chain(),
     //)
 
-    .add_systems(Update, player_input_game)
+    .add_systems(Update, player_input_game.run_if(in_state(GameState::Playing)))
     
     .add_systems(
         Update,
@@ -269,11 +291,14 @@ fn main () {
             apply_gravity,
             do_physics,
         )
-            .chain(),
+            .chain()
+            .run_if(in_state(GameState::Playing)),
     )
 
     .add_systems(Update, update_resource_counts.run_if(in_state(GameState::Playing)))
     .add_systems(Update, update_breath_ui.run_if(in_state(GameState::Playing)))
+    .add_systems(Update, (crate_slot_buttons, update_crate_ui).chain().run_if(in_state(GameState::Playing)))
+    .add_systems(Update, (recipe_buttons, update_crafting_ui).chain().run_if(in_state(GameState::Playing)))
     .add_systems(Update, update_progress_bar)
     .add_systems(Update, fit_canvas)
      
@@ -350,15 +375,15 @@ enum Action {
     MoveLeft, MoveRight,
     Crouch, Jump,
     Look, Primary, Secondary,
-    MenuBack,
+    MenuBack, Crafting,
     Slot1, Slot2, Slot3, Slot4, Slot5, Slot6, Slot7, Slot8, Slot9, Slot0
 }
 
-const INPUT_MAP: [(Action, InputKind); 20] = [(Action::MoveForward, InputKind::PhysicalKey(KeyCode::KeyW)), (Action::MoveBackward, InputKind::PhysicalKey(KeyCode::KeyS)),
+const INPUT_MAP: [(Action, InputKind); 21] = [(Action::MoveForward, InputKind::PhysicalKey(KeyCode::KeyW)), (Action::MoveBackward, InputKind::PhysicalKey(KeyCode::KeyS)),
                                             (Action::MoveLeft, InputKind::PhysicalKey(KeyCode::KeyA)), (Action::MoveRight, InputKind::PhysicalKey(KeyCode::KeyD)),
                                             (Action::Crouch, InputKind::PhysicalKey(KeyCode::ShiftLeft)), (Action::Jump, InputKind::PhysicalKey(KeyCode::Space)),
                                             (Action::Look, InputKind::DualAxis(DualAxis::mouse_motion())), (Action::Primary, InputKind::Mouse(MouseButton::Left)), (Action::Secondary, InputKind::Mouse(MouseButton::Right)),
-                                            (Action::MenuBack, InputKind::PhysicalKey(KeyCode::Escape)),
+                                            (Action::MenuBack, InputKind::PhysicalKey(KeyCode::Escape)), (Action::Crafting, InputKind::PhysicalKey(KeyCode::KeyE)),
 
                                             (Action::Slot1, InputKind::PhysicalKey(KeyCode::Digit1)), (Action::Slot2, InputKind::PhysicalKey(KeyCode::Digit2)), (Action::Slot3, InputKind::PhysicalKey(KeyCode::Digit3)), 
                                             (Action::Slot4, InputKind::PhysicalKey(KeyCode::Digit4)), (Action::Slot5, InputKind::PhysicalKey(KeyCode::Digit5)), (Action::Slot6, InputKind::PhysicalKey(KeyCode::Digit6)), 
@@ -473,6 +498,7 @@ pub fn setup (
         LinearVelocity::default(),
         Gravity(14.0),
         Player,
+        PersistentID::PLAYER,
         MoveToSpawn,
         InputManagerBundle::<Action> {
             // Stores "which actions are currently pressed"
@@ -0,0 +1,126 @@
+use std::fmt::Display;
+
+use bevy::{math::DVec2, prelude::*};
+use noise::{NoiseFn, Perlin, ScalePoint};
+
+use crate::BlockID;
+
+/// How much neighbouring biomes bleed into each other, in climate space. Bigger means wider, softer edges.
+const BLEND_SPREAD: f64 = 0.12;
+/// How much the climate wobbles from block to block, so the surface at biome edges is ragged instead of a clean line.
+const EDGE_JITTER: f64 = 0.03;
+
+// Resources
+/// Picks a biome for every column of the world from temperature and humidity noise.
+/// Inserted by [`generate_chunks`](crate::generate_chunks) along with the terrain generator it belongs to.
+#[derive(Clone, Resource)]
+pub struct BiomeMap {
+    temperature: ScalePoint<Perlin>,
+    humidity: ScalePoint<Perlin>,
+    jitter: ScalePoint<Perlin>,
+}
+impl BiomeMap {
+    pub fn new(seed: u32) -> Self {
+        Self {
+            temperature: ScalePoint::new(Perlin::new(seed + 10)).set_scale(0.004),
+            humidity: ScalePoint::new(Perlin::new(seed + 11)).set_scale(0.005),
+            jitter: ScalePoint::new(Perlin::new(seed + 12)).set_scale(0.5),
+        }
+    }
+
+    /// Temperature and humidity of the column at `x`, `z`. Both are roughly between -1 and 1.
+    pub fn climate(&self, x: f64, z: f64) -> DVec2 {
+        DVec2::new(self.temperature.get([x, z]), self.humidity.get([x, z]))
+    }
+
+    /// The biome the column containing `position` belongs to.
+    pub fn biome_at(&self, position: IVec3) -> Biome {
+        self.sample(position.x as f64, position.z as f64).biome
+    }
+
+    /// Everything terrain generation needs to know about the column at `x`, `z`.
+    /// Shape and vegetation are blended between every biome by how close the climate is to theirs, the blocks come from whichever is closest.
+    pub fn sample(&self, x: f64, z: f64) -> BiomeSample {
+        let climate = self.climate(x, z);
+        let jittered = climate + DVec2::splat(self.jitter.get([x, z]) * EDGE_JITTER);
+
+        let mut total_weight = 0.0;
+        let mut amplitude = 0.0;
+        let mut tree_threshold = 0.0;
+        for biome in Biome::ALL {
+            let attributes = biome.get_attributes();
+            let weight = (-attributes.climate.distance_squared(climate) / BLEND_SPREAD.powi(2)).exp();
+            total_weight += weight;
+            amplitude += attributes.amplitude * weight;
+            tree_threshold += attributes.tree_threshold * weight;
+        }
+
+        let biome = Biome::ALL.into_iter()
+            .min_by(|a, b| a.get_attributes().climate.distance_squared(jittered).total_cmp(&b.get_attributes().climate.distance_squared(jittered)))
+            .unwrap();
+
+        // Far away from every biome the weights can all round down to nothing, so just go with the closest one.
+        if total_weight <= f64::EPSILON {
+            let attributes = biome.get_attributes();
+            return BiomeSample { biome, amplitude: attributes.amplitude, tree_threshold: attributes.tree_threshold }
+        }
+
+        BiomeSample { biome, amplitude: amplitude / total_weight, tree_threshold: tree_threshold / total_weight }
+    }
+}
+
+// Data
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Biome {
+    Plains,
+    Forest,
+    Desert,
+    Tundra,
+    Swamp,
+}
+impl Biome {
+    pub const ALL: [Biome; 5] = [Biome::Plains, Biome::Forest, Biome::Desert, Biome::Tundra, Biome::Swamp];
+
+    pub fn get_attributes(self) -> BiomeAttributes {
+        match self {
+            Biome::Plains => BiomeAttributes { climate: DVec2::new(0.0, -0.05), surface: BlockID::Grass, filler: BlockID::Dirt, tree_threshold: 0.88, amplitude: 0.7 },
+            Biome::Forest => BiomeAttributes { climate: DVec2::new(0.0, 0.25), surface: BlockID::Grass, filler: BlockID::Dirt, tree_threshold: 0.72, amplitude: 1.0 },
+            Biome::Desert => BiomeAttributes { climate: DVec2::new(0.35, -0.3), surface: BlockID::Sand, filler: BlockID::Sand, tree_threshold: 2.0, amplitude: 0.5 },
+            Biome::Tundra => BiomeAttributes { climate: DVec2::new(-0.35, 0.0), surface: BlockID::Snow, filler: BlockID::Dirt, tree_threshold: 0.9, amplitude: 1.4 },
+            Biome::Swamp => BiomeAttributes { climate: DVec2::new(0.3, 0.35), surface: BlockID::Grass, filler: BlockID::Dirt, tree_threshold: 0.8, amplitude: 0.3 },
+        }
+    }
+}
+impl Display for Biome {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Biome::Plains => write!(f, "Plains"),
+            Biome::Forest => write!(f, "Forest"),
+            Biome::Desert => write!(f, "Desert"),
+            Biome::Tundra => write!(f, "Tundra"),
+            Biome::Swamp => write!(f, "Swamp"),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct BiomeAttributes {
+    /// Temperature and humidity the biome is at its most biome-y.
+    pub climate: DVec2,
+    /// The top block of the ground.
+    pub surface: BlockID,
+    /// What's under the surface, down until the stone starts.
+    pub filler: BlockID,
+    /// Tree noise has to be above this for a tree to grow. Anything over 1 means no trees at all.
+    pub tree_threshold: f64,
+    /// How hilly the terrain gets. 1 is the old, biome-less terrain.
+    pub amplitude: f64,
+}
+
+/// What a column of the world looks like, with the edges of biomes blended together.
+#[derive(Clone, Copy, Debug)]
+pub struct BiomeSample {
+    pub biome: Biome,
+    pub amplitude: f64,
+    pub tree_threshold: f64,
+}
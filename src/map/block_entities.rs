@@ -0,0 +1,45 @@
+use std::io;
+
+use bevy::{prelude::*, utils::HashMap};
+use serde::{Deserialize, Serialize};
+
+use crate::{BlockID, Inventory};
+
+// Data
+/// Extra data for a block that needs more than an id and damage to describe it.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum BlockEntity {
+    Crate(Inventory),
+}
+impl BlockEntity {
+    /// What a freshly placed block of this kind starts out with, if it needs anything at all.
+    pub fn new(id: BlockID) -> Option<BlockEntity> {
+        match id {
+            BlockID::Crate => Some(BlockEntity::Crate(Inventory::default())),
+            _ => None,
+        }
+    }
+
+    /// Everything that should come out of the block when it's broken.
+    pub fn into_contents(self) -> Inventory {
+        match self {
+            BlockEntity::Crate(inventory) => inventory,
+        }
+    }
+}
+
+/// Block entities of a single chunk, keyed by their position within it.
+#[derive(Default, Clone, Debug, Deref, DerefMut)]
+pub struct BlockEntities(HashMap<IVec3, BlockEntity>);
+impl BlockEntities {
+    /// Most chunks don't have any block entities, and the ones that do only have a few, so they're just written as RON.
+    pub fn encode(&self) -> io::Result<String> {
+        let entries = self.iter().map(|(position, block_entity)| (position.to_array(), block_entity)).collect::<Vec<_>>();
+        ron::to_string(&entries).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+    }
+
+    pub fn decode(data: &str) -> io::Result<BlockEntities> {
+        let entries: Vec<([i32; 3], BlockEntity)> = ron::from_str(data).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        Ok(BlockEntities(entries.into_iter().map(|(position, block_entity)| (IVec3::from_array(position), block_entity)).collect()))
+    }
+}
@@ -0,0 +1,188 @@
+use std::{fmt::Display, fs, ops::Index};
+
+use bevy::{prelude::*, utils::HashMap};
+use serde::Deserialize;
+
+use crate::{tools::ToolKind, BlockAttributes, BlockID, BlockVisibility, Item, Slip, Solidity, TextureCoords};
+
+pub const BLOCK_REGISTRY_PATH: &str = "assets/blocks.ron";
+
+/// Every block the game knows about, indexed by [`BlockID`].
+/// Loaded from [`BLOCK_REGISTRY_PATH`] when the [`MapPlugin`](crate::MapPlugin) is built.
+#[derive(Clone, Resource)]
+pub struct BlockRegistry {
+    attributes: Vec<BlockAttributes>,
+    names: Vec<String>,
+    ids: HashMap<String, BlockID>,
+}
+impl BlockRegistry {
+    pub fn load(path: &str) -> Result<BlockRegistry, BlockRegistryFault> {
+        let text = fs::read_to_string(path).map_err(|err| BlockRegistryFault::Io(err.to_string()))?;
+        let file: BlockDefinitionFile = ron::from_str(&text).map_err(|err| BlockRegistryFault::Parse(err.to_string()))?;
+        BlockRegistry::from_definitions(file.blocks)
+    }
+
+    pub fn from_definitions(definitions: Vec<BlockDefinition>) -> Result<BlockRegistry, BlockRegistryFault> {
+        let mut ids = HashMap::<String, BlockID>::new();
+        let max_id = definitions.iter().map(|definition| definition.id).max().unwrap_or(0);
+
+        for definition in definitions.iter() {
+            if ids.values().any(|id| **id == definition.id) {
+                return Err(BlockRegistryFault::DuplicateID(definition.id));
+            }
+            if ids.insert(definition.name.clone(), BlockID(definition.id)).is_some() {
+                return Err(BlockRegistryFault::DuplicateName(definition.name.clone()));
+            }
+        }
+
+        // Gaps in the ID space are filled with air-like blocks so that indexing never goes out of bounds.
+        let mut attributes = vec![BlockAttributes { solidity: Solidity::NonSolid, visibility: BlockVisibility::Invisible, ..default() }; max_id as usize + 1];
+        let mut names = vec![String::new(); max_id as usize + 1];
+
+        for definition in definitions {
+            let breaks_into = match &definition.breaks_into {
+                Some(name) => *ids.get(name).ok_or(BlockRegistryFault::UnknownBlock(name.clone()))?,
+                None => BlockID::Air,
+            };
+
+            if definition.cost_to_build.len() > 3 {
+                return Err(BlockRegistryFault::TooManyCosts(definition.name));
+            }
+            let mut cost_to_build = [None; 3];
+            for (i, cost) in definition.cost_to_build.iter().enumerate() {
+                cost_to_build[i] = Some(*cost);
+            }
+
+            attributes[definition.id as usize] = BlockAttributes {
+                health: definition.health,
+                toughness: definition.toughness,
+                tool: definition.tool,
+                tex_coords: definition.textures.into(),
+                breaks_into,
+                give_on_damage: definition.give_on_damage,
+                cost_to_build,
+                solidity: definition.solidity,
+                slip: definition.slip.map(|(x, y, z)| Slip(Vec3::new(x, y, z))).unwrap_or_default(),
+                visibility: definition.visibility,
+                light: definition.light,
+            };
+            names[definition.id as usize] = definition.name;
+        }
+
+        // Code refers to some blocks directly, so make sure the file agrees with it.
+        for (name, id) in BlockID::BUILTIN {
+            if ids.get(*name) != Some(id) {
+                return Err(BlockRegistryFault::BuiltinMismatch(name.to_string(), **id));
+            }
+        }
+
+        Ok(BlockRegistry { attributes, names, ids })
+    }
+
+    pub fn get(&self, id: BlockID) -> &BlockAttributes {
+        &self.attributes[*id as usize]
+    }
+
+    /// Looks up a block by the name given to it in the registry file.
+    pub fn id(&self, name: &str) -> Option<BlockID> {
+        self.ids.get(name).copied()
+    }
+
+    pub fn name(&self, id: BlockID) -> &str {
+        &self.names[*id as usize]
+    }
+
+    pub fn contains(&self, id: BlockID) -> bool {
+        (*id as usize) < self.names.len() && !self.names[*id as usize].is_empty()
+    }
+
+    /// An iterator over every registered block.
+    pub fn iter(&self) -> impl Iterator<Item = (BlockID, &BlockAttributes)> {
+        self.attributes.iter().enumerate().filter(|(i, _)| !self.names[*i].is_empty()).map(|(i, attributes)| (BlockID(i as u8), attributes))
+    }
+}
+impl Index<BlockID> for BlockRegistry {
+    type Output = BlockAttributes;
+
+    fn index(&self, id: BlockID) -> &Self::Output {
+        self.get(id)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum BlockRegistryFault {
+    Io(String),
+    Parse(String),
+    DuplicateID(u8),
+    DuplicateName(String),
+    UnknownBlock(String),
+    TooManyCosts(String),
+    BuiltinMismatch(String, u8),
+}
+impl Display for BlockRegistryFault {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BlockRegistryFault::Io(err) => write!(f, "could not read block registry: {}", err),
+            BlockRegistryFault::Parse(err) => write!(f, "could not parse block registry: {}", err),
+            BlockRegistryFault::DuplicateID(id) => write!(f, "block id {} is defined more than once", id),
+            BlockRegistryFault::DuplicateName(name) => write!(f, "block \"{}\" is defined more than once", name),
+            BlockRegistryFault::UnknownBlock(name) => write!(f, "block \"{}\" is referenced but never defined", name),
+            BlockRegistryFault::TooManyCosts(name) => write!(f, "block \"{}\" has more than 3 build costs", name),
+            BlockRegistryFault::BuiltinMismatch(name, id) => write!(f, "block \"{}\" must be defined with id {}", name, id),
+        }
+    }
+}
+
+// File format
+#[derive(Deserialize)]
+pub struct BlockDefinitionFile {
+    pub blocks: Vec<BlockDefinition>,
+}
+
+#[derive(Deserialize)]
+pub struct BlockDefinition {
+    pub id: u8,
+    pub name: String,
+    #[serde(default)]
+    pub health: u8,
+    #[serde(default)]
+    pub toughness: u8,
+    #[serde(default)]
+    pub tool: Option<ToolKind>,
+    #[serde(default)]
+    pub textures: TextureDefinition,
+    #[serde(default)]
+    pub breaks_into: Option<String>,
+    #[serde(default)]
+    pub give_on_damage: Option<Item>,
+    #[serde(default)]
+    pub cost_to_build: Vec<Item>,
+    #[serde(default)]
+    pub solidity: Solidity,
+    #[serde(default)]
+    pub slip: Option<(f32, f32, f32)>,
+    #[serde(default)]
+    pub visibility: BlockVisibility,
+    #[serde(default)]
+    pub light: u8,
+}
+
+/// Mirrors the constructors on [`TextureCoords`].
+#[derive(Default, Deserialize)]
+pub enum TextureDefinition {
+    #[default] None,
+    Symmetrical((i32, i32)),
+    AsymmetricY { top: (i32, i32), bottom: (i32, i32), sides: (i32, i32) },
+    UniqueTop { top: (i32, i32), sides: (i32, i32) },
+}
+impl From<TextureDefinition> for TextureCoords {
+    fn from(definition: TextureDefinition) -> Self {
+        let coord = |(x, y): (i32, i32)| IVec2::new(x, y);
+        match definition {
+            TextureDefinition::None => TextureCoords::default(),
+            TextureDefinition::Symmetrical(c) => TextureCoords::symmetrical(coord(c)),
+            TextureDefinition::AsymmetricY { top, bottom, sides } => TextureCoords::asymmetric_y(coord(top), coord(bottom), coord(sides)),
+            TextureDefinition::UniqueTop { top, sides } => TextureCoords::unique_top(coord(top), coord(sides)),
+        }
+    }
+}
@@ -0,0 +1,251 @@
+use std::collections::VecDeque;
+
+use bevy::{prelude::*, utils::HashSet};
+use itertools::iproduct;
+
+use crate::{block_pos_from_global, chunk_pos_from_global, directions::{DIR_6, DOWN}, point::GridPoint, update_chunk_events_from_global, blocks::BlockRegistry, Block, BlockAttributes, BlockVisibility, ChunkMap, UpdateChunkEvent, CHUNK_SIZE, WORLD_HEIGHT};
+
+pub const MAX_LIGHT: u8 = 15;
+/// How bright a face with no light at all is drawn, so that caves aren't pitch black.
+const MIN_BRIGHTNESS: f32 = 0.05;
+
+// Data
+/// Light level of a voxel. Sky light is kept in the high four bits and block light in the low four.
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Light(u8);
+impl Light {
+    pub fn sky(self) -> u8 {
+        self.0 >> 4
+    }
+
+    pub fn block(self) -> u8 {
+        self.0 & 0x0F
+    }
+
+    /// Whichever kind of light is brighter, which is what gets drawn.
+    pub fn level(self) -> u8 {
+        self.sky().max(self.block())
+    }
+
+    fn get(self, channel: LightChannel) -> u8 {
+        match channel {
+            LightChannel::Sky => self.sky(),
+            LightChannel::Block => self.block(),
+        }
+    }
+
+    fn set(&mut self, channel: LightChannel, level: u8) {
+        match channel {
+            LightChannel::Sky => self.0 = (self.0 & 0x0F) | (level << 4),
+            LightChannel::Block => self.0 = (self.0 & 0xF0) | level,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum LightChannel {
+    Sky,
+    Block,
+}
+
+// Helpers
+/// Lights a chunk that was just put in the [`ChunkMap`], pulling in light from its neighbors and spreading its own light out to them.
+/// Returns the chunks that need to be remeshed.
+pub fn light_new_chunk (chunk_pos: IVec3, chunk_map: &mut ChunkMap, block_registry: &BlockRegistry) -> Vec<UpdateChunkEvent> {
+    let mut changed = HashSet::new();
+    let offset = chunk_pos * CHUNK_SIZE;
+
+    for channel in [LightChannel::Sky, LightChannel::Block] {
+        let mut queue = VecDeque::new();
+
+        for (x, y, z) in iproduct!(0..CHUNK_SIZE, 0..CHUNK_SIZE, 0..CHUNK_SIZE) {
+            let position = offset + IVec3::new(x, y, z);
+            let source = source_level(position, channel, chunk_map, block_registry);
+            if source > 0 {
+                set_light(position, channel, source, chunk_map);
+                changed.insert(position);
+                queue.push_back(position);
+            }
+        }
+
+        // Whatever is lit along our border in the neighboring chunks spreads into us.
+        for (x, y, z) in iproduct!(-1..=CHUNK_SIZE, -1..=CHUNK_SIZE, -1..=CHUNK_SIZE) {
+            let local_position = IVec3::new(x, y, z);
+            let outside_axes = (0..3).filter(|i| local_position[*i] < 0 || local_position[*i] >= CHUNK_SIZE).count();
+            if outside_axes != 1 {
+                continue
+            }
+            let position = offset + local_position;
+            if light_at(position, chunk_map).map_or(0, |light| light.get(channel)) > 0 {
+                queue.push_back(position);
+            }
+        }
+
+        propagate(queue, channel, chunk_map, block_registry, &mut changed);
+    }
+
+    // The top of the chunk below us was lit as if it were under open sky while we weren't loaded. That's not true anymore wherever we block it.
+    let below_top = (offset + IVec3::new(0, -1, 0)).y;
+    let covered = iproduct!(0..CHUNK_SIZE, 0..CHUNK_SIZE)
+        .map(|(x, z)| IVec3::new(offset.x + x, below_top, offset.z + z))
+        .filter(|position| {
+            light_at(*position, chunk_map).map_or(false, |light| light.sky() == MAX_LIGHT)
+                && light_at(position.up(1), chunk_map).map_or(false, |light| light.sky() != MAX_LIGHT)
+        })
+        .collect::<Vec<_>>();
+    if !covered.is_empty() {
+        relight_into(&covered, chunk_map, block_registry, &mut changed);
+    }
+
+    chunk_events(changed)
+}
+
+/// Updates light around blocks that have been changed. Call after changing them.
+/// Returns the chunks that need to be remeshed.
+pub fn relight (positions: &[IVec3], chunk_map: &mut ChunkMap, block_registry: &BlockRegistry) -> Vec<UpdateChunkEvent> {
+    let mut changed = HashSet::new();
+    relight_into(positions, chunk_map, block_registry, &mut changed);
+    chunk_events(changed)
+}
+
+/// How bright something lit at `level` is drawn.
+pub fn light_brightness (level: u8) -> f32 {
+    MIN_BRIGHTNESS + (1.0 - MIN_BRIGHTNESS) * 0.8_f32.powi((MAX_LIGHT - level.min(MAX_LIGHT)) as i32)
+}
+
+/// Darkens everything that might have been lit by `positions`, then lights it back up from whatever sources are left.
+fn relight_into (positions: &[IVec3], chunk_map: &mut ChunkMap, block_registry: &BlockRegistry, changed: &mut HashSet<IVec3>) {
+    for channel in [LightChannel::Sky, LightChannel::Block] {
+        let mut removal_queue = VecDeque::new();
+        let mut removed = Vec::new();
+        for position in positions {
+            if let Some(light) = light_at(*position, chunk_map) {
+                set_light(*position, channel, 0, chunk_map);
+                changed.insert(*position);
+                removal_queue.push_back((*position, light.get(channel)));
+                removed.push(*position);
+            }
+        }
+
+        // Anything lit less than its neighbor could only have gotten its light from it. Anything else is lit some other way, and gets to spread back in.
+        let mut refill_queue = VecDeque::new();
+        while let Some((position, level)) = removal_queue.pop_front() {
+            for &direction in DIR_6 {
+                let adj = position + direction;
+                let Some(adj_level) = light_at(adj, chunk_map).map(|light| light.get(channel)) else {
+                    continue
+                };
+                if adj_level == 0 {
+                    continue
+                }
+
+                let sky_column = channel == LightChannel::Sky && direction == DOWN && level == MAX_LIGHT && adj_level == MAX_LIGHT;
+                if adj_level < level || sky_column {
+                    set_light(adj, channel, 0, chunk_map);
+                    changed.insert(adj);
+                    removal_queue.push_back((adj, adj_level));
+                    removed.push(adj);
+                }
+                else {
+                    refill_queue.push_back(adj);
+                }
+            }
+        }
+
+        for position in removed {
+            let source = source_level(position, channel, chunk_map, block_registry);
+            if source > light_at(position, chunk_map).map_or(0, |light| light.get(channel)) {
+                set_light(position, channel, source, chunk_map);
+                refill_queue.push_back(position);
+            }
+        }
+
+        propagate(refill_queue, channel, chunk_map, block_registry, changed);
+    }
+}
+
+/// Spreads light outward from every position in the queue, through any loaded chunk.
+fn propagate (mut queue: VecDeque<IVec3>, channel: LightChannel, chunk_map: &mut ChunkMap, block_registry: &BlockRegistry, changed: &mut HashSet<IVec3>) {
+    while let Some(position) = queue.pop_front() {
+        let Some(level) = light_at(position, chunk_map).map(|light| light.get(channel)) else {
+            continue
+        };
+        if level <= 1 {
+            continue
+        }
+
+        for &direction in DIR_6 {
+            let adj = position + direction;
+            let Some(block) = block_at(adj, chunk_map) else {
+                continue
+            };
+            let Some(cost) = light_cost(&block_registry[block.id]) else {
+                continue
+            };
+
+            // Sunlight goes straight down through open air without fading.
+            let adj_level = if channel == LightChannel::Sky && direction == DOWN && level == MAX_LIGHT && cost == 1 {
+                MAX_LIGHT
+            }
+            else {
+                level.saturating_sub(cost)
+            };
+
+            if adj_level > light_at(adj, chunk_map).map_or(0, |light| light.get(channel)) {
+                set_light(adj, channel, adj_level, chunk_map);
+                changed.insert(adj);
+                queue.push_back(adj);
+            }
+        }
+    }
+}
+
+/// How much light a position gives off by itself.
+fn source_level (position: IVec3, channel: LightChannel, chunk_map: &ChunkMap, block_registry: &BlockRegistry) -> u8 {
+    let Some(block) = block_at(position, chunk_map) else {
+        return 0
+    };
+    let attributes = &block_registry[block.id];
+
+    match channel {
+        LightChannel::Block => attributes.light.min(MAX_LIGHT),
+        // Anything open to the top of the world is in sunlight. Until the chunk above is loaded, we don't know any better, so we assume it's open too.
+        LightChannel::Sky => {
+            let above = position.up(1);
+            let open = chunk_pos_from_global(above).y > WORLD_HEIGHT || block_at(above, chunk_map).is_none();
+            if open && light_cost(attributes).is_some() { MAX_LIGHT } else { 0 }
+        },
+    }
+}
+
+/// How much light fades by passing into a block, or `None` if it doesn't let any through.
+fn light_cost (attributes: &BlockAttributes) -> Option<u8> {
+    match attributes.visibility {
+        BlockVisibility::Opaque => None,
+        BlockVisibility::Invisible => Some(1),
+        BlockVisibility::Liquid | BlockVisibility::Translucent => Some(2),
+    }
+}
+
+fn block_at (position: IVec3, chunk_map: &ChunkMap) -> Option<Block> {
+    chunk_map.get(&chunk_pos_from_global(position)).map(|chunk| chunk.blocks[block_pos_from_global(position)])
+}
+
+fn light_at (position: IVec3, chunk_map: &ChunkMap) -> Option<Light> {
+    chunk_map.get(&chunk_pos_from_global(position)).map(|chunk| chunk.light[block_pos_from_global(position)])
+}
+
+fn set_light (position: IVec3, channel: LightChannel, level: u8, chunk_map: &mut ChunkMap) {
+    if let Some(chunk) = chunk_map.get_mut(&chunk_pos_from_global(position)) {
+        chunk.light[block_pos_from_global(position)].set(channel, level);
+    }
+}
+
+/// Every chunk that has to be remeshed to show light changes at `changed`, including neighbors that mesh against them.
+fn chunk_events (changed: HashSet<IVec3>) -> Vec<UpdateChunkEvent> {
+    let mut chunks = HashSet::new();
+    for position in changed {
+        chunks.extend(update_chunk_events_from_global(position).into_iter().map(|ev| *ev));
+    }
+    chunks.into_iter().map(UpdateChunkEvent).collect()
+}
@@ -1,5 +1,5 @@
-use std::{collections::VecDeque, fs::File, io::{BufWriter, Read, Write}, ops::{Range, RangeBounds}, time::Instant};
-use bevy::{app::AppExit, ecs::event::ManualEventReader, math::{DVec3, Vec3A}, prelude::*, render::{self, render_resource::ShaderType}, tasks::{ComputeTaskPool, ParallelSliceMut}, time::Stopwatch, utils::{petgraph::data, HashMap, HashSet}, window::WindowCloseRequested};
+use std::{collections::VecDeque, fs::File, io::{self, BufWriter, Read, Write}, ops::{Range, RangeBounds}, path::Path, sync::Arc, time::{Duration, Instant}};
+use bevy::{ecs::event::ManualEventReader, math::{DVec3, Vec3A}, prelude::*, render::{self, render_resource::ShaderType}, tasks::{block_on, futures_lite::future, AsyncComputeTaskPool, ComputeTaskPool, ParallelSliceMut, Task}, time::Stopwatch, utils::{petgraph::data, HashMap, HashSet}, window::WindowCloseRequested};
 use fastrand::{Rng, choice};
 use flate2::{bufread::{DeflateDecoder, GzDecoder}, write::{DeflateEncoder, GzEncoder, ZlibEncoder}, Compression};
 use indexmap::{IndexMap, IndexSet};
@@ -9,13 +9,37 @@ use noise::{core::worley::{distance_functions::{self, euclidean, euclidean_squar
 //use rand::{seq::SliceRandom, thread_rng};
 use derive_more::{Add, AddAssign, Sub, SubAssign, Mul, MulAssign, Div, DivAssign, };
 use bevy::{ecs::{entity::{EntityMapper, MapEntities}, reflect::ReflectMapEntities}, prelude::*};
-use rusqlite::{params, Connection};
-use crate::{directions::{DIR_6, DIR_6_NO_DOWN}, grid3::Grid3, point::GridPoint, Item, ItemID, MoveToSpawn, RNGSeed, Slip, CHUNK_SIZE, WORLD_DEPTH, WORLD_HEIGHT, WORLD_SIZE};
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use crate::{decode_chunk, decode_pending_modifications, encode_pending_modifications, SaveFault, PENDING_FORMAT_VERSION, directions::{DIR_6, DIR_6_NO_DOWN}, encode_chunk, grid3::Grid3, point::GridPoint, Item, ItemID, tools::ToolKind, MoveToSpawn, RNGSeed, CurrentWorld, SavePalette, WorldDatabase, Slip, CHUNK_FORMAT_VERSION, CHUNK_SIZE, WORLD_DEPTH, WORLD_HEIGHT, WORLD_SIZE};
 
 use crate::sparse_grid3::SparseGrid3;
 
+pub mod biomes;
+pub mod block_entities;
+pub mod blocks;
+pub mod light;
+pub mod structures;
+pub mod terrain;
+pub mod trees;
+pub mod water;
+pub mod world_time;
+use biomes::*;
+use block_entities::*;
+use blocks::*;
+use light::*;
+use structures::*;
+use terrain::*;
+use trees::*;
+use water::*;
+use world_time::*;
+
 
 const SEA_LEVEL: f64 = -0.0;
+/// How many chunks can be loading at once.
+pub const MAX_CHUNK_LOADING_TASKS: usize = 32;
+/// How many chunks worth of pending modifications are kept in memory before they spill into the database.
+pub const MAX_PENDING_CHUNKS: usize = 256;
 
 
 
@@ -25,19 +49,26 @@ pub struct MapPlugin;
 
 impl Plugin for MapPlugin {
     fn build(&self, app: &mut App) {
+        let block_registry = BlockRegistry::load(BLOCK_REGISTRY_PATH).unwrap_or_else(|fault| panic!("Failed to load block registry: {}", fault));
+        let structure_registry = StructureRegistry::load(STRUCTURES_PATH, &block_registry).unwrap_or_else(|fault| panic!("Failed to load structures: {}", fault));
+
         app
+            .insert_resource(block_registry)
+            .insert_resource(structure_registry)
             .init_resource::<ChunkMap>()
             .init_resource::<PendingModificationMap>()
             .init_resource::<ChunkLoadingQueue>()
             .init_resource::<ChunkUnloadingQueue>()
             .init_resource::<ChunkSavingQueue>()
+            .init_resource::<ChunkLoadingTasks>()
             .init_resource::<ChunkStatusMap>()
-            .init_resource::<FunnyMapConsts>()
+            .init_resource::<WorldTime>()
             .add_event::<BlockUpdateEvent>()
             .add_event::<LoadChunkEvent>()
             .add_event::<LoadReasonChangeEvent>()
             .add_event::<UpdateChunkEvent>()
-            .add_event::<GenerateTreeEvent>();
+            .add_event::<GenerateTreeEvent>()
+            .add_event::<GenerateStructureEvent>();
     }
 }
  
@@ -52,34 +83,33 @@ pub fn generate_chunks (
 
     mut evr_load_chunk: EventReader<LoadChunkEvent>,
     mut evw_gen_tree: EventWriter<GenerateTreeEvent>,
+    mut evw_gen_structure: EventWriter<GenerateStructureEvent>,
     mut evw_update_chunk: EventWriter<UpdateChunkEvent>,
 
     mut loader_query: Query<(&mut ChunkLoader)>,
 
     partial_save_map: Res<ChunkSavingQueue>,
+    palette: Res<SavePalette>,
+    database: Res<WorldDatabase>,
     mut loading_queue: ResMut<ChunkLoadingQueue>,
+    mut loading_tasks: ResMut<ChunkLoadingTasks>,
     mut chunk_status_map: ResMut<ChunkStatusMap>,
-    funny_map_consts: Res<FunnyMapConsts>,
+    block_registry: Res<BlockRegistry>,
+    structure_registry: Res<StructureRegistry>,
+    mut generator: Local<Option<Arc<TerrainGenerator>>>,
+    /// Chunks that have been generated since the generator was made. Any chunk their trees and structures reach past into got its part the first time.
+    mut generated_before: Local<HashSet<IVec3>>,
     //time: Res<Time>,
 
     //mut next_mapgen_state: ResMut<NextState<MapGenState>>,
 ) {
-
-    let start = Instant::now();
-
-    let gradient = SingleDirectionAxialGradient { values: vec![1.0, 0.0, -0.5], points: vec![-(CHUNK_SIZE) as f64, 0.0, (WORLD_HEIGHT * CHUNK_SIZE) as f64], dimension: 1 };
-
-    let noise_gen = Blend::new(ScalePoint::new(Perlin::new(**seed)).set_scale(0.025), gradient, Constant::new(0.7));
-
-    //let tree_noise = Worley::new(**seed).set_distance_function(euclidean_squared).set_return_type(ReturnType::Distance).set_frequency(0.025 );
-
-    let tree_noise = Blend::new(
-        ScalePoint::new(Perlin::new(**seed + 1)).set_scale(0.001),
-        WhiteNoise{seed: **seed},
-        Constant::new(0.85),
-    );
-
-    //let mut chunks_to_load = Vec::new();
+    if generator.as_ref().map_or(true, |generator| generator.seed != **seed) {
+        let new_generator = TerrainGenerator::new(**seed, structure_registry.placements());
+        commands.insert_resource(new_generator.biomes.clone());
+        *generator = Some(Arc::new(new_generator));
+        generated_before.clear();
+    }
+    let generator = generator.as_ref().unwrap();
 
     //**loading_queue = VecDeque::from_iter(loading_queue.iter().filter_map(|ev| if !chunk_map.contains_key(&ev.chunk) {Some(*ev)} else {None}));
 
@@ -106,302 +136,108 @@ pub fn generate_chunks (
         }
     }
 
-    // Load only a limited amount of chunks each frame to make things smoother.
-    let conn = Connection::open("saves/1.sl3").unwrap();
-    conn.execute("pragma SYNCHRONOUS = NORMAL", []);
-    let mut chunks_loaded = 0;
-    while start.elapsed().as_millis() < 2 {
-        let start_load_chunk = Instant::now();
+    // Reading, decompressing and generating all happen on tasks. Only a limited amount run at once so that closer chunks don't wait behind far away ones.
+    let task_pool = AsyncComputeTaskPool::get();
+    while loading_tasks.len() < MAX_CHUNK_LOADING_TASKS {
         if let Some(ev) = loading_queue.pop_back() {
-            //println!(":3");
-            //println!("loading: {:?}", ev.chunk);
-            //let render_entity = Some(commands.spawn(Transform::from_translation((ev.chunk * CHUNK_SIZE).as_vec3()))
-            //                            .insert(GlobalTransform::default())
-            //                            .id());
-
-            //let water_render_entity = Some(commands.spawn(Transform::from_translation((ev.chunk * CHUNK_SIZE).as_vec3()))
-            //                            .insert(GlobalTransform::default())
-            //                            .id());
-
-            let mut chunk = Chunk { blocks: Grid3::filled(Block::new(BlockID::Air), [CHUNK_SIZE, CHUNK_SIZE, CHUNK_SIZE]),
-                                    load_reasons: HashSet::from([ev.load_reason]),
-                                    render_entity: None, //render_entity,
-                                    water_render_entity: None,
-                                    translucent_render_entity: None,
-                                  };
-            
-            //let start_chunkdata = Instant::now();
-            let potential_compressed_chunk: Option<Vec<u8>> =
-            if let Some(data) = partial_save_map.get(&ev.chunk) {
-                Some(data.clone())
-            }
-            else if let Ok(data) = conn.query_one("SELECT ChunkData FROM Chunks WHERE PosX=?1 AND PosY=?2 AND PosZ=?3", [ev.chunk.x, ev.chunk.y, ev.chunk.z], |row| row.get(0)){
-                Some(data)
-            }
-            else {
-                None
-            };
-            //println!("time to get chunkdata: {:?}", start_chunkdata.elapsed());
-
-            if let Some(compressed_chunk) = potential_compressed_chunk {
-                
-                let mut d = GzDecoder::new(&*compressed_chunk);
-                let mut chunk_data: [u8; CHUNK_SIZE.pow(3) as usize * 2] = [0; CHUNK_SIZE.pow(3) as usize * 2];
-                let result_bytes_read = d.read(&mut chunk_data);
-                match result_bytes_read {
-                    Ok(bytes_read) => {} //println!("bytes read: {}", bytes_read),
-                    Err(err) => {} //println!("update failed: {}", err),
-                }
-                for (i, data) in chunk_data.chunks(2).enumerate() {
-                    chunk.blocks.data[i].id = BlockID::from_u8(data[0]);
-                    chunk.blocks.data[i].damage = data[1];
-                }
-            }
-            else {
-                
-                                  
-                let offset = ev.chunk * CHUNK_SIZE;
-                let mut set_block_val = |position: IVec3, block_val: &mut Block| {
-                    let point = DVec3::from(offset + position);
-                
-                    let noise_val = noise_gen.get([point.x, point.y, point.z]);
-                    if noise_val >= 0.0 {
-                        *block_val = Block::new(BlockID::Dirt);
-                        // Make our block grass instead of dirt if the block above is air.
-                        if noise_gen.get([point.x, point.y + 1.0, point.z]) < 0.0 && point.y > 0.0 {
-                            *block_val = Block::new(BlockID::Grass);
-
-                            // Tree!
-                            if tree_noise.get([point.x, point.z]) > 0.80 {
-                                evw_gen_tree.send(GenerateTreeEvent(IVec3::new(point.x as i32, point.y as i32 + 1, point.z as i32)));
-                                // TODO: Maybe we want to do this in the tree generation system?
-                                *block_val = Block::new(BlockID::Dirt);
-                            }
-                        }
-                        if (noise_gen.get([point.x, point.y + 5.0, point.z]) > 0.0) || point.y < -5.0 {
-                            *block_val = Block::new(BlockID::Stone);
-                        }
-                    }
-                    if point.y < 0.0 && block_val.id == BlockID::Air {
-                        *block_val = Block::new(BlockID::Water)
-                    }
-                };
-
-                let mut all_air = true;
-                let mut all_stone = true;
-                for (i, position) in funny_map_consts.chunk_perimeter_indices.iter() {
-                    set_block_val(*position, &mut chunk.blocks.data[*i]);
-                    if chunk.blocks.data[*i].id != BlockID::Air {
-                        all_air = false;
-                    }
-                    if chunk.blocks.data[*i].id != BlockID::Stone {
-                        all_stone = false
-                    }
-                }
-                /*
-                for (position, block_val) in chunk.blocks.iter_3d_mut() {
-                    // TODO: If this works, lets get some compile-time list we can use to iterate over. that would be much faster. (probably)
-                    if position.max_element() == CHUNK_SIZE - 1 || position.min_element() == 0 {
-                        set_block_val(position, block_val);
-                        if block_val.id != BlockID::Air {
-                            all_air = false;
-                        }
-                        if block_val.id != BlockID::Stone {
-                            all_stone = false
-                        }
-                    }
-                }
-                */
-                if !(all_air || all_stone) {
-                    for (i, position) in funny_map_consts.chunk_volume_indices.iter() {
-                        set_block_val(*position, &mut chunk.blocks.data[*i]);
-                    }
-                }
-
-                if all_stone {
-                    chunk.blocks = Grid3::filled(Block::new(BlockID::Stone), [CHUNK_SIZE, CHUNK_SIZE, CHUNK_SIZE]);
-                }
-
-                if let Some(pending_chunk) = pending_map.get_mut(&ev.chunk) {
-                    for (position, modification) in pending_chunk.iter_3d_mut() {
-                        if !modification.yield_to_terrain || chunk.blocks[position].id == BlockID::Air {
-                            chunk.blocks[position] = modification.block;
-                        }
-                    }
-                }
-            }
-
-
-
-            if let Some(chunk) = chunk_map.get(&ev.chunk) {
-                if let Some(render_entity) = chunk.render_entity {
-                    commands.entity(render_entity).despawn();
-                }
-                if let Some(water_render_entity) = chunk.water_render_entity {
-                    commands.entity(water_render_entity).despawn();
-                }
-            }
-
-            chunk_map.insert(ev.chunk, chunk);
-            let loader_entity = match ev.load_reason {
-                LoadReason::Loader(entity) => entity,
-                LoadReason::Spawning(entity) => entity,
-            };
-            if let Ok(mut loader) = loader_query.get_mut(loader_entity) {
-                loader.load_list.push(ev.chunk);
-            }
-
-            evw_update_chunk.send(UpdateChunkEvent(ev.chunk));
-            for adj in ev.chunk.adj_6() {
-                evw_update_chunk.send(UpdateChunkEvent(adj));
-            }
-
-            chunk_status_map.insert(ev.chunk, ChunkStatus::Active);
-            chunks_loaded += 1;
+            // Chunks waiting to be saved are newer than what's in the database.
+            let queued_data = partial_save_map.get(&ev.chunk).cloned();
+            let database = database.clone();
+            let palette = palette.clone();
+            let generator = generator.clone();
+
+            let task = task_pool.spawn(async move {
+                load_chunk(ev.chunk, queued_data, &database, &palette, &generator)
+            });
+            loading_tasks.push((ev, task));
         }
         else {
             break;
         }
-        //println!("time to load chunk: {:?}", start_load_chunk.elapsed());
     }
-    //println!("chunks loaded this frame: {}", chunks_loaded);
-    //next_mapgen_state.set(MapGenState::TempBand);
-}
 
-pub fn generate_trees(
-    seed: Res<RNGSeed>,
-    mut chunk_map: ResMut<ChunkMap>,
-    mut pending_map: ResMut<PendingModificationMap>,
-
-    mut evr_gen_tree: EventReader<GenerateTreeEvent>,
-    mut evw_update_chunk: EventWriter<UpdateChunkEvent>,
-) {
-    let mut chunk_updates = Vec::new();
-
-    for ev in evr_gen_tree.read() {
-        let mut local_seed = **seed as u64 + 1;
-        let mut visited_positions = Vec::<IVec3>::new();
-        let mut expansion_points = vec![**ev];
-        let mut up_chance = 1.0;
-        let mut up_done = false;
-        let mut terminate_chance = -0.10;
-        let mut branch_chance = 0.0;
-        let mut branch_factor = 0.0;
-        let mut last_direction = IVec3::new(0, 1, 0);
-
-        while !expansion_points.is_empty() {
-            let point = expansion_points[expansion_points.len() - 1];
-            visited_positions.push(point);
-
-
-            let mut block_placements = vec![(point, true)];
-
-            if up_chance < 0.50 {
-                let mut adj_points = point.adj_6().map(|p| (p, false)).collect_vec();
-                block_placements.append(&mut adj_points);
-            }
-
-            for (placement_pos, is_log) in block_placements {
-                let chunk_pos = chunk_pos_from_global(placement_pos);
-                let block_pos = block_pos_from_global(placement_pos);
-                //println!("---");
-
-
-                if let Some(chunk) = chunk_map.get_mut(&chunk_pos) {
-
-                    if chunk.blocks[block_pos].id == BlockID::Air || chunk.blocks[block_pos].id == BlockID::Leaves {
-                        if is_log {
-                            chunk.blocks[block_pos] = Block::new(BlockID::Log);
-                        }
-                        else {
-                            chunk.blocks[block_pos] = Block::new(BlockID::Leaves);
-                        }
-                        continue;
-                    }
-                } 
-                // else
-                if !pending_map.contains_key(&chunk_pos) {
-                    pending_map.insert(chunk_pos, Grid3::new([CHUNK_SIZE; 3]));
-                }
+    let mut finished = Vec::new();
+    loading_tasks.retain_mut(|(ev, task)| {
+        match block_on(future::poll_once(task)) {
+            Some(loaded) => {
+                finished.push((*ev, loaded));
+                false
+            },
+            None => true,
+        }
+    });
 
-                //println!("block pre modification: {:?}", pending_map[&chunk_pos][block_pos].block.id);
-                if is_log {
-                    pending_map.get_mut(&chunk_pos).unwrap()[block_pos] = PendingModification{ yield_to_terrain: true, block: Block::new(BlockID::Log) };
+    for (ev, loaded) in finished {
+        let mut chunk = Chunk { blocks: Grid3::filled(Block::new(BlockID::Air), [CHUNK_SIZE, CHUNK_SIZE, CHUNK_SIZE]),
+                                light: Grid3::filled(Light::default(), [CHUNK_SIZE, CHUNK_SIZE, CHUNK_SIZE]),
+                                block_entities: BlockEntities::default(),
+                                load_reasons: HashSet::from([ev.load_reason]),
+                                render_entity: None,
+                                water_render_entity: None,
+                                translucent_render_entity: None,
+                                modified: false,
+                                unsaved: false,
+                                generated: false,
+                              };
+
+        match loaded {
+            LoadedChunk::Saved(blocks, block_entities) => {
+                chunk.blocks = blocks;
+                chunk.block_entities = block_entities;
+                // Only modified chunks ever get saved.
+                chunk.modified = true;
+            },
+            LoadedChunk::Generated(generated) => {
+                chunk.blocks = generated.blocks;
+                chunk.generated = true;
+
+                // Trees and structures regrow along with the chunk, so it doesn't need saving for them.
+                let regenerated = !generated_before.insert(ev.chunk);
+                for (root, species) in generated.trees {
+                    evw_gen_tree.send(GenerateTreeEvent { root, species, regenerated });
                 }
-                else if pending_map[&chunk_pos][block_pos].block.id == BlockID::Air {
-                    pending_map.get_mut(&chunk_pos).unwrap()[block_pos] = PendingModification{ yield_to_terrain: true, block: Block::new(BlockID::Leaves) };
+                for (structure, origin) in generated.structures {
+                    evw_gen_structure.send(GenerateStructureEvent { structure, origin, regenerated });
                 }
-                //println!("block post modification: {:?}", pending_map[&chunk_pos][block_pos].block.id);
 
-                for event in update_chunk_events_from_global(placement_pos) {
-                    if !chunk_updates.contains(&event) {
-                        chunk_updates.push(event);
-                    }
-                }
-            }
-            
-            
-            
-            //println!("local seed: {}", local_seed);
-            local_seed = local_seed.wrapping_mul(point.x.abs() as u64 + 1).wrapping_mul(point.y.abs() as u64 + 1).wrapping_mul(point.z.abs() as u64 + 1);
-            if local_seed == 0 { local_seed += 1};
-            if Rng::with_seed(local_seed.wrapping_add(1)).f32() < up_chance {
-                *expansion_points.last_mut().unwrap() = point.up(1);
-                last_direction = IVec3::new(0, 1, 0);
-            }
-            else if Rng::with_seed(local_seed.wrapping_add(2)).f32() < branch_chance {
-                //println!("branching!");
-                expansion_points.push(point);
-                terminate_chance = -0.10;
-                branch_chance = 0.0;
-                branch_factor += 0.02;
-            }
-            else if Rng::with_seed(local_seed.wrapping_add(3)).f32() < terminate_chance {
-                expansion_points.pop();
-                //last_direction = *Rng::with_seed(local_seed.wrapping_add(4)).choice(DIR_6_NO_DOWN).unwrap();
-            }
-            else {
-                if Rng::with_seed(local_seed.wrapping_add(4)).f32() > 0.90 && !visited_positions.contains(&(point + last_direction)) {
-                    *expansion_points.last_mut().unwrap() = point + last_direction;
-                }
-                else {
-                    let choices = point.adj_6_no_down().filter(|p| !visited_positions.contains(p)).collect_vec();
-                    if let Some(choice) = Rng::with_seed(local_seed.wrapping_add(4)).choice(choices) {
-                        *expansion_points.last_mut().unwrap() = choice;
-                        last_direction = point - choice;
-                    }
-                    else {
-                        expansion_points.pop();
+                if let Some(pending_chunk) = pending_map.take(ev.chunk, &database.lock().unwrap(), &palette) {
+                    chunk.mark_modified();
+                    for (position, modification) in pending_chunk.iter_3d() {
+                        modification.apply_to(&mut chunk.blocks[position]);
                     }
                 }
-            }
-            //let direction = Rng::with_seed((**seed) as u64).choice(DIR_6);
-            //for adj in point.adj_6() {
-
-            //}
-            up_chance -= 0.075;
-            if up_chance < 0.50 && !up_done {
-                up_done = true;
-                up_chance = 0.0;
-                for _ in 0..5 {
-                    expansion_points.push(point);
-                }
-            }
+            },
+        }
 
-            if up_done {
-                branch_chance += 0.20 - branch_factor;
-                terminate_chance += 0.10;
+        if let Some(chunk) = chunk_map.get(&ev.chunk) {
+            if let Some(render_entity) = chunk.render_entity {
+                commands.entity(render_entity).despawn();
             }
+            if let Some(water_render_entity) = chunk.water_render_entity {
+                commands.entity(water_render_entity).despawn();
+            }
+        }
 
-
-            //println!("branch chance: {}", branch_chance);
-            
+        chunk_map.insert(ev.chunk, chunk);
+        let loader_entity = match ev.load_reason {
+            LoadReason::Loader(entity) => entity,
+            LoadReason::Spawning(entity) => entity,
+        };
+        if let Ok(mut loader) = loader_query.get_mut(loader_entity) {
+            loader.load_list.push(ev.chunk);
         }
 
-        for event in chunk_updates.iter() {
-            evw_update_chunk.send(*event);
+        evw_update_chunk.send(UpdateChunkEvent(ev.chunk));
+        for adj in ev.chunk.adj_6() {
+            evw_update_chunk.send(UpdateChunkEvent(adj));
+        }
+        for event in light_new_chunk(ev.chunk, &mut chunk_map, &block_registry) {
+            evw_update_chunk.send(event);
         }
+
+        chunk_status_map.insert(ev.chunk, ChunkStatus::Active);
     }
+    //next_mapgen_state.set(MapGenState::TempBand);
 }
 
 pub fn unload_chunks (
@@ -415,6 +251,7 @@ pub fn unload_chunks (
     mut chunk_status_map: ResMut<ChunkStatusMap>,
     mut unloading_queue: ResMut<ChunkUnloadingQueue>,
     mut save_queue: ResMut<ChunkSavingQueue>,
+    palette: Res<SavePalette>,
 
 ) {
     let start = Instant::now();
@@ -433,7 +270,9 @@ pub fn unload_chunks (
         }
     }
     
+    let palette = &*palette;
     let mut i = 0;
+    let mut dropped_chunks = Vec::<IVec3>::new();
     let compressed_chunks: Vec<(Vec<u8>, IVec3)> = ComputeTaskPool::get().scope(|scope| {
         while i != 4 || !close_requested_evr.is_empty() {
             i += 1;
@@ -442,30 +281,18 @@ pub fn unload_chunks (
                     continue
                 }
                 if let Some(chunk) = chunk_map.get(&pos) {
-                    // WARN: This effectively suppresses a B0003 warning by not attempting to despawn in situations that might cause that issue.
-                    //       The warning may be indicative of a larger issue, so if we notice something strange happening with chunk loading/unloading, look here.
-                    if close_requested_evr.is_empty() {
-                        if let Some(render_entity) = chunk.render_entity {
-                            commands.entity(render_entity).despawn();
-                        }
-                        if let Some(water_render_entity) = chunk.water_render_entity {
-                            commands.entity(water_render_entity).despawn();
-                        }
+                    // Untouched chunks are exactly what the generator would give us, so there's no need to save them.
+                    if !chunk.modified {
+                        dropped_chunks.push(pos);
+                        continue
                     }
-                    
 
                     scope.spawn(async move {
                         //let start_encode = Instant::now();
-                        let mut e = GzEncoder::new(Vec::new(), Compression::fast());
-                        for block in chunk.blocks.iter() {
-                            e.write(&[block.id as u8, block.damage]).unwrap();
-                        }
+                        let data = encode_chunk(&chunk.blocks, &chunk.block_entities, palette);
                         //println!("time to encode: {:?}", start_encode.elapsed());
 
-                        (match e.finish() {
-                            Ok(data) => data,
-                            Err(_) => todo!(),
-                        }, pos)
+                        (data, pos)
                     });
                     
 
@@ -480,48 +307,54 @@ pub fn unload_chunks (
         
     });
 
-    for (chunk, pos) in compressed_chunks {
-        save_queue.insert(pos, chunk);
-        //println!("{}", chunk_map.remove(&pos).is_some());
-        chunk_map.remove(&pos);
-        chunk_status_map.insert(pos, ChunkStatus::PartialSave);
+    for pos in dropped_chunks {
+        if let Some(chunk) = chunk_map.remove(&pos) {
+            despawn_chunk_meshes(&chunk, &mut commands, &close_requested_evr);
+        }
+        chunk_status_map.remove(&pos);
     }
-}
 
-pub fn save_chunks (
-    mut save_queue: ResMut<ChunkSavingQueue>,
-    close_requested_evr: EventReader<WindowCloseRequested>,
-    mut exit_evw: EventWriter<AppExit>
-) {
+    for (data, pos) in compressed_chunks {
+        match data {
+            Ok(data) => {
+                save_queue.insert(pos, data);
+                //println!("{}", chunk_map.remove(&pos).is_some());
+                if let Some(chunk) = chunk_map.remove(&pos) {
+                    despawn_chunk_meshes(&chunk, &mut commands, &close_requested_evr);
+                }
+                chunk_status_map.insert(pos, ChunkStatus::PartialSave);
+            },
+            Err(err) => {
+                // Keeping the chunk loaded means autosave tries to save it again.
+                error!("Failed to encode chunk at {} for saving, keeping it loaded: {}", pos, err);
+                if let Some(chunk) = chunk_map.get_mut(&pos) {
+                    chunk.mark_modified();
+                }
+                chunk_status_map.insert(pos, ChunkStatus::Active);
+            },
+        }
+    }
+}
 
-    if !close_requested_evr.is_empty() { //save_queue.len() > 10 || 
-        let start = Instant::now();
-        let conn = Connection::open("saves/1.sl3").unwrap();
-        conn.execute("begin", []);
-        conn.execute("pragma SYNCHRONOUS = NORMAL", []);
-        let mut stmt = conn.prepare("INSERT INTO Chunks (PosX, PosY, PosZ, ChunkData) VALUES (?1, ?2, ?3, ?4)
-                                    ON CONFLICT(PosX, PosY, PosZ) DO UPDATE SET ChunkData=excluded.ChunkData;").unwrap();
-
-        for (pos, chunk_data) in save_queue.iter() {
-            match stmt.execute(params![pos.x, pos.y, pos.z, chunk_data]) {
-                Ok(updated) => {}, //println!("{} rows were updated", updated),
-                Err(err) => {}, //println!("update failed: {}", err),
-            }
-        } 
-
-        conn.execute("end", []);
-        save_queue.clear();
-        let duration = start.elapsed();
-        println!("Time elapsed for upserts: {:?}", duration);
-        if !close_requested_evr.is_empty() {
-            exit_evw.send(AppExit);
+/// Gets rid of a chunk's meshes once it's been unloaded.
+fn despawn_chunk_meshes (chunk: &Chunk, commands: &mut Commands, close_requested_evr: &EventReader<WindowCloseRequested>) {
+    // WARN: This effectively suppresses a B0003 warning by not attempting to despawn in situations that might cause that issue.
+    //       The warning may be indicative of a larger issue, so if we notice something strange happening with chunk loading/unloading, look here.
+    if close_requested_evr.is_empty() {
+        if let Some(render_entity) = chunk.render_entity {
+            commands.entity(render_entity).despawn();
+        }
+        if let Some(water_render_entity) = chunk.water_render_entity {
+            commands.entity(water_render_entity).despawn();
         }
     }
 }
 
+/// Block updates are how water knows to move. Each one settles the water at its position and the positions around it.
 pub fn process_block_updates (
     time: Res<Time>,
     mut chunk_map: ResMut<ChunkMap>,
+    block_registry: Res<BlockRegistry>,
     mut block_update_events: ResMut<Events<BlockUpdateEvent>>,
 
     mut evw_update_chunk: EventWriter<UpdateChunkEvent>,
@@ -529,12 +362,11 @@ pub fn process_block_updates (
 
     
 ) {
-    let mut thirsty_blocks = Vec::<IVec3>::new();
     let mut requeue_queue = Vec::<BlockUpdateEvent>::new();
+    let mut settled_positions = HashSet::<IVec3>::new();
+    let mut changed_blocks = Vec::<(IVec3, Block)>::new();
 
     for ev in mevr_block_update.read(&block_update_events) {
-        let chunk_pos = chunk_pos_from_global(ev.position);
-
         if (ev.time_waited.elapsed() + time.delta()).as_millis() < 200 {
             let mut requeue_ev = ev.clone();
             requeue_ev.time_waited.tick(time.delta());
@@ -542,23 +374,17 @@ pub fn process_block_updates (
             continue
         }
 
-        // Can't borrow both mutably here and immutably later. Guess we gotta make a vec to process through after we look at everything?
-        if let Some(chunk) = chunk_map.get(&chunk_pos) {
-            let block_pos = block_pos_from_global(ev.position);
+        // Water only gets so much done each tick. Whatever's left over keeps until the next one.
+        if settled_positions.len() >= MAX_WATER_UPDATES {
+            requeue_queue.push(ev.clone());
+            continue
+        }
 
-            if chunk.blocks[block_pos].id == BlockID::Air {
-                for adj in ev.position.adj_6() {
-                    if ev.position.y - adj.y == 1 {
-                        continue
-                    }
-                    let chunk_pos_adj = chunk_pos_from_global(adj);
-                    if let Some(chunk_adj) = chunk_map.get(&chunk_pos_adj) {
-                        let block_pos_adj = block_pos_from_global(adj);
-                        if chunk_adj.blocks[block_pos_adj].id == BlockID::Water {
-                            thirsty_blocks.push(ev.position);
-                            break
-                        }
-                    }
+        // Everything is worked out from the blocks as they were at the start of the tick, then changed all at once.
+        for position in std::iter::once(ev.position).chain(ev.position.adj_6()) {
+            if settled_positions.insert(position) {
+                if let Some(block) = settle_water(position, &chunk_map) {
+                    changed_blocks.push((position, block));
                 }
             }
         }
@@ -568,16 +394,15 @@ pub fn process_block_updates (
         block_update_events.send(ev);
     }
 
-    for position in thirsty_blocks {
+    for &(position, block) in changed_blocks.iter() {
         let chunk_pos = chunk_pos_from_global(position);
 
         if let Some(chunk) = chunk_map.get_mut(&chunk_pos) {
             let block_pos = block_pos_from_global(position);
-            chunk.blocks[block_pos] = Block::new(BlockID::Water);
+            chunk.blocks[block_pos] = block;
+            chunk.mark_modified();
 
-            for adj in position.adj_6() {
-                block_update_events.send(BlockUpdateEvent { position: adj, time_waited: Stopwatch::new() });
-            }
+            block_update_events.send(BlockUpdateEvent { position, time_waited: Stopwatch::new() });
 
             for event in update_chunk_events_from_global(position) {
                 evw_update_chunk.send(event);
@@ -585,6 +410,10 @@ pub fn process_block_updates (
         }
     }
 
+    let changed_positions = changed_blocks.iter().map(|(position, _)| *position).collect_vec();
+    for event in relight(&changed_positions, &mut chunk_map, &block_registry) {
+        evw_update_chunk.send(event);
+    }
 }
 
 pub fn update_chunk_positions (
@@ -699,6 +528,15 @@ impl Default for FunnyMapConsts {
 #[derive(Default, Clone, Deref, DerefMut, Resource)]
 pub struct ChunkLoadingQueue(VecDeque<LoadChunkEvent>);
 
+/// Chunks currently being read or generated on the [`AsyncComputeTaskPool`].
+#[derive(Default, Deref, DerefMut, Resource)]
+pub struct ChunkLoadingTasks(Vec<(LoadChunkEvent, Task<LoadedChunk>)>);
+
+pub enum LoadedChunk {
+    Saved(Grid3<Block>, BlockEntities),
+    Generated(GeneratedChunk),
+}
+
 #[derive(Default, Clone, Deref, DerefMut, Resource)]
 pub struct ChunkUnloadingQueue(VecDeque<IVec3>);
 
@@ -742,26 +580,184 @@ pub enum ChunkStatus {
 #[derive(Clone, Copy, Event, Deref, DerefMut, PartialEq, Eq)]
 pub struct UpdateChunkEvent(IVec3);
 
-
-#[derive(Clone, Copy, Event, Deref, DerefMut)]
-pub struct GenerateTreeEvent(IVec3);
 #[derive(Default, Clone, Deref, DerefMut, Resource)]
 pub struct ChunkMap(HashMap<IVec3, Chunk>);
 
 #[derive(Clone)]
 pub struct PendingModification {
     /// True if terrain generation takes priority over our modification.
-    yield_to_terrain: bool,
-    block: Block,
+    pub yield_to_terrain: bool,
+    pub block: Block,
 }
 impl Default for PendingModification {
     fn default() -> Self {
         Self { yield_to_terrain: true, block: Default::default() }
     }
 }
+impl PendingModification {
+    /// True if applying this would never change anything.
+    pub fn is_empty(&self) -> bool {
+        self.yield_to_terrain && self.block.id == BlockID::Air
+    }
 
-#[derive(Default, Clone, Deref, DerefMut, Resource)]
-pub struct PendingModificationMap(HashMap<IVec3, Grid3<PendingModification>>);
+    /// Puts the modification into a freshly generated `block`, unless the terrain has priority there.
+    pub fn apply_to(&self, block: &mut Block) {
+        if !self.yield_to_terrain || block.id == BlockID::Air {
+            *block = self.block;
+        }
+    }
+}
+
+/// Modifications for chunks that haven't been generated yet, like the parts of trees that grow over chunk borders.
+/// Up to [`MAX_PENDING_CHUNKS`] are kept in memory, past that the oldest ones spill into the world database.
+#[derive(Default, Clone, Resource)]
+pub struct PendingModificationMap {
+    chunks: IndexMap<IVec3, Grid3<PendingModification>>,
+    /// Chunks with modifications that are only in the database.
+    spilled: HashSet<IVec3>,
+    /// Chunks whose modifications have been applied, but may still have a row in the database.
+    /// They're deleted along with the next save, so that they go away together with the chunk they were applied to.
+    consumed: HashSet<IVec3>,
+}
+impl PendingModificationMap {
+    /// Everything in the database starts out spilled.
+    pub fn load (conn: &Connection) -> rusqlite::Result<PendingModificationMap> {
+        let spilled = conn.prepare("SELECT PosX, PosY, PosZ FROM PendingModifications")?
+            .query_map([], |row| Ok(IVec3::new(row.get(0)?, row.get(1)?, row.get(2)?)))?
+            .collect::<rusqlite::Result<HashSet<IVec3>>>()?;
+
+        Ok(PendingModificationMap { spilled, ..default() })
+    }
+
+    /// Gets a chunk's modifications to add more to them, reading them back from the database if they were spilled.
+    pub fn get_or_insert (&mut self, chunk_pos: IVec3, conn: &Connection, palette: &SavePalette) -> &mut Grid3<PendingModification> {
+        if !self.chunks.contains_key(&chunk_pos) {
+            let mut modifications = None;
+            if self.spilled.remove(&chunk_pos) {
+                match read_pending_modifications(conn, chunk_pos, palette) {
+                    Ok(read) => modifications = read,
+                    Err(err) => error!("Failed to read pending modifications for {}: {}", chunk_pos, err),
+                }
+            }
+            // Whatever we write for this chunk from now on replaces its row in the database.
+            self.consumed.remove(&chunk_pos);
+            self.chunks.insert(chunk_pos, modifications.unwrap_or_else(|| Grid3::new([CHUNK_SIZE; 3])));
+        }
+
+        self.chunks.get_mut(&chunk_pos).unwrap()
+    }
+
+    /// Removes a chunk's modifications so that they can be applied to it.
+    pub fn take (&mut self, chunk_pos: IVec3, conn: &Connection, palette: &SavePalette) -> Option<Grid3<PendingModification>> {
+        let mut modifications = self.chunks.shift_remove(&chunk_pos);
+        if self.spilled.remove(&chunk_pos) {
+            match read_pending_modifications(conn, chunk_pos, palette) {
+                Ok(read) => modifications = read,
+                Err(err) => error!("Failed to read pending modifications for {}: {}", chunk_pos, err),
+            }
+        }
+        if modifications.is_some() {
+            self.consumed.insert(chunk_pos);
+        }
+        modifications
+    }
+
+    /// Writes the oldest modifications to the database once there are too many in memory.
+    pub fn spill (&mut self, conn: &Connection, palette: &SavePalette) -> Result<(), SaveFault> {
+        if self.chunks.len() <= MAX_PENDING_CHUNKS {
+            return Ok(())
+        }
+
+        let spill_count = self.chunks.len() - MAX_PENDING_CHUNKS / 2;
+        let encoded = self.chunks.iter().take(spill_count)
+            .map(|(pos, modifications)| encode_pending_modifications(modifications, palette).map(|data| (*pos, data)))
+            .collect::<io::Result<Vec<(IVec3, Vec<u8>)>>>()?;
+
+        let tx = conn.unchecked_transaction()?;
+        write_pending_modifications(&tx, &encoded)?;
+        tx.commit()?;
+
+        for (pos, _) in encoded {
+            self.chunks.shift_remove(&pos);
+            self.spilled.insert(pos);
+        }
+        Ok(())
+    }
+
+    /// Encodes everything in memory for saving.
+    pub fn encode_all (&self, palette: &SavePalette) -> Vec<(IVec3, Vec<u8>)> {
+        self.chunks.iter().filter_map(|(pos, modifications)| match encode_pending_modifications(modifications, palette) {
+            Ok(data) => Some((*pos, data)),
+            Err(err) => {
+                error!("Failed to encode pending modifications for {}, leaving them out of the save: {}", pos, err);
+                None
+            },
+        }).collect()
+    }
+
+    /// Takes the chunks that need their database rows deleted. Put them back with [`PendingModificationMap::restore_consumed`] if the save fails.
+    pub fn take_consumed (&mut self) -> Vec<IVec3> {
+        self.consumed.drain().collect()
+    }
+
+    pub fn restore_consumed (&mut self, consumed: Vec<IVec3>) {
+        for pos in consumed {
+            // New modifications may have shown up while saving, and those will overwrite the row anyway.
+            if !self.chunks.contains_key(&pos) {
+                self.consumed.insert(pos);
+            }
+        }
+    }
+}
+
+/// Runs on a chunk loading task. Decodes the chunk if it has been saved, otherwise generates it.
+fn load_chunk (chunk_pos: IVec3, queued_data: Option<Vec<u8>>, database: &WorldDatabase, palette: &SavePalette, generator: &TerrainGenerator) -> LoadedChunk {
+    let saved_data = match queued_data {
+        Some(data) => Some((data, CHUNK_FORMAT_VERSION)),
+        None => {
+            let conn = database.lock().unwrap();
+            match conn.query_row("SELECT ChunkData, Version FROM Chunks WHERE PosX=?1 AND PosY=?2 AND PosZ=?3", [chunk_pos.x, chunk_pos.y, chunk_pos.z], |row| Ok((row.get(0)?, row.get(1)?))).optional() {
+                Ok(data) => data,
+                Err(err) => {
+                    error!("Failed to read chunk at {}: {}", chunk_pos, err);
+                    None
+                },
+            }
+        },
+    };
+
+    if let Some((data, version)) = saved_data {
+        match decode_chunk(&data, version, palette) {
+            Ok((blocks, block_entities)) => return LoadedChunk::Saved(blocks, block_entities),
+            Err(err) => error!("Failed to read saved chunk at {}, regenerating it: {}", chunk_pos, err),
+        }
+    }
+
+    LoadedChunk::Generated(generator.generate(chunk_pos))
+}
+
+pub fn read_pending_modifications (conn: &Connection, chunk_pos: IVec3, palette: &SavePalette) -> Result<Option<Grid3<PendingModification>>, SaveFault> {
+    let row: Option<(Vec<u8>, i64)> = conn.query_row("SELECT Data, Version FROM PendingModifications WHERE PosX=?1 AND PosY=?2 AND PosZ=?3", [chunk_pos.x, chunk_pos.y, chunk_pos.z], |row| Ok((row.get(0)?, row.get(1)?))).optional()?;
+
+    match row {
+        Some((data, version @ 1..=PENDING_FORMAT_VERSION)) => Ok(Some(decode_pending_modifications(&data, version, palette)?)),
+        Some((_, version)) => {
+            warn!("Pending modifications for {} have unknown format version {}, ignoring them.", chunk_pos, version);
+            Ok(None)
+        },
+        None => Ok(None),
+    }
+}
+
+/// Upserts encoded pending modifications, usually as part of a larger transaction.
+pub fn write_pending_modifications (conn: &Connection, encoded: &[(IVec3, Vec<u8>)]) -> rusqlite::Result<()> {
+    let mut stmt = conn.prepare("INSERT INTO PendingModifications (PosX, PosY, PosZ, Data, Version) VALUES (?1, ?2, ?3, ?4, ?5)
+                                 ON CONFLICT(PosX, PosY, PosZ) DO UPDATE SET Data=excluded.Data, Version=excluded.Version;")?;
+    for (pos, data) in encoded {
+        stmt.execute(params![pos.x, pos.y, pos.z, data, PENDING_FORMAT_VERSION])?;
+    }
+    Ok(())
+}
 
 /// Denotes that an entity loads chunks around itself.
 #[derive(Default, Clone, Component)]
@@ -788,102 +784,148 @@ pub enum LoadReason {
 #[derive(Clone, Debug)]
 pub struct Chunk {
     pub blocks: Grid3<Block>,
+    /// Never saved, since it can be worked out again from the blocks when the chunk is loaded.
+    pub light: Grid3<Light>,
+    /// Extra data for the blocks that need it, keyed by their position within the chunk. Removed along with the block.
+    pub block_entities: BlockEntities,
     pub load_reasons: HashSet<LoadReason>,
     pub render_entity: Option<Entity>,
     pub water_render_entity: Option<Entity>,
     pub translucent_render_entity: Option<Entity>,
+    /// True if the blocks differ from what terrain generation would give us. Only modified chunks get saved.
+    pub modified: bool,
+    /// True if the blocks have changed since the chunk was last written to the database.
+    pub unsaved: bool,
+    /// True if the chunk came out of terrain generation this session, rather than out of the save where players may have built in it.
+    pub generated: bool,
+}
+impl Chunk {
+    /// Call whenever a chunk's blocks get changed.
+    pub fn mark_modified(&mut self) {
+        self.modified = true;
+        self.unsaved = true;
+    }
 }
 
 
 // TODO: Optimization: If we're using too much space, we can try and use u8s instead of enums. :)
-#[derive(Default, Clone, Copy, Debug)]
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq)]
 pub struct Block {
     pub id: BlockID,
     pub damage: u8,
-    //pub data: [BlockData; 1],
+    pub data: BlockData,
 }
 impl Block {
     pub fn new(id: BlockID) -> Block {
-        // TODO: Make the BlockData thing be tailored for the block we're making.
-        Block {id, damage: 0, }//data: [BlockData::None]}
+        Block {id, damage: 0, data: id.get_default_data() }
     }
 
-    pub fn get_attributes(self) -> BlockAttributes {
-        self.id.get_attributes()
+    pub fn with_data(self, data: BlockData) -> Block {
+        Block { data, ..self }
     }
 }
 
-#[derive(Default, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Reflect)]
-pub enum BlockID {
-    #[default] Air,
-    Dirt,
-    Grass,
-    Stone,
-    StoneBrick,
-    Log,
-    Leaves,
-    Water,
-    Planks,
-    Crate,
-    Scaffold,
-}
+/// Numeric id of a block. Attributes for each id live in the [`BlockRegistry`], which is loaded from a file.
+/// The ids are what gets written to disk, so they must stay stable.
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Deref, Reflect, Serialize, Deserialize)]
+pub struct BlockID(pub u8);
+// Blocks that code needs to refer to directly. These are named like enum variants since they used to be one.
+#[allow(non_upper_case_globals)]
 impl BlockID {
-    pub fn get_attributes(self) -> BlockAttributes {
-        match self {
-            BlockID::Air => BlockAttributes { health: 0, solidity: Solidity::NonSolid, ..default()  },
-            BlockID::Dirt => BlockAttributes { health: 3, tex_coords: TextureCoords::symmetrical(IVec2::new(0, 0)), ..default() },
-            BlockID::Grass => BlockAttributes { health: 1, tex_coords: TextureCoords::asymmetric_y(IVec2::new(0, 1), IVec2::new(0, 0), IVec2::new(1, 1)), breaks_into: BlockID::Dirt, ..default() },
-            BlockID::Stone => BlockAttributes { health: 5, tex_coords: TextureCoords::symmetrical(IVec2::new(0, 2)), give_on_damage: Some(Item{ id: ItemID::Stone, amount: 16, }), ..default() },
-            BlockID::StoneBrick => BlockAttributes { health: 5, tex_coords: TextureCoords::symmetrical(IVec2::new(0, 3)), give_on_damage: Some(Item{ id: ItemID::Stone, amount: 2 }), cost_to_build: [Some(Item::new(ItemID::Stone, 16)), None, None],  ..default() },
-            // Logs will have special behavior for how they get mined, most likely. (Treefelling)
-            BlockID::Log => BlockAttributes { health: 2, tex_coords: TextureCoords::symmetrical(IVec2::new(0, 4)), give_on_damage: Some(Item{id: ItemID::Wood, amount: 32}), ..default() },
-            BlockID::Leaves => BlockAttributes { health: 1, tex_coords: TextureCoords::symmetrical(IVec2::new(0, 5)), solidity: Solidity::Climable, ..default() },
-            BlockID::Water => BlockAttributes {health: 0, tex_coords: TextureCoords::unique_top(IVec2::new(0, 7), IVec2::new(1, 7)), solidity: Solidity::Water, ..default()},
-            BlockID::Planks => BlockAttributes { health: 3, tex_coords: TextureCoords::symmetrical(IVec2::new(0, 6)), solidity: Solidity::Solid, give_on_damage: Some(Item{ id: ItemID::Wood, amount: 2}), cost_to_build: [Some(Item::new(ItemID::Wood, 8)), None, None], ..default() },
-            BlockID::Crate => BlockAttributes { health: 1, tex_coords: TextureCoords::symmetrical(IVec2::new(0, 8)), solidity: Solidity::Solid, give_on_damage: Some(Item{ id: ItemID::Wood, amount: 64}), cost_to_build: [Some(Item::new(ItemID::Wood, 64)), None, None], ..default() },
-            BlockID::Scaffold => BlockAttributes { health: 1, tex_coords: TextureCoords::asymmetric_y(IVec2::new(1, 8), IVec2::new(31, 31), IVec2::new(2, 8)), solidity: Solidity::Climable, give_on_damage: Some(Item{ id: ItemID::Wood, amount: 2}), cost_to_build: [Some(Item::new(ItemID::Wood, 2)), None, None], ..default() },
-        }
-    }
-
-    fn get_default_data(self) -> [BlockData; 1] {
-        todo!()
-    }
-
-    pub fn from_u8(num: u8) -> Self {
-        match num {
-            0 => BlockID::Air,
-            1 => BlockID::Dirt,
-            2 => BlockID::Grass,
-            3 => BlockID::Stone,
-            4 => BlockID::StoneBrick,
-            5 => BlockID::Log,
-            6 => BlockID::Leaves,
-            7 => BlockID::Water,
-            8 => BlockID::Planks,
-            9 => BlockID::Crate,
-            10 => BlockID::Scaffold,
-            _ => todo!("Requested unassigned blockID!"),
-        }
+    pub const Air: BlockID = BlockID(0);
+    pub const Dirt: BlockID = BlockID(1);
+    pub const Grass: BlockID = BlockID(2);
+    pub const Stone: BlockID = BlockID(3);
+    pub const StoneBrick: BlockID = BlockID(4);
+    pub const Log: BlockID = BlockID(5);
+    pub const Leaves: BlockID = BlockID(6);
+    pub const Water: BlockID = BlockID(7);
+    pub const Planks: BlockID = BlockID(8);
+    pub const Crate: BlockID = BlockID(9);
+    pub const Scaffold: BlockID = BlockID(10);
+    pub const Workbench: BlockID = BlockID(11);
+    pub const IronOre: BlockID = BlockID(12);
+    pub const GoldOre: BlockID = BlockID(13);
+    pub const Sand: BlockID = BlockID(14);
+    pub const Snow: BlockID = BlockID(15);
+    /// Stands in for blocks in a save that the registry doesn't know about.
+    pub const Unknown: BlockID = BlockID(255);
+
+    /// The names the blocks above must have in the registry file.
+    pub const BUILTIN: &'static [(&'static str, BlockID)] = &[
+        ("air", BlockID::Air), ("dirt", BlockID::Dirt), ("grass", BlockID::Grass), ("stone", BlockID::Stone),
+        ("stone_brick", BlockID::StoneBrick), ("log", BlockID::Log), ("leaves", BlockID::Leaves), ("water", BlockID::Water),
+        ("planks", BlockID::Planks), ("crate", BlockID::Crate), ("scaffold", BlockID::Scaffold), ("workbench", BlockID::Workbench),
+        ("iron_ore", BlockID::IronOre), ("gold_ore", BlockID::GoldOre),
+        ("sand", BlockID::Sand), ("snow", BlockID::Snow),
+        ("unknown", BlockID::Unknown),
+    ];
+
+    /// Data a freshly made block starts out with. Nothing needs any yet, trees mark their own blocks as they grow.
+    pub fn get_default_data(self) -> BlockData {
+        BlockData::None
     }
 }
 
-#[derive(Default, Clone, Copy)]
+/// Small bits of state kept with every block, for things that don't need a whole [`BlockEntity`].
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq)]
 pub enum BlockData {
     #[default] None,
+    /// Grew as part of a tree, so it comes down when the tree gets felled.
+    Tree,
     // For trees and stuff.
+    /// A tree block that's coming down because something next to it broke. Holds how many felling steps it has left.
     DamagedAdjacent(u8),
+    /// Built as part of a structure, air included. Trees don't grow into these.
+    Structure,
+    /// Kept by [`BlockID::Unknown`] blocks, which are blocks that were removed from the registry. Holds the id they were saved with,
+    /// so they're written back as the same block and come back if it's ever defined again. Whatever data they had before is lost.
+    Placeholder(u8),
+}
+impl BlockData {
+    /// Written to saves as a tag followed by a value.
+    pub fn to_bytes(self) -> [u8; 2] {
+        match self {
+            BlockData::None => [0, 0],
+            BlockData::Tree => [1, 0],
+            BlockData::DamagedAdjacent(steps) => [2, steps],
+            BlockData::Structure => [3, 0],
+            BlockData::Placeholder(saved_id) => [4, saved_id],
+        }
+    }
+
+    /// Anything unrecognized just comes back as no data.
+    pub fn from_bytes(bytes: [u8; 2]) -> BlockData {
+        match bytes[0] {
+            1 => BlockData::Tree,
+            2 => BlockData::DamagedAdjacent(bytes[1]),
+            3 => BlockData::Structure,
+            4 => BlockData::Placeholder(bytes[1]),
+            _ => BlockData::None,
+        }
+    }
+
+    pub fn is_tree(self) -> bool {
+        matches!(self, BlockData::Tree | BlockData::DamagedAdjacent(_))
+    }
 }
 
 #[derive(Default, Clone, Copy)]
 pub struct BlockAttributes {
     pub health: u8,
+    /// Tool strength needed to mine the block at all.
     pub toughness: u8,
+    /// The kind of tool that's good for mining the block. No tool helps if this is `None`.
+    pub tool: Option<ToolKind>,
     pub tex_coords: TextureCoords,
     pub breaks_into: BlockID,
     pub give_on_damage: Option<Item>,
     pub cost_to_build: [Option<Item>; 3],
     pub solidity: Solidity,
     pub slip: Slip,
+    pub visibility: BlockVisibility,
+    /// Block light given off by this block, up to [`MAX_LIGHT`].
+    pub light: u8,
 }
 /*
 impl BlockAttributes {
@@ -893,7 +935,7 @@ impl BlockAttributes {
 }
  */
 
- #[derive(Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+ #[derive(Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug, Deserialize)]
  pub enum Solidity {
     #[default] Solid,
     NonSolid,
@@ -901,6 +943,15 @@ impl BlockAttributes {
     Climable,
  }
 
+/// Which mesh (if any) a block gets drawn into.
+#[derive(Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug, Deserialize)]
+pub enum BlockVisibility {
+    Invisible,
+    #[default] Opaque,
+    Liquid,
+    Translucent,
+}
+
 // We might as well make this a struct instead of an enum, since it'll be the same size either way, and this will let us clarify what is what better.
 #[derive(Default, Clone, Copy)]
 pub struct TextureCoords {
@@ -0,0 +1,312 @@
+use std::{fmt::Display, fs, path::Path};
+
+use bevy::{prelude::*, utils::HashMap};
+use fastrand::Rng;
+use serde::Deserialize;
+
+use crate::{block_pos_from_global, blocks::BlockRegistry, chunk_pos_from_global, grid3::Grid3, light::relight, update_chunk_events_from_global, Block, BlockData, BlockID, ChunkMap, PendingModification, PendingModificationMap, SavePalette, UpdateChunkEvent, WorldDatabase};
+
+/// Every `.ron` file in here is loaded as a structure, named after the file.
+pub const STRUCTURES_PATH: &str = "assets/structures";
+/// The world is split into square regions this many blocks across. Each kind of structure gets at most one try per region.
+pub const REGION_SIZE: i32 = 64;
+
+// Events
+/// Sent when a freshly generated chunk has a structure starting in it.
+#[derive(Clone, Copy, Event)]
+pub struct GenerateStructureEvent {
+    /// Index of the structure in the [`StructureRegistry`].
+    pub structure: usize,
+    /// Where the template's corner at (0, 0, 0) goes.
+    pub origin: IVec3,
+    /// True if the origin's chunk was already generated earlier this session, so every other chunk the structure covers already got its part of it.
+    pub regenerated: bool,
+}
+
+// Resources
+/// Every structure that can show up in the world. The built in ones come first, then the ones in [`STRUCTURES_PATH`], sorted by name.
+#[derive(Clone, Resource, Deref)]
+pub struct StructureRegistry(Vec<Structure>);
+impl StructureRegistry {
+    pub fn load(path: &str, block_registry: &BlockRegistry) -> Result<StructureRegistry, StructureRegistryFault> {
+        let mut structures = vec![
+            Structure { name: "gold_vault".into(), placement: Placement::Underground { min_y: -180, max_y: -70 }, chance: 0.35, template: gold_vault() },
+            Structure { name: "mineshaft".into(), placement: Placement::Underground { min_y: -60, max_y: -25 }, chance: 0.5, template: mineshaft() },
+        ];
+
+        let mut paths = fs::read_dir(path).map_err(|err| StructureRegistryFault::Io(err.to_string()))?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.extension().map_or(false, |extension| extension == "ron"))
+            .collect::<Vec<_>>();
+        paths.sort();
+
+        for path in paths {
+            let structure = Structure::load(&path, block_registry)?;
+            if structures.iter().any(|other| other.name == structure.name) {
+                return Err(StructureRegistryFault::DuplicateName(structure.name))
+            }
+            structures.push(structure);
+        }
+
+        Ok(StructureRegistry(structures))
+    }
+
+    /// Just the rules for where each structure goes, which is all terrain generation needs.
+    pub fn placements(&self) -> Vec<StructurePlacement> {
+        self.iter().map(|structure| StructurePlacement { name: structure.name.clone(), placement: structure.placement, chance: structure.chance }).collect()
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum StructureRegistryFault {
+    Io(String),
+    Parse(String, String),
+    UnknownBlock(String, String),
+    UnknownSymbol(String, char),
+    BadShape(String),
+    DuplicateName(String),
+}
+impl Display for StructureRegistryFault {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StructureRegistryFault::Io(err) => write!(f, "could not read structures: {}", err),
+            StructureRegistryFault::Parse(name, err) => write!(f, "could not parse structure \"{}\": {}", name, err),
+            StructureRegistryFault::UnknownBlock(name, block) => write!(f, "structure \"{}\" uses block \"{}\", which is never defined", name, block),
+            StructureRegistryFault::UnknownSymbol(name, symbol) => write!(f, "structure \"{}\" uses '{}', which isn't in its palette", name, symbol),
+            StructureRegistryFault::BadShape(name) => write!(f, "structure \"{}\" needs every layer to have the same number of rows, and every row the same length", name),
+            StructureRegistryFault::DuplicateName(name) => write!(f, "there's more than one structure called \"{}\"", name),
+        }
+    }
+}
+
+// Data
+#[derive(Clone)]
+pub struct Structure {
+    pub name: String,
+    pub placement: Placement,
+    /// Chance of the structure showing up in any one region.
+    pub chance: f32,
+    pub template: StructureTemplate,
+}
+impl Structure {
+    fn load(path: &Path, block_registry: &BlockRegistry) -> Result<Structure, StructureRegistryFault> {
+        let name = path.file_stem().map(|stem| stem.to_string_lossy().into_owned()).unwrap_or_default();
+        let text = fs::read_to_string(path).map_err(|err| StructureRegistryFault::Io(err.to_string()))?;
+        let file: StructureDefinitionFile = ron::from_str(&text).map_err(|err| StructureRegistryFault::Parse(name.clone(), err.to_string()))?;
+
+        let mut palette = HashMap::new();
+        for (symbol, block_name) in file.palette.iter() {
+            let id = block_registry.id(block_name).ok_or(StructureRegistryFault::UnknownBlock(name.clone(), block_name.clone()))?;
+            palette.insert(*symbol, id);
+        }
+
+        let height = file.layers.len();
+        let length = file.layers.first().map_or(0, |layer| layer.len());
+        let width = file.layers.first().and_then(|layer| layer.first()).map_or(0, |row| row.chars().count());
+        if file.layers.iter().any(|layer| layer.len() != length || layer.iter().any(|row| row.chars().count() != width)) {
+            return Err(StructureRegistryFault::BadShape(name))
+        }
+
+        let mut blocks = Grid3::new([width as i32, height as i32, length as i32]);
+        for (y, layer) in file.layers.iter().enumerate() {
+            for (z, row) in layer.iter().enumerate() {
+                for (x, symbol) in row.chars().enumerate() {
+                    if symbol == ' ' {
+                        continue
+                    }
+                    let id = palette.get(&symbol).ok_or(StructureRegistryFault::UnknownSymbol(name.clone(), symbol))?;
+                    blocks[IVec3::new(x as i32, y as i32, z as i32)] = Some(Block::new(*id));
+                }
+            }
+        }
+
+        Ok(Structure { name, placement: file.placement, chance: file.chance, template: StructureTemplate { blocks } })
+    }
+}
+
+/// What gets built. Cells that are `None` leave whatever the terrain put there alone.
+#[derive(Clone)]
+pub struct StructureTemplate {
+    pub blocks: Grid3<Option<Block>>,
+}
+impl StructureTemplate {
+    /// A template that replaces every block it covers, air included.
+    pub fn from_blocks(blocks: &Grid3<Block>) -> Self {
+        let mut template = Grid3::new(blocks.size());
+        for (position, block) in blocks.iter_3d() {
+            template[position] = Some(*block);
+        }
+        Self { blocks: template }
+    }
+}
+
+/// Where in the world a structure is allowed to go.
+#[derive(Clone, Copy, Debug, Deserialize)]
+pub enum Placement {
+    /// Sits on dry ground, with its bottom layer replacing the top block of the terrain.
+    Surface,
+    /// Buried somewhere between these heights, which its bottom layer starts at.
+    Underground { min_y: i32, max_y: i32 },
+    /// Sits on the floor of the sea, like [`Placement::Surface`] does on land.
+    Water,
+}
+
+#[derive(Clone, Debug)]
+pub struct StructurePlacement {
+    /// Where the structure goes only depends on its name, so adding or removing other structures doesn't move it.
+    pub name: String,
+    pub placement: Placement,
+    pub chance: f32,
+}
+impl StructurePlacement {
+    /// Where the structure tries to go in `region`, if it shows up there at all.
+    /// The height only means anything for [`Placement::Underground`], the others depend on the terrain.
+    pub fn site(&self, seed: u32, region: IVec2) -> Option<IVec3> {
+        let mut hash = seed as u64;
+        let name = self.name.bytes().map(|byte| byte as u64);
+        for value in [region.x as u64, region.y as u64].into_iter().chain(name) {
+            hash = (hash ^ value).wrapping_mul(0x9E37_79B9_7F4A_7C15);
+            hash ^= hash >> 29;
+        }
+        let mut rng = Rng::with_seed(hash);
+
+        if rng.f32() >= self.chance {
+            return None
+        }
+        let x = region.x * REGION_SIZE + rng.i32(0..REGION_SIZE);
+        let z = region.y * REGION_SIZE + rng.i32(0..REGION_SIZE);
+        let y = match self.placement {
+            Placement::Underground { min_y, max_y } => rng.i32(min_y..=max_y),
+            _ => 0,
+        };
+        Some(IVec3::new(x, y, z))
+    }
+}
+
+#[derive(Deserialize)]
+struct StructureDefinitionFile {
+    placement: Placement,
+    chance: f32,
+    palette: HashMap<char, String>,
+    /// Bottom layer first. Each row runs along x and each layer's rows go along z. Spaces are left as they are.
+    layers: Vec<Vec<String>>,
+}
+
+// Systems
+/// Builds structures into the chunks they cover, or leaves them pending for chunks that haven't been generated yet.
+/// Structures take priority over trees: their blocks are marked with [`BlockData::Structure`] and trees never grow into those,
+/// while structures build over trees no matter which came first.
+/// Chunks that were loaded from the save are left alone, and so is anything with a block entity, so nothing players have built gets buried.
+/// Building the same structure again leaves the same pending modifications, so doing it for a chunk regenerated in a later session is harmless.
+pub fn generate_structures (
+    mut chunk_map: ResMut<ChunkMap>,
+    mut pending_map: ResMut<PendingModificationMap>,
+    structure_registry: Res<StructureRegistry>,
+    block_registry: Res<BlockRegistry>,
+    palette: Res<SavePalette>,
+    database: Res<WorldDatabase>,
+
+    mut evr_gen_structure: EventReader<GenerateStructureEvent>,
+    mut evw_update_chunk: EventWriter<UpdateChunkEvent>,
+) {
+    let mut chunk_updates = Vec::new();
+    let mut built_blocks = Vec::new();
+
+    for ev in evr_gen_structure.read() {
+        let Some(structure) = structure_registry.get(ev.structure) else {
+            continue
+        };
+        let origin_chunk = chunk_pos_from_global(ev.origin);
+
+        for (position, block) in structure.template.blocks.iter_3d() {
+            let Some(block) = block else {
+                continue
+            };
+            let block = block.with_data(BlockData::Structure);
+            let global_position = ev.origin + position;
+            let chunk_pos = chunk_pos_from_global(global_position);
+            let block_pos = block_pos_from_global(global_position);
+            if ev.regenerated && chunk_pos != origin_chunk {
+                continue
+            }
+
+            // Chunks that aren't around yet get it once they're generated.
+            if let Some(chunk) = chunk_map.get_mut(&chunk_pos) {
+                if !chunk.generated || chunk.block_entities.contains_key(&block_pos) {
+                    continue
+                }
+                chunk.blocks[block_pos] = block;
+                // Like with trees, only the chunks other than the origin's need saving for it.
+                if chunk_pos != origin_chunk {
+                    chunk.mark_modified();
+                }
+                built_blocks.push(global_position);
+            }
+            else {
+                pending_map.get_or_insert(chunk_pos, &database.lock().unwrap(), &palette)[block_pos] = PendingModification { yield_to_terrain: false, block };
+            }
+
+            for event in update_chunk_events_from_global(global_position) {
+                if !chunk_updates.contains(&event) {
+                    chunk_updates.push(event);
+                }
+            }
+        }
+    }
+
+    for event in relight(&built_blocks, &mut chunk_map, &block_registry) {
+        if !chunk_updates.contains(&event) {
+            chunk_updates.push(event);
+        }
+    }
+    for event in chunk_updates {
+        evw_update_chunk.send(event);
+    }
+
+    if let Err(err) = pending_map.spill(&database.lock().unwrap(), &palette) {
+        error!("Failed to spill pending modifications: {}", err);
+    }
+}
+
+// Helpers
+/// A small stone brick room with a floor of gold.
+fn gold_vault() -> StructureTemplate {
+    let size = IVec3::new(7, 5, 7);
+    let mut blocks = Grid3::filled(Block::new(BlockID::StoneBrick), size);
+
+    for (position, block) in blocks.iter_3d_mut() {
+        let inside = position.cmpgt(IVec3::ZERO).all() && position.cmplt(size - 1).all();
+        if inside {
+            *block = Block::new(BlockID::Air);
+        }
+        else if position.y == 0 && (2..=4).contains(&position.x) && (2..=4).contains(&position.z) {
+            *block = Block::new(BlockID::GoldOre);
+        }
+    }
+
+    StructureTemplate::from_blocks(&blocks)
+}
+
+/// A long tunnel along x, propped up by wooden frames. The walls are whatever it was dug through.
+fn mineshaft() -> StructureTemplate {
+    let size = IVec3::new(33, 4, 5);
+    let mut blocks = Grid3::<Option<Block>>::new(size);
+
+    for x in 0..size.x {
+        for z in 1..=3 {
+            // Some of the floor has rotted away.
+            blocks[IVec3::new(x, 0, z)] = if x % 7 == 3 && z != 2 { None } else { Some(Block::new(BlockID::Planks)) };
+
+            for y in 1..=3 {
+                let block = match (x % 4 == 0, y, z) {
+                    (true, 3, _) => BlockID::Planks,
+                    (true, _, 1 | 3) => BlockID::Log,
+                    _ => BlockID::Air,
+                };
+                blocks[IVec3::new(x, y, z)] = Some(Block::new(block));
+            }
+        }
+    }
+
+    StructureTemplate { blocks }
+}
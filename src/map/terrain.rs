@@ -0,0 +1,222 @@
+use bevy::{math::DVec3, prelude::*};
+use noise::{core::worley::ReturnType, Blend, Constant, NoiseFn, Perlin, ScalePoint, Worley};
+
+use crate::{biomes::{BiomeMap, BiomeSample}, grid3::Grid3, structures::{Placement, StructurePlacement, REGION_SIZE}, trees::TreeSpecies, Block, BlockID, FunnyMapConsts, SingleDirectionAxialGradient, WhiteNoise, CHUNK_SIZE, WORLD_HEIGHT};
+
+/// Caves only get carved out below this height, so they don't poke holes in the hills or let the sea drain into them.
+const CAVE_CEILING: f64 = -20.0;
+/// Worley distances below this are hollowed out into caverns.
+const CAVERN_THRESHOLD: f64 = -0.55;
+/// How wide tunnels are. Blocks get carved where both tunnel noises are this close to zero.
+const TUNNEL_WIDTH: f64 = 0.06;
+
+/// Generates chunks from the world seed. Holds nothing but the noise, so it can be shared between chunk loading tasks.
+pub struct TerrainGenerator {
+    pub seed: u32,
+    noise_gen: Blend<f64, ScalePoint<Perlin>, SingleDirectionAxialGradient, Constant, 3>,
+    /// [`Self::noise_gen`] without the hills. Biomes scale the difference between the two to make their terrain flatter or rougher.
+    flat_gen: Blend<f64, Constant, SingleDirectionAxialGradient, Constant, 3>,
+    pub biomes: BiomeMap,
+    tree_noise: Blend<f64, ScalePoint<Perlin>, WhiteNoise, Constant, 2>,
+    cavern_noise: Worley,
+    tunnel_noise: [ScalePoint<Perlin>; 2],
+    ores: Vec<OreLayer>,
+    structures: Vec<StructurePlacement>,
+    consts: FunnyMapConsts,
+}
+
+pub struct GeneratedChunk {
+    pub blocks: Grid3<Block>,
+    /// Roots of the trees that should grow from this chunk, and what kind of tree they are.
+    pub trees: Vec<(IVec3, TreeSpecies)>,
+    /// Structures that start in this chunk, as their index in the [`StructureRegistry`](crate::structures::StructureRegistry) and origin.
+    pub structures: Vec<(usize, IVec3)>,
+}
+
+impl TerrainGenerator {
+    pub fn new(seed: u32, structures: Vec<StructurePlacement>) -> Self {
+        let gradient = SingleDirectionAxialGradient { values: vec![1.0, 0.0, -0.5], points: vec![-(CHUNK_SIZE) as f64, 0.0, (WORLD_HEIGHT * CHUNK_SIZE) as f64], dimension: 1 };
+
+        let noise_gen = Blend::new(ScalePoint::new(Perlin::new(seed)).set_scale(0.025), gradient.clone(), Constant::new(0.7));
+        let flat_gen = Blend::new(Constant::new(0.0), gradient, Constant::new(0.7));
+
+        //let tree_noise = Worley::new(**seed).set_distance_function(euclidean_squared).set_return_type(ReturnType::Distance).set_frequency(0.025 );
+
+        let tree_noise = Blend::new(
+            ScalePoint::new(Perlin::new(seed + 1)).set_scale(0.001),
+            WhiteNoise{seed},
+            Constant::new(0.85),
+        );
+
+        let cavern_noise = Worley::new(seed + 2).set_frequency(1.0 / 24.0).set_return_type(ReturnType::Distance);
+        let tunnel_noise = [
+            ScalePoint::new(Perlin::new(seed + 3)).set_scale(0.03),
+            ScalePoint::new(Perlin::new(seed + 4)).set_scale(0.03),
+        ];
+
+        let ores = vec![
+            OreLayer { block: BlockID::IronOre, noise: ScalePoint::new(Perlin::new(seed + 5)).set_scale(0.15), min_y: -160.0, peak_y: -40.0, max_y: -8.0, threshold: 0.55 },
+            OreLayer { block: BlockID::GoldOre, noise: ScalePoint::new(Perlin::new(seed + 6)).set_scale(0.2), min_y: -192.0, peak_y: -150.0, max_y: -60.0, threshold: 0.6 },
+        ];
+
+        Self { seed, noise_gen, flat_gen, biomes: BiomeMap::new(seed), tree_noise, cavern_noise, tunnel_noise, ores, structures, consts: FunnyMapConsts::default() }
+    }
+
+    /// How solid the world is at `point`, anything at or above 0 is ground.
+    fn density(&self, point: [f64; 3], amplitude: f64) -> f64 {
+        let flat = self.flat_gen.get(point);
+        flat + (self.noise_gen.get(point) - flat) * amplitude
+    }
+
+    /// The structures whose site falls in this chunk, and where exactly they go.
+    fn structure_sites(&self, chunk_pos: IVec3, columns: &[BiomeSample]) -> Vec<(usize, IVec3)> {
+        let offset = chunk_pos * CHUNK_SIZE;
+        let region = IVec2::new(offset.x.div_euclid(REGION_SIZE), offset.z.div_euclid(REGION_SIZE));
+        let mut sites = Vec::new();
+
+        for (i, structure) in self.structures.iter().enumerate() {
+            let Some(site) = structure.site(self.seed, region) else {
+                continue
+            };
+            let local = site - offset;
+            if !(0..CHUNK_SIZE).contains(&local.x) || !(0..CHUNK_SIZE).contains(&local.z) {
+                continue
+            }
+
+            let amplitude = columns[(local.x + local.z * CHUNK_SIZE) as usize].amplitude;
+            // Top of the ground in this column, if it's in this chunk.
+            let ground = (offset.y..offset.y + CHUNK_SIZE).rev().find(|y| {
+                self.density([site.x as f64, *y as f64, site.z as f64], amplitude) >= 0.0 && self.density([site.x as f64, *y as f64 + 1.0, site.z as f64], amplitude) < 0.0
+            });
+
+            let y = match structure.placement {
+                Placement::Underground { .. } => (offset.y..offset.y + CHUNK_SIZE).contains(&site.y).then_some(site.y),
+                Placement::Surface => ground.filter(|y| *y > 0),
+                Placement::Water => ground.filter(|y| *y < -1),
+            };
+            if let Some(y) = y {
+                sites.push((i, IVec3::new(site.x, y, site.z)));
+            }
+        }
+
+        sites
+    }
+
+    /// Carves caves out of the stone and seeds it with ore. Doesn't touch anything else, so the surface stays as it was.
+    fn underground(&self, position: DVec3) -> Option<BlockID> {
+        let point = [position.x, position.y, position.z];
+
+        if position.y < CAVE_CEILING {
+            if self.cavern_noise.get(point) < CAVERN_THRESHOLD {
+                return Some(BlockID::Air)
+            }
+            let [a, b] = &self.tunnel_noise;
+            if a.get(point).powi(2) + b.get(point).powi(2) < TUNNEL_WIDTH.powi(2) {
+                return Some(BlockID::Air)
+            }
+        }
+
+        self.ores.iter().find(|ore| ore.contains(position.y, point)).map(|ore| ore.block)
+    }
+
+    pub fn generate(&self, chunk_pos: IVec3) -> GeneratedChunk {
+        let mut blocks = Grid3::filled(Block::new(BlockID::Air), [CHUNK_SIZE, CHUNK_SIZE, CHUNK_SIZE]);
+        let mut trees = Vec::new();
+
+        let offset = chunk_pos * CHUNK_SIZE;
+        let columns = (0..CHUNK_SIZE * CHUNK_SIZE)
+            .map(|i| self.biomes.sample((offset.x + i % CHUNK_SIZE) as f64, (offset.z + i / CHUNK_SIZE) as f64))
+            .collect::<Vec<BiomeSample>>();
+
+        let mut set_block_val = |position: IVec3, block_val: &mut Block| {
+            let point = DVec3::from(offset + position);
+            let column = columns[(position.x + position.z * CHUNK_SIZE) as usize];
+            let biome = column.biome.get_attributes();
+
+            let noise_val = self.density([point.x, point.y, point.z], column.amplitude);
+            if noise_val >= 0.0 {
+                *block_val = Block::new(biome.filler);
+                // Make our block the surface block instead if the block above is air.
+                if self.density([point.x, point.y + 1.0, point.z], column.amplitude) < 0.0 && point.y > 0.0 {
+                    *block_val = Block::new(biome.surface);
+
+                    // Tree!
+                    if self.tree_noise.get([point.x, point.z]) > column.tree_threshold {
+                        let root = IVec3::new(point.x as i32, point.y as i32 + 1, point.z as i32);
+                        trees.push((root, TreeSpecies::pick(self.seed, root, column.biome)));
+                        // TODO: Maybe we want to do this in the tree generation system?
+                        *block_val = Block::new(BlockID::Dirt);
+                    }
+                }
+                if (self.density([point.x, point.y + 5.0, point.z], column.amplitude) > 0.0) || point.y < -5.0 {
+                    *block_val = Block::new(BlockID::Stone);
+                }
+            }
+            if point.y < 0.0 && block_val.id == BlockID::Air {
+                *block_val = Block::new(BlockID::Water)
+            }
+        };
+
+        let mut all_air = true;
+        let mut all_stone = true;
+        for (i, position) in self.consts.chunk_perimeter_indices.iter() {
+            set_block_val(*position, &mut blocks.data[*i]);
+            if blocks.data[*i].id != BlockID::Air {
+                all_air = false;
+            }
+            if blocks.data[*i].id != BlockID::Stone {
+                all_stone = false
+            }
+        }
+
+        if !(all_air || all_stone) {
+            for (i, position) in self.consts.chunk_volume_indices.iter() {
+                set_block_val(*position, &mut blocks.data[*i]);
+            }
+        }
+
+        if all_stone {
+            blocks = Grid3::filled(Block::new(BlockID::Stone), [CHUNK_SIZE, CHUNK_SIZE, CHUNK_SIZE]);
+        }
+
+        // Nothing underground is ever above the ore and cave layers, so chunks up there can skip it.
+        if !all_air && (offset.y as f64) < self.ores.iter().fold(CAVE_CEILING, |max, ore| max.max(ore.max_y)) {
+            for (position, block) in blocks.iter_3d_mut() {
+                if block.id != BlockID::Stone {
+                    continue
+                }
+                if let Some(id) = self.underground(DVec3::from(offset + position)) {
+                    *block = Block::new(id);
+                }
+            }
+        }
+
+        let structures = self.structure_sites(chunk_pos, &columns);
+
+        GeneratedChunk { blocks, trees, structures }
+    }
+}
+
+/// A band of depths that some ore shows up in. It's most common at `peak_y` and thins out towards `min_y` and `max_y`.
+struct OreLayer {
+    block: BlockID,
+    noise: ScalePoint<Perlin>,
+    min_y: f64,
+    peak_y: f64,
+    max_y: f64,
+    /// Noise value the ore shows up above at its peak. Higher means smaller, rarer veins.
+    threshold: f64,
+}
+impl OreLayer {
+    fn contains(&self, y: f64, point: [f64; 3]) -> bool {
+        let weight = if y < self.peak_y {
+            (y - self.min_y) / (self.peak_y - self.min_y)
+        } else {
+            (self.max_y - y) / (self.max_y - self.peak_y)
+        };
+        if weight <= 0.0 {
+            return false
+        }
+        self.noise.get(point) > 1.0 - (1.0 - self.threshold) * weight.min(1.0)
+    }
+}
@@ -0,0 +1,341 @@
+use std::ops::RangeInclusive;
+
+use bevy::prelude::*;
+use fastrand::Rng;
+use indexmap::IndexMap;
+
+use crate::{biomes::Biome, block_pos_from_global, blocks::BlockRegistry, chunk_pos_from_global, light::relight, update_chunk_events_from_global, Block, BlockData, BlockID, ChunkMap, PendingModification, PendingModificationMap, RNGSeed, SavePalette, UpdateChunkEvent, WorldDatabase};
+
+/// The directions branches can grow out in.
+const BRANCH_DIRECTIONS: [IVec3; 4] = [IVec3::X, IVec3::NEG_X, IVec3::Z, IVec3::NEG_Z];
+
+// Events
+/// Sent when a freshly generated chunk has a tree growing from it.
+#[derive(Clone, Copy, Event)]
+pub struct GenerateTreeEvent {
+    /// The bottom log.
+    pub root: IVec3,
+    pub species: TreeSpecies,
+    /// True if the root's chunk was already generated earlier this session, so every other chunk the tree reaches already got its part of it.
+    pub regenerated: bool,
+}
+
+// Data
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum TreeSpecies {
+    Oak,
+    Birch,
+    Pine,
+    Willow,
+}
+impl TreeSpecies {
+    pub fn get_attributes(self) -> TreeShape {
+        match self {
+            TreeSpecies::Oak => TreeShape { height: 4..=6, branchiness: 0.35, branch_length: 3, canopy: Canopy::Round { radius: 2 } },
+            TreeSpecies::Birch => TreeShape { height: 6..=8, branchiness: 0.1, branch_length: 1, canopy: Canopy::Round { radius: 1 } },
+            TreeSpecies::Pine => TreeShape { height: 7..=10, branchiness: 0.0, branch_length: 0, canopy: Canopy::Cone { radius: 3 } },
+            TreeSpecies::Willow => TreeShape { height: 4..=5, branchiness: 0.5, branch_length: 2, canopy: Canopy::Droopy { radius: 3, droop: 3 } },
+        }
+    }
+
+    /// The kind of tree that grows at `root`. Depends on nothing but the seed, the position and the biome there.
+    pub fn pick(seed: u32, root: IVec3, biome: Biome) -> TreeSpecies {
+        let roll = tree_rng(seed, root, 1).f32();
+        match biome {
+            Biome::Forest if roll < 0.4 => TreeSpecies::Birch,
+            Biome::Tundra => TreeSpecies::Pine,
+            Biome::Swamp => TreeSpecies::Willow,
+            Biome::Plains if roll < 0.1 => TreeSpecies::Pine,
+            _ => TreeSpecies::Oak,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct TreeShape {
+    /// How many logs the trunk is made of.
+    pub height: RangeInclusive<i32>,
+    /// Chance of each log in the top half of the trunk growing a branch.
+    pub branchiness: f32,
+    /// How far branches can reach out from the trunk.
+    pub branch_length: i32,
+    pub canopy: Canopy,
+}
+
+/// How the leaves at the top of a tree are laid out.
+#[derive(Clone, Copy, Debug)]
+pub enum Canopy {
+    /// A ball of leaves around the top of the trunk.
+    Round { radius: i32 },
+    /// Rings of leaves that get wider further down the trunk.
+    Cone { radius: i32 },
+    /// A flattened ball with strands of leaves hanging off its edge.
+    Droopy { radius: i32, droop: i32 },
+}
+
+// Systems
+pub fn generate_trees (
+    mut chunk_map: ResMut<ChunkMap>,
+    mut pending_map: ResMut<PendingModificationMap>,
+    seed: Res<RNGSeed>,
+    palette: Res<SavePalette>,
+    database: Res<WorldDatabase>,
+    block_registry: Res<BlockRegistry>,
+
+    mut evr_gen_tree: EventReader<GenerateTreeEvent>,
+    mut evw_update_chunk: EventWriter<UpdateChunkEvent>,
+) {
+    let mut chunk_updates = Vec::new();
+    let mut grown_blocks = Vec::new();
+
+    for ev in evr_gen_tree.read() {
+        let root_chunk = chunk_pos_from_global(ev.root);
+        for (position, block) in grow_tree(**seed, ev.root, ev.species) {
+            let chunk_pos = chunk_pos_from_global(position);
+            let block_pos = block_pos_from_global(position);
+            // Players may have changed the rest of the tree since it first grew.
+            if ev.regenerated && chunk_pos != root_chunk {
+                continue
+            }
+
+            if let Some(chunk) = chunk_map.get_mut(&chunk_pos) {
+                // Saved chunks already have whatever part of the tree they were ever going to get.
+                if !chunk.generated {
+                    continue
+                }
+                if let Some(merged) = merge_tree_block(chunk.blocks[block_pos], block) {
+                    chunk.blocks[block_pos] = merged;
+                    // The root's own chunk grows the tree again whenever it's regenerated, the others need saving to keep their part.
+                    if chunk_pos != root_chunk {
+                        chunk.mark_modified();
+                    }
+                    grown_blocks.push(position);
+                }
+            }
+            else {
+                merge_pending_tree_block(&mut pending_map.get_or_insert(chunk_pos, &database.lock().unwrap(), &palette)[block_pos], block);
+            }
+
+            for event in update_chunk_events_from_global(position) {
+                if !chunk_updates.contains(&event) {
+                    chunk_updates.push(event);
+                }
+            }
+        }
+    }
+
+    for event in relight(&grown_blocks, &mut chunk_map, &block_registry) {
+        if !chunk_updates.contains(&event) {
+            chunk_updates.push(event);
+        }
+    }
+    for event in chunk_updates {
+        evw_update_chunk.send(event);
+    }
+
+    if let Err(err) = pending_map.spill(&database.lock().unwrap(), &palette) {
+        error!("Failed to spill pending modifications: {}", err);
+    }
+}
+
+// Helpers
+/// Every block of the tree growing from `root`, logs first. The same seed, root and species always give the same tree.
+pub fn grow_tree (seed: u32, root: IVec3, species: TreeSpecies) -> Vec<(IVec3, Block)> {
+    let shape = species.get_attributes();
+    let mut rng = tree_rng(seed, root, 0);
+    let mut logs = Vec::new();
+    let mut leaves = Vec::new();
+
+    let height = rng.i32(shape.height.clone());
+    let top = root + IVec3::Y * (height - 1);
+    logs.extend((0..height).map(|y| root + IVec3::Y * y));
+
+    if shape.branch_length > 0 {
+        for y in height / 2..height - 1 {
+            if rng.f32() >= shape.branchiness {
+                continue
+            }
+            let direction = BRANCH_DIRECTIONS[rng.usize(..BRANCH_DIRECTIONS.len())];
+            let mut point = root + IVec3::Y * y;
+            for step in 1..=rng.i32(1..=shape.branch_length) {
+                point += direction;
+                // Branches reach up a little as they go out.
+                if step % 2 == 0 {
+                    point += IVec3::Y;
+                }
+                logs.push(point);
+            }
+            leaves.extend(blob(point, 1, &mut rng));
+        }
+    }
+
+    match shape.canopy {
+        Canopy::Round { radius } => leaves.extend(blob(top + IVec3::Y, radius, &mut rng)),
+        Canopy::Cone { radius } => {
+            let rings = height * 2 / 3;
+            for ring in 0..rings {
+                // Every other ring is a bit thinner, so it looks layered instead of like a solid cone.
+                let ring_radius = (radius * (ring + 1) / rings - ring % 2).max(1);
+                let center = top - IVec3::Y * ring;
+                for (x, z) in itertools::iproduct!(-ring_radius..=ring_radius, -ring_radius..=ring_radius) {
+                    if x * x + z * z <= ring_radius * ring_radius {
+                        leaves.push(center + IVec3::new(x, 0, z));
+                    }
+                }
+            }
+            leaves.push(top + IVec3::Y);
+        },
+        Canopy::Droopy { radius, droop } => {
+            let center = top + IVec3::Y;
+            for (x, z) in itertools::iproduct!(-radius..=radius, -radius..=radius) {
+                let distance = x * x + z * z;
+                if distance > radius * radius {
+                    continue
+                }
+                leaves.push(center + IVec3::new(x, 0, z));
+                if distance <= (radius - 1) * (radius - 1) {
+                    leaves.push(center + IVec3::new(x, 1, z));
+                }
+                // Only the edge hangs down.
+                else if rng.f32() < 0.6 {
+                    for y in 1..=rng.i32(1..=droop) {
+                        leaves.push(center + IVec3::new(x, -y, z));
+                    }
+                }
+            }
+        },
+    }
+
+    // Logs win over leaves wherever they overlap.
+    let mut blocks = IndexMap::new();
+    for position in logs {
+        blocks.insert(position, Block::new(BlockID::Log).with_data(BlockData::Tree));
+    }
+    for position in leaves {
+        blocks.entry(position).or_insert(Block::new(BlockID::Leaves).with_data(BlockData::Tree));
+    }
+    blocks.into_iter().collect()
+}
+
+/// What a tree growing `grown` into a spot holding `existing` leaves there, if it changes anything.
+/// Trees only grow into air, and their logs push out other trees' leaves. Doing it this way round means it doesn't matter which tree grows first.
+/// Structures always win over trees, so anything one has built, its air included, is left alone.
+pub fn merge_tree_block (existing: Block, grown: Block) -> Option<Block> {
+    if existing.data == BlockData::Structure {
+        return None
+    }
+    let replaceable = existing.id == BlockID::Air || (existing.id == BlockID::Leaves && existing.data.is_tree() && grown.id == BlockID::Log);
+    replaceable.then_some(grown)
+}
+
+/// [`merge_tree_block`] for chunks that haven't been generated yet. Blocks that have to be there no matter what, like parts of structures, are left alone.
+/// Merging the same tree in twice changes nothing, so growing one again from a regenerated chunk is harmless.
+pub fn merge_pending_tree_block (modification: &mut PendingModification, grown: Block) {
+    if !modification.yield_to_terrain {
+        return
+    }
+    if let Some(merged) = merge_tree_block(modification.block, grown) {
+        modification.block = merged;
+    }
+}
+
+/// Leaves in a rough ball around `center`. The edge is a little ragged.
+fn blob (center: IVec3, radius: i32, rng: &mut Rng) -> Vec<IVec3> {
+    let mut leaves = Vec::new();
+    for (x, y, z) in itertools::iproduct!(-radius..=radius, -radius..=radius, -radius..=radius) {
+        let distance = x * x + y * y + z * z;
+        if distance < radius * radius || (distance <= radius * radius + 1 && rng.bool()) {
+            leaves.push(center + IVec3::new(x, y, z));
+        }
+    }
+    leaves
+}
+
+/// Randomness for the tree at `root`, mixed up enough that trees next to each other don't look alike.
+fn tree_rng (seed: u32, root: IVec3, salt: u64) -> Rng {
+    let mut hash = seed as u64;
+    for value in [root.x as u64, root.y as u64, root.z as u64, salt] {
+        hash = (hash ^ value).wrapping_mul(0x9E37_79B9_7F4A_7C15);
+        hash ^= hash >> 31;
+    }
+    Rng::with_seed(hash)
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::{math::IVec3, utils::HashMap};
+    use itertools::iproduct;
+
+    use crate::{chunk_pos_from_global, Block, BlockID, PendingModification, CHUNK_SIZE};
+
+    use super::*;
+
+    const SEED: u32 = 2343;
+    /// Trees close enough together and to chunk borders that they grow into each other and across chunks.
+    const TREES: [(IVec3, TreeSpecies); 6] = [
+        (IVec3::new(14, 1, 14), TreeSpecies::Oak),
+        (IVec3::new(16, 1, 15), TreeSpecies::Willow),
+        (IVec3::new(17, 1, 12), TreeSpecies::Pine),
+        (IVec3::new(-1, 1, 2), TreeSpecies::Birch),
+        (IVec3::new(1, 1, 0), TreeSpecies::Oak),
+        (IVec3::new(15, 1, -2), TreeSpecies::Pine),
+    ];
+
+    /// Loads the chunks in `order` the way the game does. Each one gets its terrain, then its pending modifications,
+    /// then grows its own trees into whichever chunks are loaded so far, leaving pending modifications for the rest.
+    fn load_world(order: &[IVec3]) -> HashMap<IVec3, Block> {
+        let mut world = HashMap::<IVec3, Block>::new();
+        let mut pending = HashMap::<IVec3, PendingModification>::new();
+        let mut loaded = Vec::new();
+
+        for chunk_pos in order {
+            for (x, y, z) in iproduct!(0..CHUNK_SIZE, 0..CHUNK_SIZE, 0..CHUNK_SIZE) {
+                let position = *chunk_pos * CHUNK_SIZE + IVec3::new(x, y, z);
+                let mut block = Block::new(if position.y <= 0 { BlockID::Stone } else { BlockID::Air });
+                if let Some(modification) = pending.remove(&position) {
+                    modification.apply_to(&mut block);
+                }
+                world.insert(position, block);
+            }
+            loaded.push(*chunk_pos);
+
+            for (root, species) in TREES.iter().filter(|(root, _)| chunk_pos_from_global(*root) == *chunk_pos) {
+                for (position, block) in grow_tree(SEED, *root, *species) {
+                    if loaded.contains(&chunk_pos_from_global(position)) {
+                        let existing = world[&position];
+                        if let Some(merged) = merge_tree_block(existing, block) {
+                            world.insert(position, merged);
+                        }
+                    }
+                    else {
+                        merge_pending_tree_block(pending.entry(position).or_default(), block);
+                    }
+                }
+            }
+        }
+
+        world
+    }
+
+    #[test]
+    fn load_order() {
+        let mut chunks = iproduct!(-1..=1, 0..=1, -1..=1).map(|(x, y, z)| IVec3::new(x, y, z)).collect::<Vec<_>>();
+        let expected = load_world(&chunks);
+
+        chunks.reverse();
+        assert!(load_world(&chunks) == expected, "trees grew differently when loading chunks in reverse");
+
+        let mut rng = Rng::with_seed(7);
+        for _ in 0..8 {
+            rng.shuffle(&mut chunks);
+            assert!(load_world(&chunks) == expected, "trees grew differently when loading chunks in the order {:?}", chunks);
+        }
+    }
+
+    #[test]
+    fn same_tree_every_time() {
+        for (root, species) in TREES {
+            assert!(grow_tree(SEED, root, species) == grow_tree(SEED, root, species));
+        }
+    }
+}
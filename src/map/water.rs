@@ -0,0 +1,87 @@
+use bevy::prelude::*;
+
+use crate::{block_pos_from_global, chunk_pos_from_global, point::GridPoint, Block, BlockID, ChunkMap};
+
+/// How far water can spread sideways from a source before it runs out.
+pub const MAX_FLOW_DISTANCE: u8 = 7;
+/// How many positions water gets settled at per tick. Anything past this waits for the next tick.
+pub const MAX_WATER_UPDATES: usize = 256;
+
+// Water can't be damaged, so its `damage` holds how it's flowing instead.
+// The low three bits are the distance from the nearest source, and this bit is set for anything that isn't a source.
+const FLOWING_BIT: u8 = 0x08;
+const FLOW_DISTANCE_MASK: u8 = 0x07;
+
+impl Block {
+    /// Water that came from somewhere else, `distance` blocks away from its source. Falling water has a distance of 0.
+    pub fn flowing_water(distance: u8) -> Block {
+        Block { damage: FLOWING_BIT | distance.min(MAX_FLOW_DISTANCE), ..Block::new(BlockID::Water) }
+    }
+
+    pub fn is_water_source(&self) -> bool {
+        self.id == BlockID::Water && self.damage & FLOWING_BIT == 0
+    }
+
+    pub fn flow_distance(&self) -> u8 {
+        self.damage & FLOW_DISTANCE_MASK
+    }
+
+    /// Whether water can flow into this block, replacing it. Sources never get replaced.
+    pub fn can_hold_water(&self) -> bool {
+        self.id == BlockID::Air || (self.id == BlockID::Water && !self.is_water_source())
+    }
+
+    /// Height of the water's surface within its block. Sources and falling water are full.
+    pub fn water_height(&self) -> f32 {
+        if self.is_water_source() {
+            1.0
+        }
+        else {
+            (MAX_FLOW_DISTANCE + 1 - self.flow_distance()) as f32 / (MAX_FLOW_DISTANCE + 1) as f32
+        }
+    }
+}
+
+// Helpers
+/// Works out what should be at `position`, given the water around it. Returns the block it should become, or `None` if it should stay as it is.
+/// Water falls into anything below it, and spreads sideways off of whatever it's sitting on, getting shallower the further it goes.
+/// Flowing water with nothing left feeding it drains away.
+pub fn settle_water (position: IVec3, chunk_map: &ChunkMap) -> Option<Block> {
+    let block = block_at(position, chunk_map)?;
+    if !block.can_hold_water() {
+        return None
+    }
+
+    let settled = if block_at(position.up(1), chunk_map).map_or(false, |above| above.id == BlockID::Water) {
+        Block::flowing_water(0)
+    }
+    else {
+        let mut nearest = None;
+        for adj in [position.north(1), position.south(1), position.east(1), position.west(1)] {
+            let Some(adj_block) = block_at(adj, chunk_map) else {
+                continue
+            };
+            if adj_block.id != BlockID::Water || adj_block.flow_distance() >= MAX_FLOW_DISTANCE {
+                continue
+            }
+            // Water only spreads sideways once it has something to sit on. Chunks that aren't loaded count as solid.
+            if block_at(adj.down(1), chunk_map).map_or(false, |below| below.can_hold_water()) {
+                continue
+            }
+
+            let distance = adj_block.flow_distance() + 1;
+            nearest = Some(nearest.map_or(distance, |nearest: u8| nearest.min(distance)));
+        }
+
+        match nearest {
+            Some(distance) => Block::flowing_water(distance),
+            None => Block::new(BlockID::Air),
+        }
+    };
+
+    (settled.id != block.id || settled.damage != block.damage).then_some(settled)
+}
+
+fn block_at (position: IVec3, chunk_map: &ChunkMap) -> Option<Block> {
+    chunk_map.get(&chunk_pos_from_global(position)).map(|chunk| chunk.blocks[block_pos_from_global(position)])
+}
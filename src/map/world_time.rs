@@ -0,0 +1,60 @@
+use std::f64::consts::TAU;
+
+use bevy::prelude::*;
+
+/// Real seconds in one in-game day, unless something else is configured.
+pub const DEFAULT_DAY_LENGTH: f32 = 1200.0;
+/// New worlds start shortly after sunrise.
+pub const NEW_WORLD_TIME: f64 = 0.3;
+/// How far below the horizon the sun can be while it's still a bit light out, as a fraction of its full height.
+const TWILIGHT: f32 = 0.2;
+
+// Resources
+/// How long the world has been going, in days. The fractional part is the time of day, where 0 is midnight and 0.5 is noon.
+/// Inserted with the default day length by [`MapPlugin`](crate::MapPlugin), insert it before that to use a different one.
+#[derive(Clone, Copy, Debug, Resource)]
+pub struct WorldTime {
+    pub days: f64,
+    /// Real seconds in one day.
+    pub day_length: f32,
+}
+impl Default for WorldTime {
+    fn default() -> Self {
+        Self { days: NEW_WORLD_TIME, day_length: DEFAULT_DAY_LENGTH }
+    }
+}
+impl WorldTime {
+    /// How far through the current day we are, from 0 to 1.
+    pub fn time_of_day(&self) -> f32 {
+        self.days.fract() as f32
+    }
+
+    /// How many full days have gone by.
+    pub fn day(&self) -> u64 {
+        self.days.floor() as u64
+    }
+
+    /// Height of the sun, from -1 at midnight to 1 at noon.
+    pub fn sun_height(&self) -> f32 {
+        -(self.days.fract() * TAU).cos() as f32
+    }
+
+    /// How much sunlight there is, from 0 at night to 1 during the day. Fades in and out around sunrise and sunset.
+    pub fn daylight(&self) -> f32 {
+        ((self.sun_height() + TWILIGHT) / (TWILIGHT * 2.0)).clamp(0.0, 1.0)
+    }
+
+    pub fn is_night(&self) -> bool {
+        self.daylight() < 0.5
+    }
+}
+
+// Systems
+pub fn advance_world_time (
+    mut world_time: ResMut<WorldTime>,
+
+    time: Res<Time>,
+) {
+    let day_length = world_time.day_length.max(f32::EPSILON) as f64;
+    world_time.days += time.delta_seconds_f64() / day_length;
+}
@@ -36,6 +36,10 @@ impl DeathEvent {
 #[derive(Component, Clone, Copy, Deref, DerefMut)]
 pub struct HasAir(pub bool);
 
+/// Entities that are gone for good when they die, instead of being revived at spawn.
+#[derive(Component, Clone, Copy)]
+pub struct DespawnOnDeath;
+
 //#[derive(Component, Clone, Deref, DerefMut)]
 //pub struct InvincibilityTimer(Timer);
 
@@ -51,6 +55,7 @@ pub enum EffectCause {
     Drowning,
     Fall,
     Revive,
+    Attack,
 }
 
 pub fn handle_breath (
@@ -103,11 +108,19 @@ pub fn handle_death (
     mut commands: Commands,
 
     query: Query<(&Stats)>,
+    despawn_query: Query<(), With<DespawnOnDeath>>,
 
     mut evr_death: EventReader<DeathEvent>,
     mut evw_stat_change: EventWriter<StatChangeEvent>,
 ) {
     for ev in evr_death.read() {
+        if despawn_query.contains(ev.entity) {
+            if let Some(entity) = commands.get_entity(ev.entity) {
+                entity.despawn_recursive();
+            }
+            continue
+        }
+
         // TODO: Handle dropping of the players resources here
         // TODO: Add some kind of sound effect for dying.
         // TODO: Perhaps some sort of respawn dialogue before respawning the player? Unsure. Perhaps some timer...
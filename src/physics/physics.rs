@@ -1,7 +1,7 @@
 use bevy::{prelude::*, utils::HashSet};
 use itertools::{iproduct, izip};
 
-use crate::{block_pos_from_global, chunk_pos_from_global, movement::Crouched, BlockID, Chunk, ChunkMap, HasAir, Solidity};
+use crate::{block_pos_from_global, blocks::BlockRegistry, chunk_pos_from_global, movement::Crouched, BlockID, Chunk, ChunkMap, HasAir, Solidity};
 
 pub const BLOCK_AABB: AabbCollider = AabbCollider{ width: 1.0, height: 1.0, length: 1.0 };
 
@@ -48,6 +48,7 @@ pub fn do_physics (
 
     time: Res<Time>,
     chunk_map: Res<ChunkMap>,
+    block_registry: Res<BlockRegistry>,
     mut evr_fall: EventWriter<FallEvent>,
 ) {
     let dist_bf_collision_calc = |dist_bf_collision: Vec3, distance: Vec3| -> Vec3 {
@@ -102,7 +103,7 @@ pub fn do_physics (
                     let block_position = block_pos_from_global(global_block_position);
 
                     if let Some(chunk) = chunk_map.get(&chunk_position) {
-                        match chunk.blocks[block_position].get_attributes().solidity {
+                        match block_registry[chunk.blocks[block_position].id].solidity {
                             Solidity::Solid => {
                                 let (penetration, normal) = collider.get_penetration_and_normal(transform.translation, BLOCK_AABB, global_block_position.as_vec3());
                                 if normal != Vec3::ZERO {
@@ -231,8 +232,8 @@ pub fn do_physics (
 
                             if surface_contact == SurfaceContact::PosY || surface_contact == SurfaceContact::NegY {
                                 if let Some(id) = collision.id {
-                                    applied_slip.x = id.get_attributes().slip.x;
-                                    applied_slip.z = id.get_attributes().slip.z;
+                                    applied_slip.x = block_registry[id].slip.x;
+                                    applied_slip.z = block_registry[id].slip.z;
                                 }
                                 else {
                                     applied_slip.x = 0.0;
@@ -241,7 +242,7 @@ pub fn do_physics (
                             }
                             else {
                                 if let Some(id) = collision.id {
-                                    applied_slip.y = id.get_attributes().slip.y;
+                                    applied_slip.y = block_registry[id].slip.y;
                                 }
                                 else {
                                     applied_slip.y = 0.0;
@@ -389,7 +390,7 @@ impl BlockCollision {
 /// From 0 to 1. Anything else will result in ?strange? behavior.
 #[derive(Component, Clone, Copy, Debug, Reflect, Deref, DerefMut)]
 #[reflect(Component)]
-pub struct Slip(Vec3);
+pub struct Slip(pub Vec3);
 impl Default for Slip {
     fn default() -> Self {
         Self(Vec3::new(0.015, 1.0, 0.015))
@@ -445,6 +446,24 @@ impl AabbCollider {
         self_max.z >= other_min.z
     }
 
+    /// How far along the ray it first touches this collider, if it touches it at all. `direction` should be normalized.
+    pub fn get_ray_intersection(&self, position: Vec3, origin: Vec3, direction: Vec3) -> Option<f32> {
+        let half_size = Vec3::new(self.width, self.height, self.length) / 2.0;
+
+        let t_min = (position - half_size - origin) / direction;
+        let t_max = (position + half_size - origin) / direction;
+
+        let near = t_min.min(t_max).max_element();
+        let far = t_min.max(t_max).min_element();
+
+        if near <= far && far >= 0.0 {
+            Some(near.max(0.0))
+        }
+        else {
+            None
+        }
+    }
+
     pub fn get_penetration_and_normal(&self, position: Vec3, other_aabb: AabbCollider, other_position: Vec3) -> (f32, Vec3) {
         let self_min = position - Vec3::new(self.width, self.height, self.length) / 2.0;
         let self_max = position + Vec3::new(self.width, self.height, self.length) / 2.0;
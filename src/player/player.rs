@@ -12,7 +12,7 @@ use leafwing_input_manager::input_mocking::QueryInput;
 use crate::hotbar::Hotbar;
 use crate::movement::{Crouched, MovementAction, MovementType};
 use crate::point::Point3d;
-use crate::{Action, BuildingEvent, MiningEvent, PLAYER_HEIGHT};
+use crate::{crafting::CraftingOpen, Action, BuildingEvent, InteractEvent, MiningEvent, OpenCrate, PLAYER_HEIGHT};
 
 //use crate::rendering::window::WindowChangeEvent;
 
@@ -26,18 +26,21 @@ pub struct Player;
 // Systems
 /// Player input.
 pub fn player_input_game (
+    mut commands: Commands,
+
     //query: Query<(Entity, &ActionState<Action>, &MovementAcceleration, &JumpImpulse, &mut LinearVelocity, Has<Grounded>,), (With<Player>)>,
-    mut query: Query<(Entity, &ActionState<Action>, &mut Transform, &Children, Option<&mut Hotbar>, Option<&mut Crouched>), (With<Player>)>,
+    mut query: Query<(Entity, &ActionState<Action>, &mut Transform, &Children, Option<&mut Hotbar>, Option<&mut Crouched>, Option<&OpenCrate>, Has<CraftingOpen>), (With<Player>)>,
     mut cam_query: Query<(&mut Transform), (Without<Player>)>,
     
     mut evw_movement: EventWriter<MovementAction>,
     mut evw_mining: EventWriter<MiningEvent>,
     mut evw_building: EventWriter<BuildingEvent>,
+    mut evw_interact: EventWriter<InteractEvent>,
 
     mut primary_window: Query<&mut Window, With<PrimaryWindow>>
 ) {
     // TODO: Perhaps we should send events for movement instead of moving directly?
-    if let Ok((player, action_state, mut transform, children, opt_hotbar, opt_crouched)) = query.get_single_mut() {
+    if let Ok((player, action_state, mut transform, children, opt_hotbar, opt_crouched, opt_open_crate, crafting_open)) = query.get_single_mut() {
         //println!("{:?}", transform.translation());
         // Modified from bevy_xpbd's examples + bevy_flycam
         let forward = Vec3::from(transform.forward());
@@ -78,12 +81,37 @@ pub fn player_input_game (
 
         
         if let Ok(mut window) = primary_window.get_single_mut() {
-            if action_state.just_pressed(&Action::MenuBack) {
+            // The mouse belongs to the crate's menu while it's open.
+            if opt_open_crate.is_some() {
+                if action_state.just_pressed(&Action::MenuBack) || action_state.just_pressed(&Action::Secondary) {
+                    commands.entity(player).remove::<OpenCrate>();
+                    window.cursor.grab_mode = CursorGrabMode::Confined;
+                    window.cursor.visible = false;
+                }
+            }
+            // Same goes for the crafting menu.
+            else if crafting_open {
+                if action_state.just_pressed(&Action::MenuBack) || action_state.just_pressed(&Action::Crafting) {
+                    commands.entity(player).remove::<CraftingOpen>();
+                    window.cursor.grab_mode = CursorGrabMode::Confined;
+                    window.cursor.visible = false;
+                }
+            }
+            else if action_state.just_pressed(&Action::Crafting) {
+                commands.entity(player).insert(CraftingOpen);
+                evw_mining.send(MiningEvent { entity: player, is_start: false });
+                evw_building.send(BuildingEvent { entity: player, is_start: false });
                 window.cursor.grab_mode = CursorGrabMode::None;
                 window.cursor.visible = true;
             }
+            else if action_state.just_pressed(&Action::MenuBack) {
+                window.cursor.grab_mode = CursorGrabMode::None;
+                window.cursor.visible = true;
+            }
+
+            let in_menu = opt_open_crate.is_some() || crafting_open;
 
-            if action_state.just_pressed(&Action::Primary) {
+            if action_state.just_pressed(&Action::Primary) && !in_menu {
                 window.cursor.grab_mode = CursorGrabMode::Confined;
                 window.cursor.visible = false;
                 evw_mining.send(MiningEvent { entity: player, is_start: true });
@@ -94,8 +122,9 @@ pub fn player_input_game (
             }
 
 
-            if action_state.just_pressed(&Action::Secondary) {
+            if action_state.just_pressed(&Action::Secondary) && !in_menu {
                 evw_building.send(BuildingEvent { entity: player, is_start: true });
+                evw_interact.send(InteractEvent { entity: player });
             }
 
             if action_state.just_released(&Action::Secondary) {
@@ -1,19 +1,20 @@
-use std::mem::size_of;
+use std::{mem::size_of, sync::Arc};
 
-use bevy::prelude::*;
+use bevy::{prelude::*, tasks::{block_on, futures_lite::future, AsyncComputeTaskPool, Task}, utils::{HashMap, HashSet}};
 use bevy::render::camera::CameraProjection;
 use bevy::render::mesh::{Indices, MeshVertexAttribute, PrimitiveTopology, VertexAttributeValues};
 use bevy::render::render_asset::RenderAssetUsages;
-use bevy::render::render_resource::Face;
+use bevy::pbr::{ExtendedMaterial, MaterialExtension};
+use bevy::render::render_resource::{AsBindGroup, Face, ShaderRef};
 use bevy::render::view::{NoFrustumCulling, RenderLayers};
 use bevy::window::WindowResized;
 use bevy_asset_loader::prelude::*;
 use itertools::iproduct;
 
-use crate::{block_pos_from_global, chunk_pos_from_global, BlockID, ChunkMap, UpdateChunkEvent, BLOCK_AABB, CHUNK_SIZE};
+use crate::{block_pos_from_global, blocks::BlockRegistry, world_time::WorldTime, chunk_pos_from_global, light::{light_brightness, Light}, Block, BlockID, BlockVisibility, Chunk, ChunkMap, UpdateChunkEvent, BLOCK_AABB, CHUNK_SIZE};
 
 use block_mesh::ndshape::{ConstShape, ConstShape3u32};
-use block_mesh::{greedy_quads, visible_block_faces, GreedyQuadsBuffer, MergeVoxel, UnitQuadBuffer, UnorientedQuad, Voxel, VoxelVisibility, RIGHT_HANDED_Y_UP_CONFIG};
+use block_mesh::{greedy_quads, visible_block_faces, GreedyQuadsBuffer, MergeVoxel, OrientedBlockFace, UnitQuadBuffer, UnorientedQuad, Voxel, VoxelVisibility, RIGHT_HANDED_Y_UP_CONFIG};
 
 pub mod meshers;
 use meshers::*;
@@ -97,284 +98,173 @@ impl MergeVoxel for VisVoxel {
     }
 }
 
+/// Opaque voxel that only merges with the same block at the same damage, lighting and ambient occlusion, so a merged quad shows one repeated texture.
+#[derive(Clone, Copy, Eq, PartialEq)]
+struct BlockVoxel {
+    visibility: VoxelVisibility,
+    /// `None` for unloaded neighbors, which are never meshed.
+    block: Option<(BlockID, u8)>,
+    /// Light level in front of each face, in the order of [`RIGHT_HANDED_Y_UP_CONFIG`]'s faces.
+    light: [u8; 6],
+    /// Ambient occlusion of each face's corners, two bits per corner.
+    ao: [u8; 6],
+}
+impl BlockVoxel {
+    const EMPTY: BlockVoxel = BlockVoxel { visibility: VoxelVisibility::Empty, block: None, light: [0; 6], ao: [0; 6] };
+    const UNLOADED: BlockVoxel = BlockVoxel { visibility: VoxelVisibility::Opaque, block: None, light: [0; 6], ao: [0; 6] };
+}
+
+impl Voxel for BlockVoxel {
+    fn get_visibility(&self) -> VoxelVisibility {
+        self.visibility
+    }
+}
+
+impl MergeVoxel for BlockVoxel {
+    type MergeValue = (Option<(BlockID, u8)>, [u8; 6], [u8; 6]);
+
+    fn merge_value(&self) -> Self::MergeValue {
+        (self.block, self.light, self.ao)
+    }
+}
+
 type ChunkShape = ConstShape3u32<18, 18, 18>;
 
+/// The sky blue the game always used to have.
+const DAY_SKY_COLOR: Vec3 = Vec3::new(129.0/256.0, 194.0/256.0, 247.0/256.0);
+const NIGHT_SKY_COLOR: Vec3 = Vec3::new(0.03, 0.04, 0.1);
+const SUNSET_SKY_COLOR: Vec3 = Vec3::new(0.95, 0.55, 0.3);
+/// How high the sun can be while the sky still has some sunset color.
+const SUNSET_HEIGHT: f32 = 0.25;
+/// How much of the sunset color there is when the sun is right on the horizon.
+const SUNSET_STRENGTH: f32 = 0.6;
+/// How bright the world is drawn in the middle of the night, compared to the middle of the day.
+const NIGHT_SUN_INTENSITY: f32 = 0.3;
+/// Sun intensity has to change by this much before the materials get updated.
+const SUN_INTENSITY_STEP: f32 = 0.005;
+
+/// How much each ambient occlusion level darkens a vertex, from fully boxed in to fully open.
+const AO_BRIGHTNESS: [f32; 4] = [0.5, 0.65, 0.8, 1.0];
+
+/// Chunk material that repeats one atlas tile across a quad. See `assets/shaders/tiled_atlas.wgsl`.
+pub type TiledAtlasMaterial = ExtendedMaterial<StandardMaterial, TiledAtlas>;
+
+#[derive(Asset, AsBindGroup, Reflect, Debug, Clone)]
+pub struct TiledAtlas {
+    /// Size of one tile, in atlas UVs.
+    #[uniform(100)]
+    pub tile_size: f32,
+    /// How bright the sun is, multiplied into every vertex color. Set by [`update_sky`].
+    #[uniform(101)]
+    pub sun_intensity: f32,
+}
+impl MaterialExtension for TiledAtlas {
+    fn fragment_shader() -> ShaderRef {
+        "shaders/tiled_atlas.wgsl".into()
+    }
+
+    fn deferred_fragment_shader() -> ShaderRef {
+        "shaders/tiled_atlas.wgsl".into()
+    }
+}
+
 // systems
 pub fn update_chunk_meshes (
     mut commands: Commands,
 
     mut evr_update_chunk: EventReader<UpdateChunkEvent>,
 
-    atlas: Res<Atlas>,
     materials: Res<Materials>,
-    mut materials_assets: ResMut<Assets<StandardMaterial>>,
+    world_material: Res<WorldMaterial>,
     mut meshes: ResMut<Assets<Mesh>>,
     mut chunk_map: ResMut<ChunkMap>,
+    block_registry: Res<BlockRegistry>,
+    mut mesh_tasks: ResMut<ChunkMeshTasks>,
+    mut shared_registry: Local<Option<Arc<BlockRegistry>>>,
 ) {
-    let mut seen_events = Vec::new();
+    if shared_registry.is_none() || block_registry.is_changed() {
+        *shared_registry = Some(Arc::new(block_registry.clone()));
+    }
+    let registry = shared_registry.as_ref().unwrap();
+
+    let task_pool = AsyncComputeTaskPool::get();
+    let mut seen_events = HashSet::new();
 
     for (ev) in evr_update_chunk.read() {
-        if !chunk_map.contains_key(&**ev) {
+        if !seen_events.insert(**ev) {
             continue
         }
-        if seen_events.contains(ev) {
+
+        // Chunks already being meshed get meshed again once that's done. Restarting them instead would mean a chunk that changes every frame never gets a mesh at all.
+        if mesh_tasks.running.contains_key(&**ev) {
+            mesh_tasks.dirty.insert(**ev);
             continue
         }
+        if let Some(task) = spawn_mesh_task(**ev, &chunk_map, registry, task_pool) {
+            mesh_tasks.running.insert(**ev, task);
+        }
+    }
 
-        seen_events.push(*ev);
-
-        // TODO: We can optimize further by having "fully full" and "fully empty" precalculated and stored in the chunk_map.
-        // TODO: We can also further optimize by having voxels/water_voxels (with padding) already set up and stored in the chunk_map or something.
-        let mut voxels = [EMPTY; ChunkShape::SIZE as usize];
-        let mut voxels_fully_full = true;
-        let mut voxels_fully_empty = false;
-
-        let mut water_voxels = [EMPTY; ChunkShape::SIZE as usize];
-        let mut water_voxels_fully_full = true;
-        let mut water_voxels_fully_empty = false;
-
-        let mut translucent_voxels = [EMPTY; ChunkShape::SIZE as usize];
-        let mut translucent_voxels_fully_full = true;
-        let mut translucent_voxels_fully_empty = false;
-
-        let offset = **ev * CHUNK_SIZE;
-
-        for (x, y, z) in iproduct!(-1..=CHUNK_SIZE, -1..=CHUNK_SIZE, -1..=CHUNK_SIZE) {
-            let global_block_position = offset + IVec3::new(x, y, z);
-            let chunk_position = chunk_pos_from_global(global_block_position);
-            let block_position = block_pos_from_global(global_block_position);
-
-
-            voxels[ChunkShape::linearize([(x + 1) as u32, (y + 1) as u32, (z + 1) as u32]) as usize] = 
-            if let Some(chunk) = chunk_map.get(&chunk_position) {
-                match chunk.blocks[block_position].id {
-                                                                // TODO: We should make this use our attributes, later, once we have more blocks that are translucent.
-                    crate::BlockID::Air | crate::BlockID::Water | crate::BlockID::Leaves | crate::BlockID::Scaffold => {
-                        voxels_fully_full = false;
-                        EMPTY
-                    },
-                    _ => {
-                        voxels_fully_empty = false;
-                        FULL
-                    }
-                }
-            }
-            else {
-                voxels_fully_empty = false;
-                FULL
-            };
-
-            water_voxels[ChunkShape::linearize([(x + 1) as u32, (y + 1) as u32, (z + 1) as u32]) as usize] = 
-            if let Some(chunk) = chunk_map.get(&chunk_position) {
-                match chunk.blocks[block_position].id {
-                    crate::BlockID::Water => {
-                        water_voxels_fully_empty = false;
-                        TRANSLUCENT
-                    },
-                    _ => {
-                        water_voxels_fully_full = false;
-                        EMPTY
-                    },
-                }
-            }
-            else {
-                water_voxels_fully_empty = false;
-                FULL
-            };
-
-            translucent_voxels[ChunkShape::linearize([(x + 1) as u32, (y + 1) as u32, (z + 1) as u32]) as usize] = 
-            if let Some(chunk) = chunk_map.get(&chunk_position) {
-                match chunk.blocks[block_position].id {
-                    // TODO: We should make this use our attributes, later, once we have more blocks that are translucent.
-                    crate::BlockID::Leaves | crate::BlockID::Scaffold => {
-                        translucent_voxels_fully_empty = false;
-                        TRANSLUCENT
-                    },
-                    _ => {
-                        translucent_voxels_fully_full = false;
-                        EMPTY
-                    }
-                }
-            }
-            else {
-                translucent_voxels_fully_empty = false;
-                FULL
-            };
+    let mut finished = Vec::new();
+    mesh_tasks.running.retain(|chunk_pos, task| {
+        match block_on(future::poll_once(task)) {
+            Some(chunk_meshes) => {
+                finished.push((*chunk_pos, chunk_meshes));
+                false
+            },
+            None => true,
         }
+    });
 
-        let faces = RIGHT_HANDED_Y_UP_CONFIG.faces;
-
-        let mut generate_mesh = |voxels: [VisVoxel; ChunkShape::SIZE as usize], material: Handle<StandardMaterial>, render_entity_type: RenderEntity, stuipd: bool| {
-            let mut buffer = UnitQuadBuffer::new();
-            if stuipd {
-                naive_mesh_wrapper(
-                    &voxels,
-                    &ChunkShape {},
-                    [0; 3],
-                    [CHUNK_SIZE as u32 + 1, CHUNK_SIZE as u32 + 1, CHUNK_SIZE as u32 + 1],
-                    &faces,
-                    &mut buffer,
-                );
-            }
-            else {
-                visible_block_faces(
-                    &voxels,
-                    &ChunkShape {},
-                    [0; 3],
-                    [CHUNK_SIZE as u32 + 1, CHUNK_SIZE as u32 + 1, CHUNK_SIZE as u32 + 1],
-                    &faces,
-                    &mut buffer,
-                );
-            }
-            
-    
-            //println!("unitquadbuffer: {:?}", buffer.groups.clone());
-    
-            let num_indices = buffer.num_quads() * 6;
-            let num_vertices = buffer.num_quads() * 4;
-            let mut indices = Vec::with_capacity(num_indices);
-            let mut positions = Vec::with_capacity(num_vertices);
-            let mut normals = Vec::with_capacity(num_vertices);
-            let mut uvs = Vec::with_capacity(num_vertices);
-            for (group, face) in buffer.groups.into_iter().zip(faces.into_iter()) {
-                for quad in group.into_iter() {
-                    indices.extend_from_slice(&face.quad_mesh_indices(positions.len() as u32));
-                    positions.extend_from_slice(&face.quad_mesh_positions(&quad.into(), 1.0));
-                    normals.extend_from_slice(&face.quad_mesh_normals());
-    
-                    let block = chunk_map[&**ev].blocks[UVec3::from(quad.minimum) - UVec3::new(1, 1, 1)];
-                    let attributes = block.get_attributes();
-                    let normal = face.signed_normal();
-    
-                    let mut tex_coord = if normal.x == 1 {attributes.tex_coords.east}
-                                 else if normal.x == -1 {attributes.tex_coords.west}
-                                 else if normal.y == 1 {attributes.tex_coords.top}
-                                 else if normal.y == -1 {attributes.tex_coords.bottom}
-                                 else if normal.z == 1 {attributes.tex_coords.north}
-                                 else {attributes.tex_coords.south};
-    
-                    tex_coord.x += block.damage as i32;
-    
-                    
-                    let quad_uvs = face.tex_coords(RIGHT_HANDED_Y_UP_CONFIG.u_flip_face, true, &UnorientedQuad::from(quad)).map(|uv| {
-                        let mut u = uv[0] * 8.0;
-                        let mut v = uv[1] * 8.0;
-                        //if u != 0.0 {u -= 1.0};
-                        //if v != 0.0 {v -= 1.0};
-                        u += tex_coord.x as f32 * 8.0;
-                        v += tex_coord.y as f32 * 8.0;
-                        [u/256.0, v/256.0]
-                    });
-    
-                    uvs.extend_from_slice(&quad_uvs);
-                }
-            }
-    
-            // TODO: Should we maybe set this to RENDER_WORLD only instead?
-            let mut render_mesh = Mesh::new(PrimitiveTopology::TriangleList, RenderAssetUsages::MAIN_WORLD | RenderAssetUsages::RENDER_WORLD);
-            render_mesh.insert_attribute(
-                Mesh::ATTRIBUTE_POSITION,
-                VertexAttributeValues::Float32x3(positions),
-            );
-            render_mesh.insert_attribute(
-                Mesh::ATTRIBUTE_NORMAL,
-                VertexAttributeValues::Float32x3(normals),
-            );
-            render_mesh.insert_attribute(
-                Mesh::ATTRIBUTE_UV_0,
-                VertexAttributeValues::Float32x2(uvs),
-            );
-            render_mesh.insert_indices(Indices::U32(indices.clone()));
-    
-            render_mesh.translate_by(Vec3::new(-1.5, -1.5, -1.5));
-    
-            let mesh_handle = meshes.add(render_mesh.clone());
-    
-            // TODO: Use insert_unique to insert a PbrBundle once insert_unique is available.
-            /*
-            commands.entity(entity).insert(PbrBundle{
-                mesh: mesh_handle,
-                material: materials.add(material),
-    
-                //visibility: Visibility::Visible,
-                //inherited_visibility: InheritedVisibility::VISIBLE,
-    
-                ..default()
-                /*
-                global_transform: todo!(),
-                view_visibility: todo!(),
-                 */
-            });
-            */
-    
-            if render_mesh.count_vertices() != 0 {
-                let render_entity_bundle = (
-                    Transform::from_translation((**ev * CHUNK_SIZE).as_vec3()),
-                    GlobalTransform::default(),
-                    mesh_handle,
-                    material,
-                    Visibility::default(),
-                    InheritedVisibility::default(),
-                    ViewVisibility::default(),
-                    // TODO: This is a bandaid fix. Bevy isn't frustum culling correctly and we should properly fix it instead of just disabling it. Oh well.
-                    NoFrustumCulling,
-                );
-
-                // This code fucking sucks.
-                if let Some(render_entity) = match render_entity_type {
-                    RenderEntity::World => chunk_map.get_mut(&**ev).unwrap().render_entity,
-                    RenderEntity::Water => chunk_map.get_mut(&**ev).unwrap().water_render_entity,
-                    RenderEntity::Translucent => chunk_map.get_mut(&**ev).unwrap().translucent_render_entity,
-                } {
-                    commands.entity(render_entity).insert(render_entity_bundle);
-                }
-                else {
-                    match render_entity_type {
-                        RenderEntity::World => chunk_map.get_mut(&**ev).unwrap().render_entity = Some(commands.spawn(render_entity_bundle).id()),
-                        RenderEntity::Water => chunk_map.get_mut(&**ev).unwrap().water_render_entity = Some(commands.spawn(render_entity_bundle).id()),
-                        RenderEntity::Translucent => chunk_map.get_mut(&**ev).unwrap().translucent_render_entity = Some(commands.spawn(render_entity_bundle).id()),
-                    }
-                }
+    for (chunk_pos, chunk_meshes) in finished {
+        // The chunk may have been unloaded while it was being meshed.
+        if let Some(chunk) = chunk_map.get_mut(&chunk_pos) {
+            if let Some(mesh) = chunk_meshes.world {
+                apply_chunk_mesh(&mut commands, &mut meshes, chunk_pos, RenderEntity::World.of(chunk), mesh, world_material.0.clone());
             }
-            else {
-                if let Some(render_entity) = match render_entity_type {
-                    RenderEntity::World => chunk_map.get_mut(&**ev).unwrap().render_entity,
-                    RenderEntity::Water => chunk_map.get_mut(&**ev).unwrap().water_render_entity,
-                    RenderEntity::Translucent => chunk_map.get_mut(&**ev).unwrap().translucent_render_entity,
-                } {
-                    commands.entity(render_entity).despawn_recursive();
-
-                    match render_entity_type {
-                        RenderEntity::World => chunk_map.get_mut(&**ev).unwrap().render_entity = None,
-                        RenderEntity::Water => chunk_map.get_mut(&**ev).unwrap().water_render_entity = None,
-                        RenderEntity::Translucent => chunk_map.get_mut(&**ev).unwrap().translucent_render_entity = None,
-                    }
+            for (render_entity_type, mesh, material) in [
+                (RenderEntity::Water, chunk_meshes.water, &materials.water_res_8x8),
+                (RenderEntity::Translucent, chunk_meshes.translucent, &materials.translucent_res_8x8),
+            ] {
+                if let Some(mesh) = mesh {
+                    apply_chunk_mesh(&mut commands, &mut meshes, chunk_pos, render_entity_type.of(chunk), mesh, material.clone());
                 }
             }
-        };
-
-        if !voxels_fully_empty || !voxels_fully_full {
-            generate_mesh(voxels, materials.world_res_8x8.clone(), RenderEntity::World, false);
-        }
-        
-        if !water_voxels_fully_empty || !water_voxels_fully_full {
-            generate_mesh(water_voxels, materials.water_res_8x8.clone(), RenderEntity::Water, false);
         }
 
-        if !translucent_voxels_fully_empty || !translucent_voxels_fully_full {
-            generate_mesh(translucent_voxels, materials.translucent_res_8x8.clone(), RenderEntity::Translucent, true);
+        if mesh_tasks.dirty.remove(&chunk_pos) {
+            if let Some(task) = spawn_mesh_task(chunk_pos, &chunk_map, registry, task_pool) {
+                mesh_tasks.running.insert(chunk_pos, task);
+            }
         }
-         
     }
 }
 
+/// Starts meshing a chunk as it is right now, if it's loaded.
+fn spawn_mesh_task (chunk_pos: IVec3, chunk_map: &ChunkMap, registry: &Arc<BlockRegistry>, task_pool: &AsyncComputeTaskPool) -> Option<Task<ChunkMeshes>> {
+    let snapshot = ChunkSnapshot::new(chunk_pos, chunk_map)?;
+    let registry = registry.clone();
+    Some(task_pool.spawn(async move {
+        mesh_chunk(&snapshot, &registry)
+    }))
+}
+
 pub fn modify_materials (
+    mut commands: Commands,
+
     materials: Res<Materials>,
     mut material_assets: ResMut<Assets<StandardMaterial>>,
+    mut tiled_material_assets: ResMut<Assets<TiledAtlasMaterial>>,
 ) {
     if let Some(world_res_8x8) = material_assets.get_mut(&materials.world_res_8x8) {
         world_res_8x8.unlit = true;
         world_res_8x8.alpha_mode = AlphaMode::Opaque;
+
+        let world_material = tiled_material_assets.add(TiledAtlasMaterial {
+            base: world_res_8x8.clone(),
+            extension: TiledAtlas { tile_size: 8.0/256.0, sun_intensity: 1.0 },
+        });
+        commands.insert_resource(WorldMaterial(world_material));
     }
 
     if let Some(water_res_8x8) = material_assets.get_mut(&materials.water_res_8x8) {
@@ -390,6 +280,13 @@ pub fn modify_materials (
         translucent_res_8x8.cull_mode = None;
         translucent_res_8x8.double_sided = true;
     }
+
+    if let Some(items_8x8) = material_assets.get_mut(&materials.items_8x8) {
+        items_8x8.unlit = true;
+        items_8x8.alpha_mode = AlphaMode::Mask(0.5);
+        items_8x8.cull_mode = None;
+        items_8x8.double_sided = true;
+    }
 }
 
 pub fn update_water_material (
@@ -429,11 +326,492 @@ pub fn update_water_material (
     }
 }
 
+/// Colors the sky and dims the world to match the time of day.
+/// Sky and block light are already mixed together in the vertex colors, so the sun dims both of them alike. It never goes all the way dark so that torchlit places stay visible at night.
+pub fn update_sky (
+    mut clear_color: ResMut<ClearColor>,
+    mut material_assets: ResMut<Assets<StandardMaterial>>,
+    mut tiled_material_assets: ResMut<Assets<TiledAtlasMaterial>>,
+    world_time: Res<WorldTime>,
+    materials: Res<Materials>,
+    world_material: Option<Res<WorldMaterial>>,
+
+    mut applied_intensity: Local<Option<f32>>,
+) {
+    let daylight = world_time.daylight();
+    // A bit of orange while the sun is close to the horizon.
+    let dusk = (1.0 - world_time.sun_height().abs() / SUNSET_HEIGHT).max(0.0);
+    let sky = NIGHT_SKY_COLOR.lerp(DAY_SKY_COLOR, daylight).lerp(SUNSET_SKY_COLOR, dusk * SUNSET_STRENGTH);
+    clear_color.0 = Color::rgb(sky.x, sky.y, sky.z);
+
+    // Changing materials makes them get prepared again, so only bother when the difference can be seen.
+    let intensity = NIGHT_SUN_INTENSITY + (1.0 - NIGHT_SUN_INTENSITY) * daylight;
+    if applied_intensity.map_or(false, |applied| (applied - intensity).abs() < SUN_INTENSITY_STEP) {
+        return
+    }
+    *applied_intensity = Some(intensity);
+
+    if let Some(material) = world_material.and_then(|world_material| tiled_material_assets.get_mut(&world_material.0)) {
+        material.extension.sun_intensity = intensity;
+    }
+    for handle in [&materials.water_res_8x8, &materials.translucent_res_8x8, &materials.items_8x8] {
+        if let Some(material) = material_assets.get_mut(handle) {
+            material.base_color = Color::rgb(intensity, intensity, intensity);
+        }
+    }
+}
+
+// Helpers
+/// Meshes a chunk from a snapshot. Runs on a task, so it can't touch the world.
+pub fn mesh_chunk (snapshot: &ChunkSnapshot, block_registry: &BlockRegistry) -> ChunkMeshes {
+    // TODO: We can optimize further by having "fully full" and "fully empty" precalculated and stored in the chunk_map.
+    let mut voxels = [BlockVoxel::EMPTY; ChunkShape::SIZE as usize];
+    let mut voxels_fully_full = true;
+    let mut voxels_fully_empty = false;
+
+    let mut water_voxels = [EMPTY; ChunkShape::SIZE as usize];
+    let mut water_voxels_fully_full = true;
+    let mut water_voxels_fully_empty = false;
+
+    let mut translucent_voxels = [EMPTY; ChunkShape::SIZE as usize];
+    let mut translucent_voxels_fully_full = true;
+    let mut translucent_voxels_fully_empty = false;
+
+    let faces = RIGHT_HANDED_Y_UP_CONFIG.faces;
+    let face_normals = faces.map(|face| face.signed_normal().to_array());
+
+    // Only opaque blocks cast ambient occlusion. Unloaded ones don't, since we don't know what's there.
+    let occluders = snapshot.blocks.iter()
+        .map(|block| block.map_or(false, |block| block_registry[block.id].visibility == BlockVisibility::Opaque))
+        .collect::<Vec<bool>>();
+
+    for (i, block) in snapshot.blocks.iter().enumerate() {
+        // Blocks in chunks that aren't loaded are treated as full, so we don't mesh faces against them.
+        let visibility = block.map(|block| block_registry[block.id].visibility);
+
+        voxels[i] = match (block, visibility) {
+            (Some(block), Some(BlockVisibility::Opaque)) => {
+                voxels_fully_empty = false;
+                let position = ChunkShape::delinearize(i as u32);
+                BlockVoxel {
+                    visibility: VoxelVisibility::Opaque,
+                    block: Some((block.id, block.damage)),
+                    light: snapshot.face_light_levels(i, &face_normals),
+                    ao: faces.map(|face| {
+                        let ao = vertex_ao(&occluders, &face, position);
+                        ao[0] | ao[1] << 2 | ao[2] << 4 | ao[3] << 6
+                    }),
+                }
+            },
+            (None, _) => {
+                voxels_fully_empty = false;
+                BlockVoxel::UNLOADED
+            },
+            _ => {
+                voxels_fully_full = false;
+                BlockVoxel::EMPTY
+            }
+        };
+
+        water_voxels[i] = match visibility {
+            Some(BlockVisibility::Liquid) => {
+                water_voxels_fully_empty = false;
+                TRANSLUCENT
+            },
+            None => {
+                water_voxels_fully_empty = false;
+                FULL
+            },
+            _ => {
+                water_voxels_fully_full = false;
+                EMPTY
+            },
+        };
+
+        translucent_voxels[i] = match visibility {
+            Some(BlockVisibility::Translucent) => {
+                translucent_voxels_fully_empty = false;
+                TRANSLUCENT
+            },
+            None => {
+                translucent_voxels_fully_empty = false;
+                FULL
+            },
+            _ => {
+                translucent_voxels_fully_full = false;
+                EMPTY
+            }
+        };
+    }
+
+    let generate_mesh = |voxels: [VisVoxel; ChunkShape::SIZE as usize], stuipd: bool| -> Mesh {
+        let mut buffer = UnitQuadBuffer::new();
+        if stuipd {
+            naive_mesh_wrapper(
+                &voxels,
+                &ChunkShape {},
+                [0; 3],
+                [CHUNK_SIZE as u32 + 1, CHUNK_SIZE as u32 + 1, CHUNK_SIZE as u32 + 1],
+                &faces,
+                &mut buffer,
+            );
+        }
+        else {
+            visible_block_faces(
+                &voxels,
+                &ChunkShape {},
+                [0; 3],
+                [CHUNK_SIZE as u32 + 1, CHUNK_SIZE as u32 + 1, CHUNK_SIZE as u32 + 1],
+                &faces,
+                &mut buffer,
+            );
+        }
+
+        let num_indices = buffer.num_quads() * 6;
+        let num_vertices = buffer.num_quads() * 4;
+        let mut indices = Vec::with_capacity(num_indices);
+        let mut positions = Vec::with_capacity(num_vertices);
+        let mut normals = Vec::with_capacity(num_vertices);
+        let mut uvs = Vec::with_capacity(num_vertices);
+        let mut colors = Vec::with_capacity(num_vertices);
+        for (group, face) in buffer.groups.into_iter().zip(faces.into_iter()) {
+            for quad in group.into_iter() {
+                // Quads are only ever made for blocks inside the chunk, so these are always loaded.
+                let block = snapshot.get(quad.minimum).unwrap_or_default();
+
+                let mut quad_positions = face.quad_mesh_positions(&quad.into(), 1.0);
+                // Water that isn't full sits lower in its block, along with the top edges of its sides. Water with more water on top is always full.
+                if block.id == BlockID::Water && !snapshot.get([quad.minimum[0], quad.minimum[1] + 1, quad.minimum[2]]).map_or(false, |above| above.id == BlockID::Water) {
+                    let top = quad.minimum[1] as f32 + 1.0;
+                    for position in quad_positions.iter_mut().filter(|position| position[1] == top) {
+                        position[1] = top - 1.0 + block.water_height();
+                    }
+                }
+
+                let ao = vertex_ao(&occluders, &face, quad.minimum);
+                indices.extend_from_slice(&quad_indices(&face, positions.len() as u32, ao));
+                positions.extend_from_slice(&quad_positions);
+                normals.extend_from_slice(&face.quad_mesh_normals());
+
+                let tex_coord = face_tex_coord(block, block_registry, face.signed_normal().to_array());
+
+                let quad_uvs = face.tex_coords(RIGHT_HANDED_Y_UP_CONFIG.u_flip_face, true, &UnorientedQuad::from(quad)).map(|uv| {
+                    let mut u = uv[0] * 8.0;
+                    let mut v = uv[1] * 8.0;
+                    u += tex_coord.x as f32 * 8.0;
+                    v += tex_coord.y as f32 * 8.0;
+                    [u/256.0, v/256.0]
+                });
+
+                uvs.extend_from_slice(&quad_uvs);
+                colors.extend_from_slice(&vertex_colors(snapshot.face_color(quad.minimum, face.signed_normal().to_array()), ao));
+            }
+        }
+
+        // TODO: Should we maybe set this to RENDER_WORLD only instead?
+        let mut render_mesh = Mesh::new(PrimitiveTopology::TriangleList, RenderAssetUsages::MAIN_WORLD | RenderAssetUsages::RENDER_WORLD);
+        render_mesh.insert_attribute(
+            Mesh::ATTRIBUTE_POSITION,
+            VertexAttributeValues::Float32x3(positions),
+        );
+        render_mesh.insert_attribute(
+            Mesh::ATTRIBUTE_NORMAL,
+            VertexAttributeValues::Float32x3(normals),
+        );
+        render_mesh.insert_attribute(
+            Mesh::ATTRIBUTE_UV_0,
+            VertexAttributeValues::Float32x2(uvs),
+        );
+        render_mesh.insert_attribute(
+            Mesh::ATTRIBUTE_COLOR,
+            VertexAttributeValues::Float32x4(colors),
+        );
+        render_mesh.insert_indices(Indices::U32(indices));
+
+        render_mesh.translate_by(Vec3::new(-1.5, -1.5, -1.5));
+        render_mesh
+    };
+
+    // Opaque blocks get merged into as few quads as possible. The UVs count tiles across the quad, and the
+    // tile's corner in the atlas goes in UV_1 so that the TiledAtlas shader can repeat it.
+    let generate_greedy_mesh = |voxels: [BlockVoxel; ChunkShape::SIZE as usize]| -> Mesh {
+        let mut buffer = GreedyQuadsBuffer::new(voxels.len());
+        greedy_quads(
+            &voxels,
+            &ChunkShape {},
+            [0; 3],
+            [CHUNK_SIZE as u32 + 1, CHUNK_SIZE as u32 + 1, CHUNK_SIZE as u32 + 1],
+            &faces,
+            &mut buffer,
+        );
+
+        let num_indices = buffer.quads.num_quads() * 6;
+        let num_vertices = buffer.quads.num_quads() * 4;
+        let mut indices = Vec::with_capacity(num_indices);
+        let mut positions = Vec::with_capacity(num_vertices);
+        let mut normals = Vec::with_capacity(num_vertices);
+        let mut uvs = Vec::with_capacity(num_vertices);
+        let mut tile_uvs = Vec::with_capacity(num_vertices);
+        let mut colors = Vec::with_capacity(num_vertices);
+        for (group, face) in buffer.quads.groups.into_iter().zip(faces.into_iter()) {
+            for quad in group.into_iter() {
+                // Ambient occlusion is part of what quads merge on, so the first block's corners are the quad's corners.
+                let ao = vertex_ao(&occluders, &face, quad.minimum);
+                indices.extend_from_slice(&quad_indices(&face, positions.len() as u32, ao));
+                positions.extend_from_slice(&face.quad_mesh_positions(&quad, 1.0));
+                normals.extend_from_slice(&face.quad_mesh_normals());
+
+                // Every block in a merged quad has the same id and damage, so the first one speaks for all of them.
+                let block = snapshot.get(quad.minimum).unwrap_or_default();
+                let tex_coord = face_tex_coord(block, block_registry, face.signed_normal().to_array());
+
+                uvs.extend_from_slice(&face.tex_coords(RIGHT_HANDED_Y_UP_CONFIG.u_flip_face, true, &quad));
+                tile_uvs.extend_from_slice(&[[tex_coord.x as f32 * 8.0/256.0, tex_coord.y as f32 * 8.0/256.0]; 4]);
+                // Same goes for lighting.
+                colors.extend_from_slice(&vertex_colors(snapshot.face_color(quad.minimum, face.signed_normal().to_array()), ao));
+            }
+        }
+
+        let mut render_mesh = Mesh::new(PrimitiveTopology::TriangleList, RenderAssetUsages::MAIN_WORLD | RenderAssetUsages::RENDER_WORLD);
+        render_mesh.insert_attribute(
+            Mesh::ATTRIBUTE_POSITION,
+            VertexAttributeValues::Float32x3(positions),
+        );
+        render_mesh.insert_attribute(
+            Mesh::ATTRIBUTE_NORMAL,
+            VertexAttributeValues::Float32x3(normals),
+        );
+        render_mesh.insert_attribute(
+            Mesh::ATTRIBUTE_UV_0,
+            VertexAttributeValues::Float32x2(uvs),
+        );
+        render_mesh.insert_attribute(
+            Mesh::ATTRIBUTE_UV_1,
+            VertexAttributeValues::Float32x2(tile_uvs),
+        );
+        render_mesh.insert_attribute(
+            Mesh::ATTRIBUTE_COLOR,
+            VertexAttributeValues::Float32x4(colors),
+        );
+        render_mesh.insert_indices(Indices::U32(indices));
+
+        render_mesh.translate_by(Vec3::new(-1.5, -1.5, -1.5));
+        render_mesh
+    };
+
+    ChunkMeshes {
+        world: (!voxels_fully_empty || !voxels_fully_full).then(|| generate_greedy_mesh(voxels)),
+        water: (!water_voxels_fully_empty || !water_voxels_fully_full).then(|| generate_mesh(water_voxels, false)),
+        translucent: (!translucent_voxels_fully_empty || !translucent_voxels_fully_full).then(|| generate_mesh(translucent_voxels, true)),
+    }
+}
+
+/// Ambient occlusion at each corner of a block's face, in the order of [`OrientedBlockFace::quad_corners`]. 0 is fully boxed in, 3 is fully open.
+/// Looks at the three blocks around each corner in the layer the face is looking into.
+fn vertex_ao (occluders: &[bool], face: &OrientedBlockFace, padded_position: [u32; 3]) -> [u8; 4] {
+    let normal = face.signed_normal().to_array();
+    let front = [0, 1, 2].map(|i| padded_position[i] as i32 + normal[i]);
+    // The two axes the face lies in.
+    let [a, b] = match normal.iter().position(|n| *n != 0) {
+        Some(0) => [1, 2],
+        Some(1) => [0, 2],
+        _ => [0, 1],
+    };
+
+    let occluded = |offset_a: i32, offset_b: i32| -> bool {
+        let mut position = front;
+        position[a] += offset_a;
+        position[b] += offset_b;
+        position.iter().all(|coord| (0..CHUNK_SIZE + 2).contains(coord))
+            && occluders[ChunkShape::linearize(position.map(|coord| coord as u32)) as usize]
+    };
+
+    let quad = UnorientedQuad { minimum: padded_position, width: 1, height: 1 };
+    face.quad_corners(&quad).map(|corner| {
+        let corner = corner.to_array();
+        // Which way the corner is from the middle of the face.
+        let step_a = if corner[a] > padded_position[a] { 1 } else { -1 };
+        let step_b = if corner[b] > padded_position[b] { 1 } else { -1 };
+
+        let side_a = occluded(step_a, 0);
+        let side_b = occluded(0, step_b);
+        let diagonal = occluded(step_a, step_b);
+        if side_a && side_b {
+            0
+        }
+        else {
+            3 - side_a as u8 - side_b as u8 - diagonal as u8
+        }
+    })
+}
+
+/// Triangulates a quad along whichever diagonal keeps ambient occlusion from smearing across the whole quad.
+fn quad_indices (face: &OrientedBlockFace, start: u32, ao: [u8; 4]) -> [u32; 6] {
+    let indices = face.quad_mesh_indices(start);
+    // The default split runs from corner 1 to corner 2. Flip it when corners 0 and 3 are the brighter pair.
+    if ao[0] + ao[3] > ao[1] + ao[2] {
+        let counter_clockwise = indices[1] == start + 1;
+        if counter_clockwise {
+            [start, start + 1, start + 3, start, start + 3, start + 2]
+        }
+        else {
+            [start, start + 3, start + 1, start, start + 2, start + 3]
+        }
+    }
+    else {
+        indices
+    }
+}
+
+/// Darkens a face's color at each corner by its ambient occlusion.
+fn vertex_colors (color: [f32; 4], ao: [u8; 4]) -> [[f32; 4]; 4] {
+    ao.map(|ao| {
+        let brightness = AO_BRIGHTNESS[ao as usize];
+        [color[0] * brightness, color[1] * brightness, color[2] * brightness, color[3]]
+    })
+}
+
+/// Which atlas tile a block shows on the face pointing along `normal`. Damage shifts it to the right, except for liquids, which keep their flow in it instead.
+fn face_tex_coord (block: Block, block_registry: &BlockRegistry, normal: [i32; 3]) -> IVec2 {
+    let attributes = block_registry[block.id];
+
+    let mut tex_coord = match normal {
+        [1, 0, 0] => attributes.tex_coords.east,
+        [-1, 0, 0] => attributes.tex_coords.west,
+        [0, 1, 0] => attributes.tex_coords.top,
+        [0, -1, 0] => attributes.tex_coords.bottom,
+        [0, 0, 1] => attributes.tex_coords.north,
+        _ => attributes.tex_coords.south,
+    };
+
+    if attributes.visibility != BlockVisibility::Liquid {
+        tex_coord.x += block.damage as i32;
+    }
+    tex_coord
+}
+
+/// Puts a finished mesh on a chunk's render entity, spawning the entity if needed. Empty meshes despawn it instead.
+fn apply_chunk_mesh<M: Material> (commands: &mut Commands, meshes: &mut Assets<Mesh>, chunk_pos: IVec3, render_entity: &mut Option<Entity>, mesh: Mesh, material: Handle<M>) {
+    if mesh.count_vertices() != 0 {
+        let render_entity_bundle = (
+            Transform::from_translation((chunk_pos * CHUNK_SIZE).as_vec3()),
+            GlobalTransform::default(),
+            meshes.add(mesh),
+            material,
+            Visibility::default(),
+            InheritedVisibility::default(),
+            ViewVisibility::default(),
+            // TODO: This is a bandaid fix. Bevy isn't frustum culling correctly and we should properly fix it instead of just disabling it. Oh well.
+            NoFrustumCulling,
+        );
+
+        if let Some(render_entity) = render_entity {
+            commands.entity(*render_entity).insert(render_entity_bundle);
+        }
+        else {
+            *render_entity = Some(commands.spawn(render_entity_bundle).id());
+        }
+    }
+    else if let Some(entity) = render_entity.take() {
+        commands.entity(entity).despawn_recursive();
+    }
+}
+
 pub enum RenderEntity {
     World,
     Water,
     Translucent
 }
+impl RenderEntity {
+    pub fn of (&self, chunk: &mut Chunk) -> &mut Option<Entity> {
+        match self {
+            RenderEntity::World => &mut chunk.render_entity,
+            RenderEntity::Water => &mut chunk.water_render_entity,
+            RenderEntity::Translucent => &mut chunk.translucent_render_entity,
+        }
+    }
+}
+
+/// Copy of a chunk plus a one block border from its neighbors, which is everything needed to mesh it.
+pub struct ChunkSnapshot {
+    /// Indexed with [`ChunkShape`]. `None` where the neighboring chunk isn't loaded.
+    pub blocks: Vec<Option<Block>>,
+    /// Indexed with [`ChunkShape`]. Dark where the neighboring chunk isn't loaded.
+    pub light: Vec<Light>,
+}
+impl ChunkSnapshot {
+    pub fn new (chunk_pos: IVec3, chunk_map: &ChunkMap) -> Option<ChunkSnapshot> {
+        let chunk = chunk_map.get(&chunk_pos)?;
+        let offset = chunk_pos * CHUNK_SIZE;
+        let mut blocks = vec![None; ChunkShape::SIZE as usize];
+        let mut light = vec![Light::default(); ChunkShape::SIZE as usize];
+
+        for (x, y, z) in iproduct!(-1..=CHUNK_SIZE, -1..=CHUNK_SIZE, -1..=CHUNK_SIZE) {
+            let local_position = IVec3::new(x, y, z);
+            let i = ChunkShape::linearize([(x + 1) as u32, (y + 1) as u32, (z + 1) as u32]) as usize;
+            if local_position.min_element() >= 0 && local_position.max_element() < CHUNK_SIZE {
+                blocks[i] = Some(chunk.blocks[local_position]);
+                light[i] = chunk.light[local_position];
+            }
+            else {
+                let global_block_position = offset + local_position;
+                if let Some(adj_chunk) = chunk_map.get(&chunk_pos_from_global(global_block_position)) {
+                    blocks[i] = Some(adj_chunk.blocks[block_pos_from_global(global_block_position)]);
+                    light[i] = adj_chunk.light[block_pos_from_global(global_block_position)];
+                }
+            }
+        }
+
+        Some(ChunkSnapshot { blocks, light })
+    }
+
+    /// Gets a block using padded coordinates, where the chunk itself starts at `[1, 1, 1]`.
+    pub fn get (&self, padded_position: [u32; 3]) -> Option<Block> {
+        self.blocks[ChunkShape::linearize(padded_position) as usize]
+    }
+
+    /// Gets the light level using padded coordinates. Anything outside the snapshot is dark.
+    pub fn light_level (&self, padded_position: [i32; 3]) -> u8 {
+        if padded_position.iter().all(|coord| (0..CHUNK_SIZE + 2).contains(coord)) {
+            self.light[ChunkShape::linearize(padded_position.map(|coord| coord as u32)) as usize].level()
+        }
+        else {
+            0
+        }
+    }
+
+    /// Light level in front of each face of the voxel at index `i`.
+    fn face_light_levels (&self, i: usize, face_normals: &[[i32; 3]; 6]) -> [u8; 6] {
+        let [x, y, z] = ChunkShape::delinearize(i as u32);
+        face_normals.map(|normal| self.light_level([x as i32 + normal[0], y as i32 + normal[1], z as i32 + normal[2]]))
+    }
+
+    /// Vertex color for a face of the block at `padded_position`, lit by whatever is in front of it.
+    fn face_color (&self, padded_position: [u32; 3], normal: [i32; 3]) -> [f32; 4] {
+        let level = self.light_level([padded_position[0] as i32 + normal[0], padded_position[1] as i32 + normal[1], padded_position[2] as i32 + normal[2]]);
+        let brightness = light_brightness(level);
+        [brightness, brightness, brightness, 1.0]
+    }
+}
+
+/// Meshes for each of a chunk's render entities. `None` if that entity doesn't need to change.
+pub struct ChunkMeshes {
+    pub world: Option<Mesh>,
+    pub water: Option<Mesh>,
+    pub translucent: Option<Mesh>,
+}
+
+/// The [`TiledAtlasMaterial`] opaque chunk geometry is drawn with. Made from [`Materials::world_res_8x8`] in [`modify_materials`].
+#[derive(Resource)]
+pub struct WorldMaterial(pub Handle<TiledAtlasMaterial>);
+
+/// Chunks that are being meshed on the [`AsyncComputeTaskPool`].
+#[derive(Default, Resource)]
+pub struct ChunkMeshTasks {
+    running: HashMap<IVec3, Task<ChunkMeshes>>,
+    /// Chunks that changed while they were being meshed, and need meshing again once they're done.
+    dirty: HashSet<IVec3>,
+}
 
 #[derive(AssetCollection, Resource)]
 pub struct Atlas{
@@ -463,5 +841,8 @@ pub struct Materials{
     #[asset(standard_material)]
     #[asset(path = "textures_8x8.png")]
     pub translucent_res_8x8: Handle<StandardMaterial>,
+    #[asset(standard_material)]
+    #[asset(path = "items_8x8.png")]
+    pub items_8x8: Handle<StandardMaterial>,
 
 }
\ No newline at end of file
@@ -0,0 +1,210 @@
+use std::{path::Path, time::{Duration, Instant}};
+
+use bevy::{app::AppExit, prelude::*, tasks::{block_on, futures_lite::future, AsyncComputeTaskPool, Task}, window::WindowCloseRequested};
+use rusqlite::{params, Connection};
+
+use crate::{block_entities::BlockEntities, encode_chunk, grid3::Grid3, write_pending_modifications, PendingModificationMap, hotbar::Hotbar, movement::Crouched, Block, ChunkMap, ChunkSavingQueue, ChunkStatus, ChunkStatusMap, CurrentWorld, InGameCamera, Inventory, Player, SaveFault, SavePalette, Stats, world_time::WorldTime, CHUNK_FORMAT_VERSION};
+
+use super::entities::{collect_players, write_entity, PersistentID, SavedEntity};
+
+/// Seconds between autosaves.
+pub const AUTOSAVE_INTERVAL: f32 = 60.0;
+/// How long a save waits on the database if another connection is writing to it.
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+// Resources
+#[derive(Resource)]
+pub struct Autosave {
+    pub timer: Timer,
+    task: Option<Task<(WorldSnapshot, Result<(), SaveFault>)>>,
+}
+impl Default for Autosave {
+    fn default() -> Self {
+        Self { timer: Timer::from_seconds(AUTOSAVE_INTERVAL, TimerMode::Repeating), task: None }
+    }
+}
+impl Autosave {
+    /// Blocks until the autosave in progress (if any) is done, so that it can't overwrite anything written after it.
+    pub fn wait(&mut self) -> Option<(WorldSnapshot, Result<(), SaveFault>)> {
+        self.task.take().map(block_on)
+    }
+}
+
+// Data
+/// Everything a save writes, copied out of the world so it can be written off the main thread.
+#[derive(Default)]
+pub struct WorldSnapshot {
+    /// Unloaded chunks from the [`ChunkSavingQueue`], already encoded.
+    pub queued_chunks: Vec<(IVec3, Vec<u8>)>,
+    /// Loaded chunks with unsaved changes. These get encoded along with the write.
+    pub loaded_chunks: Vec<(IVec3, Grid3<Block>, BlockEntities)>,
+    pub entities: Vec<(PersistentID, SavedEntity)>,
+    /// Everything in the [`PendingModificationMap`] that's in memory, already encoded.
+    pub pending_modifications: Vec<(IVec3, Vec<u8>)>,
+    /// Chunks whose pending modifications have been applied and can be deleted.
+    pub consumed_modifications: Vec<IVec3>,
+    /// [`WorldTime::days`] at the time of the save.
+    pub world_time: f64,
+}
+
+// Systems
+pub fn start_autosave (
+    mut autosave: ResMut<Autosave>,
+    mut chunk_map: ResMut<ChunkMap>,
+    mut pending_map: ResMut<PendingModificationMap>,
+    save_queue: Res<ChunkSavingQueue>,
+    palette: Res<SavePalette>,
+    current_world: Res<CurrentWorld>,
+    world_time: Res<WorldTime>,
+    time: Res<Time>,
+
+    player_query: Query<(&PersistentID, &Transform, &Inventory, &Stats, &Hotbar, &Crouched, &Children), With<Player>>,
+    cam_query: Query<&Transform, (With<InGameCamera>, Without<Player>)>,
+) {
+    autosave.timer.tick(time.delta());
+    // If the last autosave is somehow still running, we'll just catch everything up next time.
+    if !autosave.timer.just_finished() || autosave.task.is_some() {
+        return
+    }
+
+    let mut snapshot = WorldSnapshot {
+        queued_chunks: save_queue.iter().map(|(pos, data)| (*pos, data.clone())).collect(),
+        entities: collect_players(&player_query, &cam_query),
+        pending_modifications: pending_map.encode_all(&palette),
+        consumed_modifications: pending_map.take_consumed(),
+        world_time: world_time.days,
+        ..default()
+    };
+    for (pos, chunk) in chunk_map.iter_mut() {
+        if chunk.unsaved {
+            chunk.unsaved = false;
+            snapshot.loaded_chunks.push((*pos, chunk.blocks.clone(), chunk.block_entities.clone()));
+        }
+    }
+
+    let db_path = current_world.db_path();
+    let palette = palette.clone();
+    autosave.task = Some(AsyncComputeTaskPool::get().spawn(async move {
+        let result = write_snapshot(&db_path, &snapshot, &palette);
+        (snapshot, result)
+    }));
+}
+
+pub fn finish_autosave (
+    mut autosave: ResMut<Autosave>,
+    mut chunk_map: ResMut<ChunkMap>,
+    mut pending_map: ResMut<PendingModificationMap>,
+    mut save_queue: ResMut<ChunkSavingQueue>,
+    mut chunk_status_map: ResMut<ChunkStatusMap>,
+) {
+    let finished = match &mut autosave.task {
+        Some(task) => block_on(future::poll_once(task)),
+        None => None,
+    };
+
+    if let Some((snapshot, result)) = finished {
+        autosave.task = None;
+        match result {
+            Ok(()) => {
+                // A chunk may have been loaded and unloaded again while we were saving, in which case the queue has newer data for it.
+                for (pos, data) in snapshot.queued_chunks {
+                    if save_queue.get(&pos) == Some(&data) {
+                        save_queue.swap_remove(&pos);
+                        if chunk_status_map.get(&pos) == Some(&ChunkStatus::PartialSave) {
+                            chunk_status_map.remove(&pos);
+                        }
+                    }
+                }
+            },
+            Err(err) => {
+                error!("Autosave failed: {}", err);
+                // Unloaded chunks are still in the queue, but loaded ones need to be tried again.
+                for (pos, _, _) in snapshot.loaded_chunks {
+                    if let Some(chunk) = chunk_map.get_mut(&pos) {
+                        chunk.unsaved = true;
+                    }
+                }
+                pending_map.restore_consumed(snapshot.consumed_modifications);
+            },
+        }
+    }
+}
+
+/// Writes everything when the game is closed. [`unload_chunks`](crate::unload_chunks) has already queued up every loaded chunk by the time this runs.
+pub fn save_on_exit (
+    mut autosave: ResMut<Autosave>,
+    mut save_queue: ResMut<ChunkSavingQueue>,
+    mut pending_map: ResMut<PendingModificationMap>,
+    palette: Res<SavePalette>,
+    current_world: Res<CurrentWorld>,
+    world_time: Res<WorldTime>,
+
+    player_query: Query<(&PersistentID, &Transform, &Inventory, &Stats, &Hotbar, &Crouched, &Children), With<Player>>,
+    cam_query: Query<&Transform, (With<InGameCamera>, Without<Player>)>,
+
+    close_requested_evr: EventReader<WindowCloseRequested>,
+    mut exit_evw: EventWriter<AppExit>,
+) {
+    if close_requested_evr.is_empty() {
+        return
+    }
+
+    if let Some((snapshot, Err(err))) = autosave.wait() {
+        error!("Autosave failed: {}", err);
+        pending_map.restore_consumed(snapshot.consumed_modifications);
+    }
+
+    let start = Instant::now();
+    let snapshot = WorldSnapshot {
+        queued_chunks: save_queue.drain(..).collect(),
+        entities: collect_players(&player_query, &cam_query),
+        pending_modifications: pending_map.encode_all(&palette),
+        consumed_modifications: pending_map.take_consumed(),
+        world_time: world_time.days,
+        ..default()
+    };
+
+    match write_snapshot(&current_world.db_path(), &snapshot, &palette) {
+        Ok(()) => debug!("Time elapsed for save: {:?}", start.elapsed()),
+        Err(err) => error!("Failed to save world: {}", err),
+    }
+    exit_evw.send(AppExit);
+}
+
+// Helpers
+/// Writes a snapshot in a single transaction, so a crash partway through leaves the last complete save intact.
+pub fn write_snapshot (db_path: &Path, snapshot: &WorldSnapshot, palette: &SavePalette) -> Result<(), SaveFault> {
+    let mut conn = Connection::open(db_path)?;
+    conn.busy_timeout(BUSY_TIMEOUT)?;
+    conn.pragma_update(None, "synchronous", "NORMAL")?;
+
+    let tx = conn.transaction()?;
+    {
+        let mut stmt = tx.prepare("INSERT INTO Chunks (PosX, PosY, PosZ, ChunkData, Version) VALUES (?1, ?2, ?3, ?4, ?5)
+                                   ON CONFLICT(PosX, PosY, PosZ) DO UPDATE SET ChunkData=excluded.ChunkData, Version=excluded.Version;")?;
+
+        for (pos, data) in snapshot.queued_chunks.iter() {
+            stmt.execute(params![pos.x, pos.y, pos.z, data, CHUNK_FORMAT_VERSION])?;
+        }
+        // Written after the queue, since a loaded chunk is always newer than a queued copy of it.
+        for (pos, blocks, block_entities) in snapshot.loaded_chunks.iter() {
+            let data = encode_chunk(blocks, block_entities, palette)?;
+            stmt.execute(params![pos.x, pos.y, pos.z, data, CHUNK_FORMAT_VERSION])?;
+        }
+    }
+    {
+        let mut stmt = tx.prepare("DELETE FROM PendingModifications WHERE PosX=?1 AND PosY=?2 AND PosZ=?3")?;
+        for pos in snapshot.consumed_modifications.iter() {
+            stmt.execute([pos.x, pos.y, pos.z])?;
+        }
+    }
+    // After the deletes, since modifications for a chunk can show up again after it's been unloaded.
+    write_pending_modifications(&tx, &snapshot.pending_modifications)?;
+    for (id, entity) in snapshot.entities.iter() {
+        write_entity(&tx, *id, entity)?;
+    }
+    tx.execute("UPDATE Metadata SET Time = ?1", [snapshot.world_time])?;
+    tx.commit()?;
+
+    Ok(())
+}
@@ -0,0 +1,128 @@
+use std::f32::consts::PI;
+
+use bevy::{prelude::*, utils::HashMap};
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+
+use crate::{hotbar::Hotbar, movement::Crouched, ChunkPosition, CurrentWorld, InGameCamera, Inventory, Item, MoveToSpawn, Player, Stat, StatType, Stats};
+
+/// Version of the entity data stored per row in the `Entities` table.
+/// 1: RON encoded [`SavedEntity`].
+pub const ENTITY_FORMAT_VERSION: i64 = 1;
+
+// Components
+/// Identifies an entity across saves. Entities without one are not saved.
+#[derive(Component, Clone, Copy, Debug, PartialEq, Eq, Hash, Reflect)]
+#[reflect(Component)]
+pub struct PersistentID(pub i64);
+impl PersistentID {
+    /// There's only ever one player, so it always gets the same id.
+    pub const PLAYER: PersistentID = PersistentID(0);
+}
+
+// Data
+/// Everything about an entity that gets written to the world database.
+#[derive(Clone, Serialize, Deserialize)]
+pub enum SavedEntity {
+    Player(PlayerState),
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct PlayerState {
+    pub translation: [f32; 3],
+    /// Rotation of the player around the Y axis.
+    pub yaw: f32,
+    /// Rotation of the player's camera around the X axis.
+    pub pitch: f32,
+    pub inventory: Vec<Item>,
+    pub stats: Vec<(StatType, Stat)>,
+    pub hotbar: Hotbar,
+    pub crouched: bool,
+}
+
+// Systems
+/// Puts saved players back where they were. Players without a saved state are left to [`MoveToSpawn`].
+pub fn load_players (
+    mut commands: Commands,
+
+    mut query: Query<(Entity, &PersistentID, &mut Transform, &mut Inventory, &mut Stats, &mut Hotbar, &mut Crouched, &mut ChunkPosition, &Children), With<Player>>,
+    mut cam_query: Query<&mut Transform, (With<InGameCamera>, Without<Player>)>,
+
+    current_world: Res<CurrentWorld>,
+) {
+    let conn = Connection::open(current_world.db_path()).unwrap();
+
+    for (entity, id, mut transform, mut inventory, mut stats, mut hotbar, mut crouched, mut chunk_position, children) in &mut query {
+        let state = match read_entity(&conn, *id) {
+            Ok(Some(SavedEntity::Player(state))) => state,
+            Ok(None) => continue,
+            Err(err) => {
+                error!("Failed to load player {}: {}", id.0, err);
+                continue
+            }
+        };
+
+        transform.translation = Vec3::from_array(state.translation);
+        transform.rotation = Quat::from_axis_angle(Vec3::Y, state.yaw);
+        for child in children.iter() {
+            if let Ok(mut cam_transform) = cam_query.get_mut(*child) {
+                cam_transform.rotation = Quat::from_axis_angle(Vec3::X, state.pitch.clamp(-PI/2.0, PI/2.0));
+            }
+        }
+
+        **inventory = state.inventory;
+        **stats = HashMap::from_iter(state.stats);
+        *hotbar = state.hotbar;
+        **crouched = state.crouched;
+
+        // The player may be loaded into the chunk they already think they're in, so chunk loaders need a nudge.
+        chunk_position.set_changed();
+        commands.entity(entity).remove::<MoveToSpawn>();
+    }
+}
+
+// Helpers
+pub fn collect_players (
+    query: &Query<(&PersistentID, &Transform, &Inventory, &Stats, &Hotbar, &Crouched, &Children), With<Player>>,
+    cam_query: &Query<&Transform, (With<InGameCamera>, Without<Player>)>,
+) -> Vec<(PersistentID, SavedEntity)> {
+    query.iter().map(|(id, transform, inventory, stats, hotbar, crouched, children)| {
+        let pitch = children.iter()
+            .find_map(|child| cam_query.get(*child).ok())
+            .map(|cam_transform| cam_transform.rotation.to_euler(EulerRot::XYZ).0)
+            .unwrap_or(0.0);
+
+        let mut stats: Vec<(StatType, Stat)> = stats.iter().map(|(stat_type, stat)| (*stat_type, stat.clone())).collect();
+        stats.sort_unstable_by_key(|(stat_type, _)| *stat_type);
+
+        (*id, SavedEntity::Player(PlayerState {
+            translation: transform.translation.to_array(),
+            yaw: transform.rotation.to_euler(EulerRot::YXZ).0,
+            pitch,
+            inventory: inventory.to_vec(),
+            stats,
+            hotbar: hotbar.clone(),
+            crouched: **crouched,
+        }))
+    }).collect()
+}
+
+pub fn write_entity (conn: &Connection, id: PersistentID, entity: &SavedEntity) -> rusqlite::Result<()> {
+    let data = ron::to_string(entity).map_err(|err| rusqlite::Error::ToSqlConversionFailure(Box::new(err)))?;
+    conn.execute("INSERT INTO Entities (EntityID, Data, Version) VALUES (?1, ?2, ?3)
+                  ON CONFLICT(EntityID) DO UPDATE SET Data=excluded.Data, Version=excluded.Version;", params![id.0, data, ENTITY_FORMAT_VERSION])?;
+    Ok(())
+}
+
+pub fn read_entity (conn: &Connection, id: PersistentID) -> rusqlite::Result<Option<SavedEntity>> {
+    let row: Option<(String, i64)> = conn.query_row("SELECT Data, Version FROM Entities WHERE EntityID = ?1", [id.0], |row| Ok((row.get(0)?, row.get(1)?))).optional()?;
+
+    match row {
+        Some((data, ENTITY_FORMAT_VERSION)) => ron::from_str(&data).map(Some).map_err(|err| rusqlite::Error::FromSqlConversionFailure(0, rusqlite::types::Type::Text, Box::new(err))),
+        Some((_, version)) => {
+            warn!("Entity {} was saved with unknown format version {}, ignoring it.", id.0, version);
+            Ok(None)
+        },
+        None => Ok(None),
+    }
+}
@@ -0,0 +1,460 @@
+use std::{fmt::Display, fs, io::{self, Read, Write}, path::{Path, PathBuf}, sync::{Arc, Mutex}, time::{Duration, SystemTime, UNIX_EPOCH}};
+
+use bevy::{app::AppExit, prelude::*, utils::HashMap, window::WindowCloseRequested};
+use flate2::{bufread::GzDecoder, write::GzEncoder, Compression};
+use rusqlite::{params, Connection};
+
+pub mod autosave;
+pub mod entities;
+use autosave::*;
+use entities::*;
+
+use crate::{block_entities::BlockEntities, blocks::BlockRegistry, BlockData, world_time::{WorldTime, NEW_WORLD_TIME}, grid3::Grid3, Block, BlockID, GameState, PendingModification, PendingModificationMap, RNGSeed, CHUNK_SIZE};
+
+pub const SAVES_DIRECTORY: &str = "saves";
+/// Name of the database inside each world's directory.
+pub const WORLD_FILE: &str = "world.sl3";
+/// Empty world database that new worlds are copied from.
+pub const SAVE_TEMPLATE_PATH: &str = "SaveTemplate.sl3";
+/// Where the single world used to live before worlds got their own directories.
+const LEGACY_SAVE_PATH: &str = "saves/1.sl3";
+/// The seed every world used before seeds were saved.
+const LEGACY_SEED: u32 = 2343;
+
+/// Version of the chunk data written by [`encode_chunk`], stored per row in the `Chunks` table.
+/// 0: Raw `[id, damage]` pairs using the ids of the old `BlockID` enum. Written before worlds had a palette.
+/// 1: `[saved id, damage]` pairs, where saved ids are looked up in the world's `Palette` table.
+/// 2: Version 1, followed by the chunk's block entities as RON.
+/// 3: `[saved id, damage, data tag, data value]` for each block, followed by block entities like version 2.
+pub const CHUNK_FORMAT_VERSION: i64 = 3;
+
+/// Version of the data in the `PendingModifications` table.
+/// 1: `[index (u16 LE), yields to terrain, saved id, damage]` for each cell that isn't empty.
+/// 2: Version 1 with the block's `[data tag, data value]` added to each cell.
+pub const PENDING_FORMAT_VERSION: i64 = 2;
+
+/// Block names in the order of the old `BlockID` enum. Chunks with format version 0 are read through this.
+const LEGACY_PALETTE: [&str; 11] = ["air", "dirt", "grass", "stone", "stone_brick", "log", "leaves", "water", "planks", "crate", "scaffold"];
+
+//Plugin
+pub struct SaveLoadPlugin;
+
+impl Plugin for SaveLoadPlugin {
+    fn build(&self, app: &mut App) {
+        app
+        .init_resource::<SavePalette>()
+        .init_resource::<Autosave>()
+        .add_systems(OnEnter(GameState::Playing), (open_world, load_players).chain())
+        .add_systems(Update, (finish_autosave, start_autosave).chain().run_if(in_state(GameState::Playing)));
+    }
+}
+
+// Systems
+pub fn open_world (
+    mut commands: Commands,
+
+    current_world: Res<CurrentWorld>,
+    block_registry: Res<BlockRegistry>,
+    mut world_time: ResMut<WorldTime>,
+) {
+    let conn = Connection::open(current_world.db_path()).unwrap();
+    if let Err(err) = migrate_world(&conn) {
+        panic!("Failed to migrate world save: {}", err);
+    }
+    match SavePalette::load(&conn, &block_registry) {
+        Ok(palette) => commands.insert_resource(palette),
+        Err(err) => panic!("Failed to load world palette: {}", err),
+    }
+    if let Err(err) = conn.execute("UPDATE Metadata SET LastPlayed = ?1", [unix_time()]) {
+        error!("Failed to update last played time: {}", err);
+    }
+    match conn.query_one("SELECT Time FROM Metadata", [], |row| row.get::<_, Option<f64>>(0)) {
+        Ok(Some(days)) => world_time.days = days,
+        Ok(None) => world_time.days = NEW_WORLD_TIME,
+        Err(err) => error!("Failed to load world time: {}", err),
+    }
+    match PendingModificationMap::load(&conn) {
+        Ok(pending_map) => commands.insert_resource(pending_map),
+        Err(err) => panic!("Failed to load pending modifications: {}", err),
+    }
+    commands.insert_resource(RNGSeed(current_world.metadata.seed));
+
+    // Lets chunks keep loading while an autosave is being written.
+    if let Err(err) = conn.pragma_update(None, "journal_mode", "WAL") {
+        error!("Failed to enable write-ahead logging: {}", err);
+    }
+    conn.busy_timeout(Duration::from_secs(5)).unwrap();
+    conn.pragma_update(None, "synchronous", "NORMAL").unwrap();
+    commands.insert_resource(WorldDatabase(Arc::new(Mutex::new(conn))));
+}
+
+/// Outside of gameplay there's nothing to save, so closing the window can exit right away.
+pub fn exit_on_close_requested (
+    close_requested_evr: EventReader<WindowCloseRequested>,
+    mut exit_evw: EventWriter<AppExit>,
+) {
+    if !close_requested_evr.is_empty() {
+        exit_evw.send(AppExit);
+    }
+}
+
+// Data
+/// Connection to the current world's database that stays open while it's being played. Chunk loading tasks share it to read chunks.
+#[derive(Clone, Resource, Deref)]
+pub struct WorldDatabase(pub Arc<Mutex<Connection>>);
+
+/// The world that is currently being played. Inserted by the world select screen.
+#[derive(Clone, Resource)]
+pub struct CurrentWorld {
+    pub directory: PathBuf,
+    pub metadata: WorldMetadata,
+}
+impl CurrentWorld {
+    pub fn db_path(&self) -> PathBuf {
+        self.directory.join(WORLD_FILE)
+    }
+}
+
+/// The single row of a world's `Metadata` table.
+#[derive(Clone, Debug)]
+pub struct WorldMetadata {
+    pub seed: u32,
+    pub name: String,
+    /// Unix time in seconds.
+    pub created: i64,
+    /// Unix time in seconds.
+    pub last_played: i64,
+    /// Version of the game that created the world.
+    pub game_version: String,
+}
+impl WorldMetadata {
+    pub fn read(conn: &Connection) -> rusqlite::Result<WorldMetadata> {
+        conn.query_one("SELECT Seed, Name, Created, LastPlayed, GameVersion FROM Metadata", [], |row| Ok(WorldMetadata {
+            seed: row.get(0)?,
+            name: row.get(1)?,
+            created: row.get(2)?,
+            last_played: row.get(3)?,
+            game_version: row.get(4)?,
+        }))
+    }
+
+    pub fn write(&self, conn: &Connection) -> rusqlite::Result<()> {
+        conn.execute("DELETE FROM Metadata", [])?;
+        conn.execute("INSERT INTO Metadata (Seed, Name, Created, LastPlayed, GameVersion) VALUES (?1, ?2, ?3, ?4, ?5)",
+                     params![self.seed, self.name, self.created, self.last_played, self.game_version])?;
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub enum SaveFault {
+    Io(io::Error),
+    Sql(rusqlite::Error),
+}
+impl From<io::Error> for SaveFault {
+    fn from(err: io::Error) -> Self {
+        SaveFault::Io(err)
+    }
+}
+impl From<rusqlite::Error> for SaveFault {
+    fn from(err: rusqlite::Error) -> Self {
+        SaveFault::Sql(err)
+    }
+}
+impl Display for SaveFault {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SaveFault::Io(err) => write!(f, "{}", err),
+            SaveFault::Sql(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+// Helpers
+pub fn unix_time () -> i64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|duration| duration.as_secs() as i64).unwrap_or(0)
+}
+
+/// Every world in the saves directory, most recently played first.
+pub fn list_worlds () -> Result<Vec<CurrentWorld>, SaveFault> {
+    fs::create_dir_all(SAVES_DIRECTORY)?;
+    migrate_legacy_save()?;
+
+    let mut worlds = Vec::new();
+    for entry in fs::read_dir(SAVES_DIRECTORY)? {
+        let directory = entry?.path();
+        let db_path = directory.join(WORLD_FILE);
+        if !db_path.is_file() {
+            continue
+        }
+        let metadata = Connection::open(&db_path).and_then(|conn| {
+            migrate_world(&conn)?;
+            WorldMetadata::read(&conn)
+        });
+        match metadata {
+            Ok(metadata) => worlds.push(CurrentWorld { directory, metadata }),
+            Err(err) => warn!("Skipping world at {}: {}", directory.display(), err),
+        }
+    }
+    worlds.sort_by_key(|world| -world.metadata.last_played);
+
+    Ok(worlds)
+}
+
+/// Makes a new world directory from the save template.
+pub fn create_world (name: &str, seed: u32) -> Result<CurrentWorld, SaveFault> {
+    fs::create_dir_all(SAVES_DIRECTORY)?;
+
+    // Keep directory names boring so that any name is safe to use.
+    let mut stem: String = name.chars().map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_lowercase() } else { '_' }).collect();
+    if stem.is_empty() {
+        stem = "world".to_string();
+    }
+    let mut directory = Path::new(SAVES_DIRECTORY).join(&stem);
+    let mut n = 1;
+    while directory.exists() {
+        n += 1;
+        directory = Path::new(SAVES_DIRECTORY).join(format!("{}_{}", stem, n));
+    }
+
+    fs::create_dir(&directory)?;
+    fs::copy(SAVE_TEMPLATE_PATH, directory.join(WORLD_FILE))?;
+
+    let now = unix_time();
+    let metadata = WorldMetadata { seed, name: name.to_string(), created: now, last_played: now, game_version: env!("CARGO_PKG_VERSION").to_string() };
+    let conn = Connection::open(directory.join(WORLD_FILE))?;
+    migrate_world(&conn)?;
+    metadata.write(&conn)?;
+
+    Ok(CurrentWorld { directory, metadata })
+}
+
+pub fn rename_world (world: &mut CurrentWorld, name: &str) -> Result<(), SaveFault> {
+    let conn = Connection::open(world.db_path())?;
+    conn.execute("UPDATE Metadata SET Name = ?1", [name])?;
+    world.metadata.name = name.to_string();
+    Ok(())
+}
+
+pub fn delete_world (world: &CurrentWorld) -> Result<(), SaveFault> {
+    fs::remove_dir_all(&world.directory)?;
+    Ok(())
+}
+
+/// Moves the old single save file into a world directory of its own.
+fn migrate_legacy_save () -> Result<(), SaveFault> {
+    let legacy_path = Path::new(LEGACY_SAVE_PATH);
+    if !legacy_path.is_file() {
+        return Ok(())
+    }
+
+    let mut directory = Path::new(SAVES_DIRECTORY).join("world_1");
+    let mut n = 1;
+    while directory.exists() {
+        n += 1;
+        directory = Path::new(SAVES_DIRECTORY).join(format!("world_1_{}", n));
+    }
+    fs::create_dir(&directory)?;
+    fs::rename(legacy_path, directory.join(WORLD_FILE))?;
+
+    let conn = Connection::open(directory.join(WORLD_FILE))?;
+    migrate_world(&conn)?;
+    if WorldMetadata::read(&conn).is_err() {
+        let now = unix_time();
+        WorldMetadata { seed: LEGACY_SEED, name: "World 1".to_string(), created: now, last_played: now, game_version: env!("CARGO_PKG_VERSION").to_string() }.write(&conn)?;
+    }
+
+    Ok(())
+}
+
+/// Brings an older world save up to the current schema.
+pub fn migrate_world (conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute("CREATE TABLE IF NOT EXISTS Palette (SavedID INTEGER PRIMARY KEY, Name TEXT NOT NULL UNIQUE) STRICT", [])?;
+    conn.execute("CREATE TABLE IF NOT EXISTS PendingModifications (PosX INTEGER NOT NULL, PosY INTEGER NOT NULL, PosZ INTEGER NOT NULL, Data BLOB NOT NULL, Version INTEGER NOT NULL, PRIMARY KEY (PosX, PosY, PosZ)) STRICT", [])?;
+    conn.execute("CREATE TABLE IF NOT EXISTS Entities (EntityID INTEGER PRIMARY KEY, Data TEXT NOT NULL, Version INTEGER NOT NULL) STRICT", [])?;
+    conn.execute("CREATE TABLE IF NOT EXISTS Metadata (Seed INTEGER NOT NULL, Name TEXT NOT NULL, Created INTEGER NOT NULL, LastPlayed INTEGER NOT NULL, GameVersion TEXT NOT NULL) STRICT", [])?;
+
+    let has_version = conn.prepare("SELECT 1 FROM pragma_table_info('Chunks') WHERE name = 'Version'")?.exists([])?;
+    if !has_version {
+        // Every chunk that exists at this point was written before versioning.
+        conn.execute("ALTER TABLE Chunks ADD COLUMN Version INTEGER NOT NULL DEFAULT 0", [])?;
+    }
+
+    let has_time = conn.prepare("SELECT 1 FROM pragma_table_info('Metadata') WHERE name = 'Time'")?.exists([])?;
+    if !has_time {
+        // Stays null until the world is first saved.
+        conn.execute("ALTER TABLE Metadata ADD COLUMN Time REAL", [])?;
+    }
+
+    Ok(())
+}
+
+/// Compresses a chunk's blocks and block entities for the `Chunks` table, using the saved ids from the world's palette.
+pub fn encode_chunk (blocks: &Grid3<Block>, block_entities: &BlockEntities, palette: &SavePalette) -> std::io::Result<Vec<u8>> {
+    let mut e = GzEncoder::new(Vec::new(), Compression::fast());
+    for block in blocks.iter() {
+        e.write_all(&palette.encode_block(*block)?)?;
+    }
+    e.write_all(block_entities.encode()?.as_bytes())?;
+    e.finish()
+}
+
+/// Decompresses chunk data from the `Chunks` table. Blocks the registry no longer knows about come back as [`BlockID::Unknown`] placeholders.
+pub fn decode_chunk (compressed_chunk: &[u8], version: i64, palette: &SavePalette) -> std::io::Result<(Grid3<Block>, BlockEntities)> {
+    let mut blocks = Grid3::filled(Block::new(BlockID::Air), [CHUNK_SIZE, CHUNK_SIZE, CHUNK_SIZE]);
+
+    let bytes_per_block = if version >= 3 { 4 } else { 2 };
+    let mut d = GzDecoder::new(compressed_chunk);
+    let mut chunk_data = vec![0; CHUNK_SIZE.pow(3) as usize * bytes_per_block];
+    d.read_exact(&mut chunk_data)?;
+
+    for (i, data) in chunk_data.chunks(bytes_per_block).enumerate() {
+        let saved_id = match version {
+            0 => palette.legacy_saved_id(data[0]),
+            _ => Some(data[0]),
+        };
+        let block_data = if version >= 3 { BlockData::from_bytes([data[2], data[3]]) } else { BlockData::None };
+        blocks.data[i] = match saved_id {
+            Some(saved_id) => palette.decode_block(saved_id, data[1], block_data),
+            None => Block { id: BlockID::Unknown, damage: data[1], data: block_data },
+        };
+    }
+
+    let block_entities = match version {
+        0 | 1 => BlockEntities::default(),
+        _ => {
+            let mut block_entity_data = String::new();
+            d.read_to_string(&mut block_entity_data)?;
+            BlockEntities::decode(&block_entity_data)?
+        },
+    };
+
+    Ok((blocks, block_entities))
+}
+
+/// Pending modifications are mostly empty, so only the cells that hold something get written.
+pub fn encode_pending_modifications (modifications: &Grid3<PendingModification>, palette: &SavePalette) -> io::Result<Vec<u8>> {
+    let mut data = Vec::new();
+    for (i, modification) in modifications.iter().enumerate() {
+        if modification.is_empty() {
+            continue
+        }
+        data.extend_from_slice(&(i as u16).to_le_bytes());
+        data.push(modification.yield_to_terrain as u8);
+        data.extend_from_slice(&palette.encode_block(modification.block)?);
+    }
+    Ok(data)
+}
+
+pub fn decode_pending_modifications (data: &[u8], version: i64, palette: &SavePalette) -> std::io::Result<Grid3<PendingModification>> {
+    let mut modifications = Grid3::<PendingModification>::new([CHUNK_SIZE; 3]);
+
+    let entry_size = if version >= 2 { 7 } else { 5 };
+    if data.len() % entry_size != 0 {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "pending modification data is truncated"));
+    }
+    for entry in data.chunks(entry_size) {
+        let i = u16::from_le_bytes([entry[0], entry[1]]) as usize;
+        if i >= modifications.data.len() {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "pending modification is outside of its chunk"));
+        }
+        modifications.data[i] = PendingModification {
+            yield_to_terrain: entry[2] != 0,
+            block: palette.decode_block(entry[3], entry[4], if version >= 2 { BlockData::from_bytes([entry[5], entry[6]]) } else { BlockData::None }),
+        };
+    }
+
+    Ok(modifications)
+}
+
+/// Maps the block ids stored in a world save to the ids in the current [`BlockRegistry`] and back.
+/// The palette only ever grows, so a saved id always refers to the same block name for the lifetime of a world.
+#[derive(Default, Clone, Resource)]
+pub struct SavePalette {
+    /// Indexed by saved id.
+    to_current: Vec<BlockID>,
+    to_saved: HashMap<BlockID, u8>,
+    /// Saved ids of the blocks in [`LEGACY_PALETTE`], for chunks from before palettes existed.
+    legacy: Vec<Option<u8>>,
+}
+impl SavePalette {
+    pub fn load (conn: &Connection, block_registry: &BlockRegistry) -> rusqlite::Result<SavePalette> {
+        let mut saved_names = conn.prepare("SELECT SavedID, Name FROM Palette ORDER BY SavedID")?
+            .query_map([], |row| Ok((row.get::<_, u8>(0)?, row.get::<_, String>(1)?)))?
+            .collect::<rusqlite::Result<Vec<(u8, String)>>>()?;
+
+        // Rolled back if anything fails, so the connection isn't left stuck in a transaction.
+        let tx = conn.unchecked_transaction()?;
+        let mut insert = tx.prepare("INSERT INTO Palette (SavedID, Name) VALUES (?1, ?2)")?;
+
+        // Worlds from before palettes existed need to keep reading their ids the way the old enum did.
+        if saved_names.is_empty() && tx.prepare("SELECT 1 FROM Chunks WHERE Version = 0")?.exists([])? {
+            for (saved_id, name) in LEGACY_PALETTE.iter().enumerate() {
+                insert.execute(params![saved_id, name])?;
+                saved_names.push((saved_id as u8, name.to_string()));
+            }
+        }
+
+        // New blocks get the next free saved id, so nothing that's already on disk changes meaning.
+        for (id, _) in block_registry.iter() {
+            let name = block_registry.name(id);
+            if saved_names.iter().any(|(_, saved_name)| saved_name == name) {
+                continue
+            }
+            let next_id = saved_names.iter().map(|(saved_id, _)| *saved_id as usize + 1).max().unwrap_or(0);
+            if next_id > u8::MAX as usize {
+                warn!("World palette is full, {} will be saved as unknown.", name);
+                continue
+            }
+            insert.execute(params![next_id, name])?;
+            saved_names.push((next_id as u8, name.to_string()));
+        }
+        drop(insert);
+        tx.commit()?;
+
+        let mut palette = SavePalette::default();
+        palette.legacy = LEGACY_PALETTE.iter().map(|name| saved_names.iter().find(|(_, saved_name)| saved_name == name).map(|(saved_id, _)| *saved_id)).collect();
+        for (saved_id, name) in saved_names {
+            if palette.to_current.len() <= saved_id as usize {
+                palette.to_current.resize(saved_id as usize + 1, BlockID::Unknown);
+            }
+            // Blocks that were removed from the registry stay in the palette, they just load as placeholders.
+            if let Some(id) = block_registry.id(&name) {
+                palette.to_current[saved_id as usize] = id;
+                palette.to_saved.insert(id, saved_id);
+            }
+        }
+
+        Ok(palette)
+    }
+
+    pub fn current_id (&self, saved_id: u8) -> BlockID {
+        *self.to_current.get(saved_id as usize).unwrap_or(&BlockID::Unknown)
+    }
+
+    /// What an id from the old block enum was saved as in this world's palette, if it's in there at all.
+    pub fn legacy_saved_id (&self, legacy_id: u8) -> Option<u8> {
+        self.legacy.get(legacy_id as usize).copied().flatten()
+    }
+
+    /// Turns a saved block back into a block. Ones the registry doesn't know anymore become [`BlockData::Placeholder`]s that remember their saved id.
+    pub fn decode_block (&self, saved_id: u8, damage: u8, data: BlockData) -> Block {
+        let id = self.current_id(saved_id);
+        if id == BlockID::Unknown && self.to_saved.get(&BlockID::Unknown) != Some(&saved_id) {
+            return Block { id, damage, data: BlockData::Placeholder(saved_id) }
+        }
+        Block { id, damage, data }
+    }
+
+    /// The saved id, damage and data of a block, in the order they're written.
+    /// Placeholders go back out as the id they came in with. Anything else without a saved id is an error, since there's no id it could be written as without turning into some other block.
+    pub fn encode_block (&self, block: Block) -> io::Result<[u8; 4]> {
+        let (saved_id, data) = match block.data {
+            BlockData::Placeholder(saved_id) if block.id == BlockID::Unknown => (Some(saved_id), BlockData::None),
+            data => (self.to_saved.get(&block.id).or_else(|| self.to_saved.get(&BlockID::Unknown)).copied(), data),
+        };
+        let saved_id = saved_id.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, format!("block {:?} has no saved id in the world's palette", block.id)))?;
+        let [tag, value] = data.to_bytes();
+        Ok([saved_id, block.damage, tag, value])
+    }
+}
@@ -1,6 +1,7 @@
 use std::collections::BTreeMap;
 
 use bevy::{prelude::*, utils::HashMap};
+use serde::{Deserialize, Serialize};
 
 use crate::{DeathEvent, EffectCause, Instigator};
 
@@ -16,13 +17,13 @@ impl Plugin for StatsPlugin {
 
 
 
-#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug, Reflect, Hash)]
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug, Reflect, Hash, Serialize, Deserialize)]
 pub enum StatType {
     Health,
     Breath,
 }
 
-#[derive(Clone, Reflect)]
+#[derive(Clone, Reflect, Serialize, Deserialize)]
 pub struct Stat {
     pub base: f32,
     pub effective: f32,
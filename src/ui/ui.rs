@@ -3,7 +3,9 @@ use std::cmp::min;
 use bevy::{a11y::AccessibilityNode, prelude::*};
 use iyes_perf_ui::PerfUiCompleteBundle;
 
-use crate::{hotbar::{Hotbar, SlotAction}, Atlas, BuildingEvent, BuildingTimer, HasAir, Inventory, ItemID, MiningEvent, MiningTimer, Player, StatChangeEvent, StatType, Stats};
+pub mod world_select;
+
+use crate::{block_entities::BlockEntity, block_pos_from_global, blocks::BlockRegistry, chunk_pos_from_global, crafting::{nearby_stations, CraftEvent, CraftingOpen, RecipeRegistry}, hotbar::{Hotbar, SlotAction}, Atlas, BuildingEvent, BuildingTimer, ChunkMap, HasAir, Inventory, Item, ItemID, MiningEvent, MiningTimer, OpenCrate, Player, StatChangeEvent, StatType, Stats};
 
 
 pub fn setup_ui (
@@ -236,6 +238,46 @@ pub fn setup_ui (
                     )
                     .insert(TextureAtlas{ layout: atlas.ui_16x16_layout.clone(), index: 3 as usize});
             });
+
+        commands.spawn(NodeBundle {
+            style: Style {
+                display: Display::None,
+                flex_direction: FlexDirection::Column,
+
+                position_type: PositionType::Absolute,
+                align_items: AlignItems::Center,
+                align_self: AlignSelf::Center,
+                justify_self: JustifySelf::Center,
+                row_gap: Val::Px(8.0),
+                padding: UiRect::all(Val::Px(16.0)),
+
+                ..default()
+            },
+            background_color: BackgroundColor(Color::Rgba { red: 0.1, green: 0.1, blue: 0.1, alpha: 0.75 }),
+
+        ..default()
+        })
+        .insert(CrateRoot);
+
+        commands.spawn(NodeBundle {
+            style: Style {
+                display: Display::None,
+                flex_direction: FlexDirection::Column,
+
+                position_type: PositionType::Absolute,
+                align_items: AlignItems::Start,
+                align_self: AlignSelf::Center,
+                justify_self: JustifySelf::Center,
+                row_gap: Val::Px(8.0),
+                padding: UiRect::all(Val::Px(16.0)),
+
+                ..default()
+            },
+            background_color: BackgroundColor(Color::Rgba { red: 0.1, green: 0.1, blue: 0.1, alpha: 0.75 }),
+
+        ..default()
+        })
+        .insert(CraftingRoot);
 }
 
 pub fn update_breath_ui (
@@ -380,6 +422,7 @@ pub fn update_hotbar (
     hotbar_slot_query: Query<(Entity), With<HotBarSlot>>,
 
     atlas: Res<Atlas>,
+    block_registry: Res<BlockRegistry>,
 ) {
     if let Ok(hotbar) = player_query.get_single() {
         if let Ok(root) = root_query.get_single() {
@@ -438,7 +481,7 @@ pub fn update_hotbar (
                     .insert(TextureAtlas{ layout: atlas.res_8x8_layout.clone(), index: match hotbar.slots[i] {
                         SlotAction::None => {panic!()},
                         SlotAction::Block(block_id) => {
-                            let coords = block_id.get_attributes().tex_coords.top;
+                            let coords = block_registry[block_id].tex_coords.top;
                             // TODO: Hardcoded values are cringe.
                             (coords.y * 32 + coords.x) as usize
                         },
@@ -460,6 +503,253 @@ pub fn update_hotbar (
 }
 
 
+/// Shows what's in the player's open crate next to what they're carrying. Clicking a stack moves it to the other side.
+pub fn update_crate_ui (
+    mut commands: Commands,
+
+    player_query: Query<(Ref<Inventory>, Option<Ref<OpenCrate>>), With<Player>>,
+    mut root_query: Query<(Entity, &mut Style), With<CrateRoot>>,
+    mut removed_open_crates: RemovedComponents<OpenCrate>,
+
+    chunk_map: Res<ChunkMap>,
+    atlas: Res<Atlas>,
+) {
+    let Ok((inventory, opt_open_crate)) = player_query.get_single() else {
+        return
+    };
+    let Ok((root, mut root_style)) = root_query.get_single_mut() else {
+        return
+    };
+
+    let closed = removed_open_crates.read().count() > 0;
+    let opened = opt_open_crate.as_ref().map_or(false, |open_crate| open_crate.is_changed());
+    if !closed && !opened && !inventory.is_changed() {
+        return
+    }
+
+    commands.entity(root).despawn_descendants();
+
+    let Some(open_crate) = opt_open_crate else {
+        root_style.display = Display::None;
+        return
+    };
+    let Some(BlockEntity::Crate(contents)) = chunk_map.get(&chunk_pos_from_global(**open_crate)).and_then(|chunk| chunk.block_entities.get(&block_pos_from_global(**open_crate))) else {
+        root_style.display = Display::None;
+        return
+    };
+    root_style.display = Display::Flex;
+
+    for (title, items, in_crate) in [("Crate", contents, true), ("Inventory", &*inventory, false)] {
+        let title_entity = commands.spawn(TextBundle::from_section(title, TextStyle { font_size: 50.0, color: Color::WHITE, ..default()}))
+            .id();
+
+        let row_entity = commands.spawn(NodeBundle {
+            style: Style {
+                flex_direction: FlexDirection::Row,
+                min_height: Val::Px(64.),
+                column_gap: Val::Px(8.0),
+
+                ..default()
+            },
+
+            ..default()
+        })
+        .id();
+
+        for item in items.iter() {
+            let number_entity = commands.spawn(TextBundle::from_section(item.amount.to_string(), TextStyle { font_size: 50.0, color: Color::WHITE, ..default()}))
+                .id();
+
+            let tex_coords = item.get_tex_coords();
+            let index = tex_coords.x + tex_coords.y * 32;
+
+            let image_entity = commands.spawn(ImageBundle {
+                style: Style {
+                    width: Val::Px(64.),
+                    height: Val::Px(64.),
+                    ..default()
+                },
+                image: UiImage::new(atlas.items_8x8.clone()),
+                ..default()
+                },
+                )
+                .insert(TextureAtlas{ layout: atlas.items_8x8_layout.clone(), index: index as usize})
+                .id();
+
+            let slot_entity = commands.spawn(ButtonBundle {
+                style: Style {
+                    flex_direction: FlexDirection::Row,
+                    align_items: AlignItems::Center,
+
+                    ..default()
+                },
+                background_color: BackgroundColor(Color::NONE),
+
+                ..default()
+            })
+            .insert(CrateSlot { item: *item, in_crate })
+            .add_child(number_entity)
+            .add_child(image_entity)
+            .id();
+
+            commands.entity(row_entity).add_child(slot_entity);
+        }
+
+        commands.entity(root).add_child(title_entity).add_child(row_entity);
+    }
+}
+
+pub fn crate_slot_buttons (
+    slot_query: Query<(&Interaction, &CrateSlot), Changed<Interaction>>,
+    mut player_query: Query<(&mut Inventory, &OpenCrate), With<Player>>,
+
+    mut chunk_map: ResMut<ChunkMap>,
+) {
+    let Ok((mut inventory, open_crate)) = player_query.get_single_mut() else {
+        return
+    };
+    let Some(chunk) = chunk_map.get_mut(&chunk_pos_from_global(**open_crate)) else {
+        return
+    };
+    let Some(BlockEntity::Crate(contents)) = chunk.block_entities.get_mut(&block_pos_from_global(**open_crate)) else {
+        return
+    };
+
+    let mut moved = false;
+    for (interaction, slot) in &slot_query {
+        if *interaction != Interaction::Pressed {
+            continue
+        }
+
+        let (from, to) = if slot.in_crate { (&mut *contents, &mut *inventory) } else { (&mut *inventory, &mut *contents) };
+        if let Ok(item) = from.take_item(slot.item) {
+            // Whole stacks or nothing. If it doesn't fit, it goes back where it came from.
+            if to.insert_item(item).is_err() {
+                let _ = from.insert_item(item);
+            }
+        }
+        moved = true;
+    }
+
+    if moved {
+        chunk.mark_modified();
+    }
+}
+
+/// Lists every recipe the player could craft right now. Clicking one crafts it.
+pub fn update_crafting_ui (
+    mut commands: Commands,
+
+    player_query: Query<(Ref<Inventory>, &Transform, Has<CraftingOpen>), With<Player>>,
+    mut root_query: Query<(Entity, &mut Style), With<CraftingRoot>>,
+
+    recipe_registry: Res<RecipeRegistry>,
+    block_registry: Res<BlockRegistry>,
+    chunk_map: Res<ChunkMap>,
+    atlas: Res<Atlas>,
+
+    mut shown: Local<Option<Vec<usize>>>,
+) {
+    let Ok((inventory, transform, crafting_open)) = player_query.get_single() else {
+        return
+    };
+    let Ok((root, mut root_style)) = root_query.get_single_mut() else {
+        return
+    };
+
+    if !crafting_open {
+        if shown.take().is_some() {
+            commands.entity(root).despawn_descendants();
+            root_style.display = Display::None;
+        }
+        return
+    }
+
+    // Walking up to a station can make more recipes craftable, so this has to be checked every frame rather than only when the inventory changes.
+    let stations = nearby_stations(transform.translation, &chunk_map);
+    let craftable = recipe_registry.craftable(&inventory, &stations, &block_registry).collect::<Vec<_>>();
+    if shown.as_ref() == Some(&craftable) && !inventory.is_changed() {
+        return
+    }
+
+    commands.entity(root).despawn_descendants();
+    root_style.display = Display::Flex;
+
+    let title_entity = commands.spawn(TextBundle::from_section("Crafting", TextStyle { font_size: 50.0, color: Color::WHITE, ..default()}))
+        .id();
+    commands.entity(root).add_child(title_entity);
+
+    if craftable.is_empty() {
+        let empty_entity = commands.spawn(TextBundle::from_section("Nothing to craft", TextStyle { font_size: 30.0, color: Color::GRAY, ..default()}))
+            .id();
+        commands.entity(root).add_child(empty_entity);
+    }
+
+    for index in craftable.iter() {
+        let recipe = &recipe_registry[*index];
+
+        let button_entity = commands.spawn(ButtonBundle {
+            style: Style {
+                flex_direction: FlexDirection::Row,
+                align_items: AlignItems::Center,
+                column_gap: Val::Px(8.0),
+
+                ..default()
+            },
+            background_color: BackgroundColor(Color::NONE),
+
+            ..default()
+        })
+        .insert(RecipeButton(*index))
+        .id();
+
+        for output in recipe.outputs.iter() {
+            let tex_coords = output.get_tex_coords();
+            let index = tex_coords.x + tex_coords.y * 32;
+
+            let image_entity = commands.spawn(ImageBundle {
+                style: Style {
+                    width: Val::Px(64.),
+                    height: Val::Px(64.),
+                    ..default()
+                },
+                image: UiImage::new(atlas.items_8x8.clone()),
+                ..default()
+                },
+                )
+                .insert(TextureAtlas{ layout: atlas.items_8x8_layout.clone(), index: index as usize})
+                .id();
+            commands.entity(button_entity).add_child(image_entity);
+        }
+
+        let inputs = recipe.inputs.iter().map(|input| format!("{} {}", input.amount, input.id)).collect::<Vec<_>>().join(", ");
+        let text_entity = commands.spawn(TextBundle::from_section(format!("{} ({})", recipe.name, inputs), TextStyle { font_size: 30.0, color: Color::WHITE, ..default()}))
+            .id();
+        commands.entity(button_entity).add_child(text_entity);
+
+        commands.entity(root).add_child(button_entity);
+    }
+
+    *shown = Some(craftable);
+}
+
+pub fn recipe_buttons (
+    button_query: Query<(&Interaction, &RecipeButton), Changed<Interaction>>,
+    player_query: Query<Entity, (With<Player>, With<CraftingOpen>)>,
+
+    mut evw_craft: EventWriter<CraftEvent>,
+) {
+    let Ok(player) = player_query.get_single() else {
+        return
+    };
+
+    for (interaction, button) in &button_query {
+        if *interaction == Interaction::Pressed {
+            evw_craft.send(CraftEvent { entity: player, recipe: **button });
+        }
+    }
+}
+
 const PROGRESS_BAR_SMOOTHNESS: f32 = 12.0;
 
 pub fn update_progress_bar (
@@ -538,4 +828,23 @@ pub struct BreathRoot;
 
 #[derive(Component, Clone, Debug, Reflect)]
 #[reflect(Component)]
-pub struct BreathValue;
\ No newline at end of file
+pub struct BreathValue;
+
+#[derive(Component, Clone, Debug, Reflect)]
+#[reflect(Component)]
+pub struct CrateRoot;
+
+#[derive(Component, Clone, Debug, Reflect)]
+#[reflect(Component)]
+pub struct CraftingRoot;
+
+/// Crafts the recipe with this index in the [`RecipeRegistry`] when clicked.
+#[derive(Component, Clone, Copy, Debug, Deref)]
+pub struct RecipeButton(pub usize);
+
+/// A stack of items in the crate menu, and which side of it the stack is on.
+#[derive(Component, Clone, Debug)]
+pub struct CrateSlot {
+    pub item: Item,
+    pub in_crate: bool,
+}
@@ -0,0 +1,300 @@
+use bevy::{prelude::*, window::ReceivedCharacter};
+
+use crate::{create_world, delete_world, list_worlds, rename_world, unix_time, CurrentWorld, GameState};
+
+const MAX_NAME_LENGTH: usize = 32;
+
+const BUTTON_COLOR: Color = Color::Rgba { red: 0.15, green: 0.15, blue: 0.15, alpha: 0.9 };
+const BUTTON_HOVERED_COLOR: Color = Color::Rgba { red: 0.3, green: 0.3, blue: 0.3, alpha: 0.9 };
+const BUTTON_SELECTED_COLOR: Color = Color::Rgba { red: 0.45, green: 0.38, blue: 0.1, alpha: 0.9 };
+
+// Resources
+#[derive(Default, Resource)]
+pub struct WorldSelection {
+    pub worlds: Vec<CurrentWorld>,
+    pub selected: Option<usize>,
+    /// Used as the name for new and renamed worlds.
+    pub name_input: String,
+    /// Deleting takes two presses.
+    pub delete_armed: bool,
+}
+impl WorldSelection {
+    fn refresh(&mut self) {
+        self.worlds = match list_worlds() {
+            Ok(worlds) => worlds,
+            Err(err) => {
+                error!("Failed to list worlds: {}", err);
+                vec![]
+            },
+        };
+        self.selected = None;
+        self.delete_armed = false;
+    }
+}
+
+// Components
+#[derive(Component, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WorldSelectButton {
+    World(usize),
+    New,
+    Play,
+    Rename,
+    Delete,
+}
+
+#[derive(Component, Clone, Debug)]
+pub struct WorldSelectRoot;
+
+#[derive(Component, Clone, Debug)]
+pub struct WorldListRoot;
+
+#[derive(Component, Clone, Debug)]
+pub struct WorldNameText;
+
+#[derive(Component, Clone, Debug)]
+pub struct DeleteButtonText;
+
+// Systems
+pub fn setup_world_select (
+    mut commands: Commands,
+) {
+    let mut selection = WorldSelection::default();
+    selection.refresh();
+    if !selection.worlds.is_empty() {
+        selection.selected = Some(0);
+        selection.name_input = selection.worlds[0].metadata.name.clone();
+    }
+    commands.insert_resource(selection);
+
+    let text_style = TextStyle { font_size: 40.0, color: Color::WHITE, ..default() };
+
+    commands.spawn(NodeBundle {
+        style: Style {
+            width: Val::Percent(100.0),
+            height: Val::Percent(100.0),
+            flex_direction: FlexDirection::Column,
+            align_items: AlignItems::Center,
+            justify_content: JustifyContent::Center,
+            row_gap: Val::Px(12.0),
+
+            ..default()
+        },
+
+        ..default()
+    })
+    .insert(WorldSelectRoot)
+    .with_children(|parent| {
+        parent.spawn(TextBundle::from_section("Select a World", TextStyle { font_size: 60.0, color: Color::WHITE, ..default() }));
+
+        parent.spawn(NodeBundle {
+            style: Style {
+                flex_direction: FlexDirection::Column,
+                row_gap: Val::Px(4.0),
+                min_width: Val::Px(600.0),
+                ..default()
+            },
+            ..default()
+        })
+        .insert(WorldListRoot);
+
+        parent.spawn(TextBundle::from_section("", text_style.clone()))
+            .insert(WorldNameText);
+
+        parent.spawn(NodeBundle {
+            style: Style {
+                flex_direction: FlexDirection::Row,
+                column_gap: Val::Px(8.0),
+                ..default()
+            },
+            ..default()
+        })
+        .with_children(|parent| {
+            for (button, label) in [(WorldSelectButton::New, "New"), (WorldSelectButton::Play, "Play"), (WorldSelectButton::Rename, "Rename"), (WorldSelectButton::Delete, "Delete")] {
+                parent.spawn(ButtonBundle {
+                    style: Style {
+                        padding: UiRect::all(Val::Px(8.0)),
+                        ..default()
+                    },
+                    background_color: BackgroundColor(BUTTON_COLOR),
+                    ..default()
+                })
+                .insert(button)
+                .with_children(|parent| {
+                    let mut text = parent.spawn(TextBundle::from_section(label, text_style.clone()));
+                    if button == WorldSelectButton::Delete {
+                        text.insert(DeleteButtonText);
+                    }
+                });
+            }
+        });
+    });
+}
+
+pub fn cleanup_world_select (
+    mut commands: Commands,
+
+    root_query: Query<Entity, With<WorldSelectRoot>>,
+) {
+    for entity in &root_query {
+        commands.entity(entity).despawn_recursive();
+    }
+    commands.remove_resource::<WorldSelection>();
+}
+
+/// Typing edits the name used for creating and renaming worlds.
+pub fn world_select_name_input (
+    mut selection: ResMut<WorldSelection>,
+
+    mut evr_char: EventReader<ReceivedCharacter>,
+    keys: Res<ButtonInput<KeyCode>>,
+) {
+    for ev in evr_char.read() {
+        for c in ev.char.chars() {
+            if !c.is_control() && selection.name_input.chars().count() < MAX_NAME_LENGTH {
+                selection.name_input.push(c);
+            }
+        }
+    }
+
+    if keys.just_pressed(KeyCode::Backspace) {
+        selection.name_input.pop();
+    }
+}
+
+pub fn world_select_buttons (
+    mut commands: Commands,
+
+    mut selection: ResMut<WorldSelection>,
+    mut next_state: ResMut<NextState<GameState>>,
+
+    interaction_query: Query<(&Interaction, &WorldSelectButton), Changed<Interaction>>,
+) {
+    for (interaction, button) in &interaction_query {
+        if *interaction != Interaction::Pressed {
+            continue
+        }
+
+        if *button != WorldSelectButton::Delete {
+            selection.delete_armed = false;
+        }
+
+        match *button {
+            WorldSelectButton::World(i) => {
+                selection.selected = Some(i);
+                selection.name_input = selection.worlds[i].metadata.name.clone();
+            },
+            WorldSelectButton::New => {
+                let name = if selection.name_input.trim().is_empty() { "New World".to_string() } else { selection.name_input.trim().to_string() };
+                match create_world(&name, fastrand::u32(..)) {
+                    Ok(world) => {
+                        selection.refresh();
+                        selection.selected = selection.worlds.iter().position(|w| w.directory == world.directory);
+                    },
+                    Err(err) => error!("Failed to create world: {}", err),
+                }
+            },
+            WorldSelectButton::Play => {
+                if let Some(i) = selection.selected {
+                    commands.insert_resource(selection.worlds[i].clone());
+                    next_state.set(GameState::Playing);
+                }
+            },
+            WorldSelectButton::Rename => {
+                let name = selection.name_input.trim().to_string();
+                if let Some(i) = selection.selected {
+                    if !name.is_empty() {
+                        if let Err(err) = rename_world(&mut selection.worlds[i], &name) {
+                            error!("Failed to rename world: {}", err);
+                        }
+                    }
+                }
+            },
+            WorldSelectButton::Delete => {
+                if let Some(i) = selection.selected {
+                    if !selection.delete_armed {
+                        selection.delete_armed = true;
+                        continue
+                    }
+                    if let Err(err) = delete_world(&selection.worlds[i]) {
+                        error!("Failed to delete world: {}", err);
+                    }
+                    selection.refresh();
+                }
+            },
+        }
+    }
+}
+
+/// Rebuilds the world list whenever the selection changes, the same way the hotbar gets rebuilt.
+pub fn update_world_select (
+    mut commands: Commands,
+
+    selection: Res<WorldSelection>,
+
+    list_query: Query<(Entity, Option<&Children>), With<WorldListRoot>>,
+    mut name_text_query: Query<&mut Text, (With<WorldNameText>, Without<DeleteButtonText>)>,
+    mut delete_text_query: Query<&mut Text, (With<DeleteButtonText>, Without<WorldNameText>)>,
+    mut button_query: Query<(&Interaction, &WorldSelectButton, &mut BackgroundColor)>,
+) {
+    for (interaction, button, mut background_color) in &mut button_query {
+        *background_color = if *button == WorldSelectButton::World(selection.selected.unwrap_or(usize::MAX)) {
+            BackgroundColor(BUTTON_SELECTED_COLOR)
+        }
+        else if *interaction == Interaction::Hovered {
+            BackgroundColor(BUTTON_HOVERED_COLOR)
+        }
+        else {
+            BackgroundColor(BUTTON_COLOR)
+        };
+    }
+
+    if !selection.is_changed() {
+        return
+    }
+
+    if let Ok(mut text) = name_text_query.get_single_mut() {
+        *text = Text::from_section(format!("Name: {}_", selection.name_input), TextStyle { font_size: 40.0, color: Color::WHITE, ..default() });
+    }
+    if let Ok(mut text) = delete_text_query.get_single_mut() {
+        let label = if selection.delete_armed { "Really delete?" } else { "Delete" };
+        *text = Text::from_section(label, TextStyle { font_size: 40.0, color: Color::WHITE, ..default() });
+    }
+
+    if let Ok((root, opt_children)) = list_query.get_single() {
+        if let Some(children) = opt_children {
+            for child in children.iter() {
+                commands.entity(*child).despawn_recursive();
+            }
+        }
+
+        let now = unix_time();
+        for (i, world) in selection.worlds.iter().enumerate() {
+            let label = format!("{} - {}", world.metadata.name, time_since(now - world.metadata.last_played));
+            let button = commands.spawn(ButtonBundle {
+                style: Style {
+                    padding: UiRect::all(Val::Px(6.0)),
+                    ..default()
+                },
+                background_color: BackgroundColor(BUTTON_COLOR),
+                ..default()
+            })
+            .insert(WorldSelectButton::World(i))
+            .with_children(|parent| {
+                parent.spawn(TextBundle::from_section(label, TextStyle { font_size: 32.0, color: Color::WHITE, ..default() }));
+            })
+            .id();
+
+            commands.entity(root).add_child(button);
+        }
+    }
+}
+
+// Helpers
+fn time_since (seconds: i64) -> String {
+    match seconds {
+        s if s < 60 => "just now".to_string(),
+        s if s < 60 * 60 => format!("{} minutes ago", s / 60),
+        s if s < 60 * 60 * 24 => format!("{} hours ago", s / (60 * 60)),
+        s => format!("{} days ago", s / (60 * 60 * 24)),
+    }
+}